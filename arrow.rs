@@ -0,0 +1,432 @@
+//! Parquet files of records
+//!
+//! Buffers records into Apache Arrow `RecordBatch`es and writes each batch as
+//! a row group of a Parquet file, with the `ArrowWriter` of the `parquet`
+//! crate. Built with `Async::to_arrow` the buffering and the writing run on
+//! the worker thread, batched as with `Async::new_batch`.
+//!
+//! # Schema
+//!
+//! Every batch has the following columns, in that order, see `schema`:
+//!
+//! * `level` - `Utf8`, the level name, eg. `WARNING`
+//! * `ts` - `Timestamp(Microsecond, "UTC")`, when the worker thread laid the
+//!   batch out, the same for all its records
+//! * `msg` - `Utf8`, the message
+//! * `tag` - `Utf8`, the tag, empty if there's no tag
+//! * one nullable `Utf8` column per configured key, named after it: the
+//!   formatted value of the key, record key-values taking precedence over
+//!   logger ones, null if the record has no such key
+//!
+//! None of the columns but the key ones are nullable. Key-values not
+//! configured as a column are left out.
+//!
+//! # Flushing
+//!
+//! A row group is written once `max_rows` records are buffered or the oldest
+//! one waited for `max_delay`, whichever comes first, as well as on
+//! `Async::flush` and when the drain finishes. The Parquet footer is written
+//! when the drain finishes: until then, the file can't be read.
+
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_array::{StringArray, TimestampMicrosecondArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
+use slog::{Key, Serializer, KV};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use {diagnostic, AsyncRecord, TryBatchDrain};
+
+/// Schema of the batches with a column for each of `keys`, see the module
+/// documentation.
+pub fn schema<K: AsRef<str>>(keys: &[K]) -> Schema {
+    let mut fields = vec![
+        Field::new("level", DataType::Utf8, false),
+        Field::new(
+            "ts",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("msg", DataType::Utf8, false),
+        Field::new("tag", DataType::Utf8, false),
+    ];
+    fields.extend(
+        keys.iter()
+            .map(|key| Field::new(key.as_ref(), DataType::Utf8, true)),
+    );
+    Schema::new(fields)
+}
+
+/// `TryBatchDrain` writing records to a Parquet file, a row group per batch
+///
+/// See `Async::to_arrow`.
+pub struct ArrowBatchDrain<W: Write + Send> {
+    writer: Mutex<ArrowWriter<W>>,
+    schema: SchemaRef,
+    keys: Vec<String>,
+}
+
+impl<W: Write + Send + 'static> ArrowBatchDrain<W> {
+    /// Write a Parquet file to `writer`, with a column for each of `keys`.
+    pub fn new<K: Into<String>, I: IntoIterator<Item = K>>(
+        writer: W,
+        keys: I,
+    ) -> Result<Self, ParquetError> {
+        let keys: Vec<String> = keys.into_iter().map(Into::into).collect();
+        let schema = Arc::new(schema(&keys));
+        let writer = ArrowWriter::try_new(writer, schema.clone(), None)?;
+        Ok(ArrowBatchDrain {
+            writer: Mutex::new(writer),
+            schema,
+            keys,
+        })
+    }
+
+    /// Lay `records` out in columns.
+    fn record_batch(
+        &self,
+        records: &[AsyncRecord],
+    ) -> Result<RecordBatch, ParquetError> {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| i64::try_from(since.as_micros()).unwrap_or(i64::MAX))
+            .unwrap_or(0);
+        let mut kv: Vec<Vec<Option<String>>> =
+            vec![Vec::with_capacity(records.len()); self.keys.len()];
+        for record in records {
+            let mut values = vec![None; self.keys.len()];
+            record.as_record_values(|record, logger_values| {
+                let mut ser = ColumnSerializer {
+                    keys: &self.keys,
+                    values: &mut values,
+                };
+                // `ColumnSerializer` doesn't fail. Record key-values come
+                // last, overwriting logger ones.
+                let _ = logger_values.serialize(record, &mut ser);
+                let _ = record.kv().serialize(record, &mut ser);
+            });
+            for (column, value) in kv.iter_mut().zip(values) {
+                column.push(value);
+            }
+        }
+
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(
+                records.iter().map(|r| r.level().as_str()),
+            )),
+            Arc::new(
+                TimestampMicrosecondArray::from(vec![ts; records.len()])
+                    .with_timezone("UTC"),
+            ),
+            Arc::new(StringArray::from_iter_values(
+                records.iter().map(|r| r.msg().to_string()),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                records.iter().map(AsyncRecord::tag),
+            )),
+        ];
+        columns.extend(
+            kv.into_iter()
+                .map(|column| Arc::new(StringArray::from(column)) as ArrayRef),
+        );
+        Ok(RecordBatch::try_new(self.schema.clone(), columns)?)
+    }
+}
+
+impl<W: Write + Send + 'static> TryBatchDrain for ArrowBatchDrain<W> {
+    type Err = ParquetError;
+
+    fn try_log_batch(
+        &self,
+        records: &[AsyncRecord],
+    ) -> Result<(), ParquetError> {
+        let batch = self.record_batch(records)?;
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(e) => e.into_inner(),
+        };
+        writer.write(&batch)?;
+        // Ends the row group
+        writer.flush()
+    }
+}
+
+impl<W: Write + Send> Drop for ArrowBatchDrain<W> {
+    fn drop(&mut self) {
+        let writer = match self.writer.get_mut() {
+            Ok(writer) => writer,
+            Err(e) => e.into_inner(),
+        };
+        if let Err(e) = writer.finish() {
+            diagnostic(format_args!("writing the Parquet footer: {}", e));
+        }
+    }
+}
+
+/// Default number of records written at once with `Async::to_arrow`
+pub const DEFAULT_MAX_ROWS: usize = 8192;
+
+/// Default delay before writing a batch with `Async::to_arrow`
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// Collects the values of the configured keys.
+struct ColumnSerializer<'a> {
+    keys: &'a [String],
+    values: &'a mut [Option<String>],
+}
+
+impl<'a> Serializer for ColumnSerializer<'a> {
+    fn emit_arguments(
+        &mut self,
+        key: Key,
+        val: &fmt::Arguments,
+    ) -> slog::Result {
+        let key: &str = key.as_ref();
+        if let Some(i) = self.keys.iter().position(|k| k == key) {
+            self.values[i] = Some(val.to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use slog::{Drain, Level};
+    use std::fs::{self, File};
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use {Async, FuseBatch};
+
+    /// Values of the string column `name` across `batches`.
+    fn strings(batches: &[RecordBatch], name: &str) -> Vec<Option<String>> {
+        let mut values = vec![];
+        for batch in batches {
+            let column = batch
+                .column_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            values.extend(column.iter().map(|v| v.map(String::from)));
+        }
+        values
+    }
+
+    /// Path of a Parquet file for the test `name`
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "slog-async-arrow-{}-{}.parquet",
+            name,
+            process::id()
+        ))
+    }
+
+    /// Number of rows of each row group of the Parquet file at `path`.
+    fn row_group_sizes(path: &Path) -> Vec<i64> {
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+                .unwrap();
+        let metadata = reader.metadata();
+        (0..metadata.num_row_groups())
+            .map(|i| metadata.row_group(i).num_rows())
+            .collect()
+    }
+
+    #[test]
+    fn records_round_trip_through_parquet() {
+        let path = temp_path("round-trip");
+        let file = File::create(&path).unwrap();
+        let drain = ArrowBatchDrain::new(file, vec!["user", "status"]).unwrap();
+        let (drain, control) =
+            Async::new_batch(FuseBatch(drain), 2, Duration::from_secs(3600))
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let log = slog::Logger::root(drain.fuse(), o!("user" => "root"));
+        warn!(log, #"http", "slow request";
+              "status" => 504, "path" => "/api");
+        info!(log, "login"; "user" => "alice");
+        info!(log, "third");
+        // Writes the last row group and the footer
+        drop(log);
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(
+            File::open(&path).unwrap(),
+        )
+        .unwrap();
+        let fields: Vec<Field> = reader
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| (**field).clone())
+            .collect();
+        let expected: Vec<Field> = schema(&["user", "status"])
+            .fields()
+            .iter()
+            .map(|field| (**field).clone())
+            .collect();
+        assert_eq!(fields, expected);
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        assert_eq!(reader.metadata().row_group(0).num_rows(), 2);
+        let batches = reader
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let some = |values: &[&str]| -> Vec<Option<String>> {
+            values.iter().map(|v| Some(v.to_string())).collect()
+        };
+        assert_eq!(
+            strings(&batches, "level"),
+            some(&["WARNING", "INFO", "INFO"])
+        );
+        assert_eq!(
+            strings(&batches, "msg"),
+            some(&["slow request", "login", "third"])
+        );
+        assert_eq!(strings(&batches, "tag"), some(&["http", "", ""]));
+        assert_eq!(strings(&batches, "user"), some(&["root", "alice", "root"]));
+        assert_eq!(
+            strings(&batches, "status"),
+            vec![Some("504".to_string()), None, None]
+        );
+        assert!(batches[0].column_by_name("path").is_none());
+        let ts = batches[0]
+            .column_by_name("ts")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<TimestampMicrosecondArray>()
+            .unwrap();
+        assert!(ts.value(0) > 0 && ts.value(0) == ts.value(1));
+    }
+    #[test]
+    fn schema_has_typed_columns() {
+        let fields: Vec<_> = schema(&["user", "status"])
+            .fields()
+            .iter()
+            .map(|field| {
+                (
+                    field.name().clone(),
+                    field.data_type().clone(),
+                    field.is_nullable(),
+                )
+            })
+            .collect();
+        let ts = DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()));
+        assert_eq!(
+            fields,
+            vec![
+                ("level".to_string(), DataType::Utf8, false),
+                ("ts".to_string(), ts, false),
+                ("msg".to_string(), DataType::Utf8, false),
+                ("tag".to_string(), DataType::Utf8, false),
+                ("user".to_string(), DataType::Utf8, true),
+                ("status".to_string(), DataType::Utf8, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn key_values_are_formatted_into_string_columns() {
+        let drain = ArrowBatchDrain::new(
+            Vec::new(),
+            vec!["status", "ok", "ratio", "missing"],
+        )
+        .unwrap();
+        let records = [AsyncRecord::from(
+            &record!(
+                Level::Info,
+                "",
+                &format_args!("msg"),
+                b!("status" => 504u16, "ok" => false, "ratio" => 0.5)
+            ),
+            &o!().into(),
+        )];
+        let batch = drain.record_batch(&records).unwrap();
+
+        assert_eq!(batch.schema(), drain.schema);
+        let types: Vec<_> = batch
+            .columns()
+            .iter()
+            .map(|column| column.data_type().clone())
+            .collect();
+        let expected: Vec<_> = drain
+            .schema
+            .fields()
+            .iter()
+            .map(|field| field.data_type().clone())
+            .collect();
+        assert_eq!(types, expected);
+        let batches = [batch];
+        assert_eq!(strings(&batches, "status"), vec![Some("504".into())]);
+        assert_eq!(strings(&batches, "ok"), vec![Some("false".into())]);
+        assert_eq!(strings(&batches, "ratio"), vec![Some("0.5".into())]);
+        assert_eq!(strings(&batches, "missing"), vec![None]);
+    }
+
+    #[test]
+    fn each_batch_is_a_row_group() {
+        let path = temp_path("row-groups");
+        let drain =
+            ArrowBatchDrain::new(File::create(&path).unwrap(), vec!["user"])
+                .unwrap();
+        let records: Vec<_> = (0..5)
+            .map(|i| {
+                AsyncRecord::from(
+                    &record!(Level::Info, "", &format_args!("{}", i), b!()),
+                    &o!().into(),
+                )
+            })
+            .collect();
+        drain.try_log_batch(&records[..3]).unwrap();
+        drain.try_log_batch(&records[3..]).unwrap();
+        drop(drain);
+
+        assert_eq!(row_group_sizes(&path), [3, 2]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn row_groups_end_at_max_rows_and_on_flush() {
+        let path = temp_path("flush");
+        let drain = ArrowBatchDrain::new(
+            File::create(&path).unwrap(),
+            Vec::<String>::new(),
+        )
+        .unwrap();
+        let (drain, control) =
+            Async::new_batch(FuseBatch(drain), 2, Duration::from_secs(3600))
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let log_msg = |msg: &str| {
+            drain
+                .log(
+                    &record!(Level::Info, "", &format_args!("{}", msg), b!()),
+                    &o!().into(),
+                )
+                .unwrap();
+        };
+
+        // A row group of `max_rows`, then one of the record left on flush
+        for msg in &["one", "two", "three"] {
+            log_msg(msg);
+        }
+        drain.flush().unwrap();
+        // Nothing pending: no empty row group
+        drain.flush().unwrap();
+        log_msg("four");
+        log_msg("five");
+        // Nothing pending either when the drain finishes
+        drop(drain);
+
+        assert_eq!(row_group_sizes(&path), [2, 1, 2]);
+        fs::remove_file(&path).unwrap();
+    }
+}
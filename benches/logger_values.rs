@@ -0,0 +1,115 @@
+//! Compares logging with a logger's values against logging with a
+//! `LoggerValuesHandle` prepared from them.
+//!
+//! Run with `cargo bench --bench logger_values`.
+
+#[macro_use]
+extern crate slog;
+extern crate slog_async;
+
+use slog::{Drain, Key, OwnedKVList, Record, Serializer, KV};
+use slog_async::{Async, OverflowStrategy};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const RECORDS: u32 = 200_000;
+const RUNS: u32 = 5;
+
+/// Formats every key-value, like a text or JSON drain would
+struct FormattingDrain;
+
+struct Discard(usize);
+
+impl Serializer for Discard {
+    fn emit_arguments(
+        &mut self,
+        key: Key,
+        val: &fmt::Arguments,
+    ) -> slog::Result {
+        self.0 += key.len() + fmt::format(*val).len();
+        Ok(())
+    }
+}
+
+impl Drain for FormattingDrain {
+    type Ok = usize;
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &Record,
+        values: &OwnedKVList,
+    ) -> Result<usize, slog::Never> {
+        let mut ser = Discard(0);
+        let _ = values.serialize(record, &mut ser);
+        let _ = record.kv().serialize(record, &mut ser);
+        Ok(ser.0)
+    }
+}
+
+fn build() -> Async {
+    Async::new(FormattingDrain.ignore_res())
+        .overflow_strategy(OverflowStrategy::Block)
+        .build()
+}
+
+fn logger_values() -> OwnedKVList {
+    o!(
+        "service" => "checkout",
+        "version" => "1.4.2",
+        "host" => "db1.example.org",
+        "region" => "eu-west-1",
+        "instance" => 17,
+        "build" => slog::FnValue(|_| format!("{}-{}", "release", 4096)),
+    )
+    .into()
+}
+
+/// Time logging `RECORDS` records and writing them all.
+fn run<F: Fn(&Async, &Record)>(log: F) -> Duration {
+    let async_drain = build();
+    let start = Instant::now();
+    for i in 0..RECORDS {
+        log(
+            &async_drain,
+            &record!(slog::Level::Info, "", &format_args!("msg"), b!("i" => i)),
+        );
+    }
+    // Joins the worker thread once it wrote every record
+    drop(async_drain);
+    start.elapsed()
+}
+
+fn report(name: &str, runs: &[Duration]) {
+    let best = runs.iter().min().unwrap();
+    println!(
+        "{:<24} {:>8.1} ns/record (best of {})",
+        name,
+        best.as_nanos() as f64 / f64::from(RECORDS),
+        runs.len()
+    );
+}
+
+fn main() {
+    let values = logger_values();
+    let logger_values: Vec<_> = (0..RUNS)
+        .map(|_| {
+            run(|async_drain, record| {
+                async_drain.log(record, &values).unwrap();
+            })
+        })
+        .collect();
+    let handle_runs: Vec<_> = (0..RUNS)
+        .map(|_| {
+            let async_drain = build();
+            let handle = async_drain.precompute_logger_values(&values);
+            drop(async_drain);
+            run(|async_drain, record| {
+                async_drain.log_with_handle(record, &handle).unwrap();
+            })
+        })
+        .collect();
+
+    report("logger values", &logger_values);
+    report("LoggerValuesHandle", &handle_runs);
+}
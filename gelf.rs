@@ -0,0 +1,398 @@
+//! GELF drain
+//!
+//! Formats records as GELF 1.1 JSON messages and sends them to a Graylog
+//! UDP input. Wrapped by `Async` the formatting and socket IO happen on the
+//! worker thread.
+//!
+//! The message is sent as `short_message` and the level as the matching
+//! syslog severity. The record tag, when set, is sent as `_tag`, and logger
+//! and record key-values as additional `_key` fields: numbers as JSON
+//! numbers, anything else as strings. Timestamp is left for Graylog to fill
+//! in.
+//!
+//! Messages larger than the chunk size are split into GELF chunks, up to the
+//! limit of 128 chunks per message. Messages are not compressed.
+
+use slog::{Drain, Key, Level, OwnedKVList, Record, Serializer, KV};
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default chunk size, fitting the MTU of most networks
+pub const DEFAULT_CHUNK_SIZE: usize = 1420;
+
+const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+// Magic, message id, sequence number and sequence count
+const CHUNK_HEADER_SIZE: usize = 2 + 8 + 1 + 1;
+const MAX_CHUNKS: usize = 128;
+
+/// Map a `Level` to a GELF level, the syslog severity.
+///
+/// syslog has no level below debug, so `Trace` is mapped to debug as well.
+pub fn level(level: Level) -> u8 {
+    match level {
+        Level::Critical => 2,
+        Level::Error => 3,
+        Level::Warning => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Drain sending GELF formatted records to Graylog over UDP
+pub struct GelfDrain {
+    socket: UdpSocket,
+    host: String,
+    chunk_size: usize,
+    next_message_id: AtomicU64,
+}
+
+impl GelfDrain {
+    /// Send to a Graylog GELF UDP input.
+    ///
+    /// The `host` field is set to the hostname of the machine, see
+    /// `GelfDrain::host` to override it.
+    pub fn udp<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        // Only has to differ between messages in flight, from any sender
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0)
+            ^ (u64::from(process::id()) << 32);
+        Ok(GelfDrain {
+            socket,
+            host: hostname(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            next_message_id: AtomicU64::new(seed),
+        })
+    }
+
+    /// Set the `host` field of every message.
+    pub fn host<S: Into<String>>(mut self, host: S) -> Self {
+        self.host = host.into();
+        self
+    }
+
+    /// Set the largest datagram to send, chunk header included.
+    ///
+    /// Defaults to `DEFAULT_CHUNK_SIZE`; larger chunks, up to 8192 bytes,
+    /// suit local networks.
+    ///
+    /// # Panics
+    ///
+    /// If `size` leaves no room for data after the chunk header.
+    pub fn chunk_size(mut self, size: usize) -> Self {
+        assert!(size > CHUNK_HEADER_SIZE);
+        self.chunk_size = size;
+        self
+    }
+
+    fn format(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Result<String, slog::Error> {
+        let mut msg = String::from("{\"version\":\"1.1\",\"host\":");
+        push_string(&mut msg, &self.host);
+        msg.push_str(",\"short_message\":");
+        push_string(&mut msg, &record.msg().to_string());
+        write!(msg, ",\"level\":{}", level(record.level()))?;
+        if !record.tag().is_empty() {
+            msg.push_str(",\"_tag\":");
+            push_string(&mut msg, record.tag());
+        }
+        let mut ser = FieldSerializer(&mut msg);
+        logger_values.serialize(record, &mut ser)?;
+        record.kv().serialize(record, &mut ser)?;
+        msg.push('}');
+        Ok(msg)
+    }
+
+    /// Split `msg` into chunks, or `None` if it fits a single datagram.
+    fn chunks(&self, msg: &[u8]) -> io::Result<Option<Vec<Vec<u8>>>> {
+        if msg.len() <= self.chunk_size {
+            return Ok(None);
+        }
+        let data_size = self.chunk_size - CHUNK_HEADER_SIZE;
+        let count = msg.len().div_ceil(data_size);
+        if count > MAX_CHUNKS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "GELF message too large",
+            ));
+        }
+        let id = self.next_message_id.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(
+            msg.chunks(data_size)
+                .enumerate()
+                .map(|(seq, data)| {
+                    let mut chunk =
+                        Vec::with_capacity(CHUNK_HEADER_SIZE + data.len());
+                    chunk.extend_from_slice(&CHUNK_MAGIC);
+                    chunk.extend_from_slice(&id.to_be_bytes());
+                    chunk.push(seq as u8);
+                    chunk.push(count as u8);
+                    chunk.extend_from_slice(data);
+                    chunk
+                })
+                .collect(),
+        ))
+    }
+}
+
+impl Drain for GelfDrain {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()> {
+        let msg = self.format(record, logger_values)?;
+        match self.chunks(msg.as_bytes())? {
+            None => {
+                self.socket.send(msg.as_bytes())?;
+            }
+            Some(chunks) => {
+                for chunk in chunks {
+                    self.socket.send(&chunk)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Best effort hostname of the machine.
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .map(|name| name.trim().to_owned())
+        .find(|name| !name.is_empty())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| "localhost".to_owned())
+}
+
+/// Append `value` to `msg` as a JSON string.
+fn push_string(msg: &mut String, value: &str) {
+    msg.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => msg.push_str("\\\""),
+            '\\' => msg.push_str("\\\\"),
+            '\n' => msg.push_str("\\n"),
+            '\r' => msg.push_str("\\r"),
+            '\t' => msg.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(msg, "\\u{:04x}", c as u32);
+            }
+            c => msg.push(c),
+        }
+    }
+    msg.push('"');
+}
+
+/// Appends `,"_key":value` additional fields to a message.
+struct FieldSerializer<'a>(&'a mut String);
+
+impl<'a> FieldSerializer<'a> {
+    /// Append the name of the field for `key`.
+    ///
+    /// GELF only allows letters, digits, `_`, `.` and `-` in field names,
+    /// other characters are replaced by `_`. `_id` is reserved by Graylog, so
+    /// `id` is sent as `__id`.
+    fn push_key(&mut self, key: Key) {
+        let key: &str = key.as_ref();
+        self.0.push_str(",\"_");
+        if key == "id" {
+            self.0.push('_');
+        }
+        for c in key.chars() {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' {
+                self.0.push(c);
+            } else {
+                self.0.push('_');
+            }
+        }
+        self.0.push_str("\":");
+    }
+
+    fn emit_number<N: fmt::Display>(
+        &mut self,
+        key: Key,
+        val: N,
+    ) -> slog::Result {
+        self.push_key(key);
+        write!(self.0, "{}", val)?;
+        Ok(())
+    }
+
+    fn emit_float(&mut self, key: Key, val: f64) -> slog::Result {
+        if !val.is_finite() {
+            // Not representable in JSON
+            return self.emit_arguments(key, &format_args!("{}", val));
+        }
+        self.emit_number(key, val)
+    }
+}
+
+impl<'a> Serializer for FieldSerializer<'a> {
+    fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
+        self.emit_number(key, val)
+    }
+    fn emit_isize(&mut self, key: Key, val: isize) -> slog::Result {
+        self.emit_number(key, val)
+    }
+    fn emit_u8(&mut self, key: Key, val: u8) -> slog::Result {
+        self.emit_number(key, val)
+    }
+    fn emit_i8(&mut self, key: Key, val: i8) -> slog::Result {
+        self.emit_number(key, val)
+    }
+    fn emit_u16(&mut self, key: Key, val: u16) -> slog::Result {
+        self.emit_number(key, val)
+    }
+    fn emit_i16(&mut self, key: Key, val: i16) -> slog::Result {
+        self.emit_number(key, val)
+    }
+    fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
+        self.emit_number(key, val)
+    }
+    fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
+        self.emit_number(key, val)
+    }
+    fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
+        self.emit_number(key, val)
+    }
+    fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
+        self.emit_number(key, val)
+    }
+    fn emit_f32(&mut self, key: Key, val: f32) -> slog::Result {
+        self.emit_float(key, f64::from(val))
+    }
+    fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
+        self.emit_float(key, val)
+    }
+
+    fn emit_arguments(
+        &mut self,
+        key: Key,
+        val: &fmt::Arguments,
+    ) -> slog::Result {
+        self.push_key(key);
+        push_string(self.0, &val.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn drain_to(server: &UdpSocket) -> GelfDrain {
+        GelfDrain::udp(server.local_addr().unwrap())
+            .unwrap()
+            .host("web-1")
+    }
+
+    #[test]
+    fn level_mapping() {
+        assert_eq!(level(Level::Critical), 2);
+        assert_eq!(level(Level::Error), 3);
+        assert_eq!(level(Level::Warning), 4);
+        assert_eq!(level(Level::Info), 6);
+        assert_eq!(level(Level::Debug), 7);
+        assert_eq!(level(Level::Trace), 7);
+    }
+
+    #[test]
+    fn fields_are_mapped() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let drain = drain_to(&server);
+        drain
+            .log(
+                &record!(
+                    Level::Warning,
+                    "myapp",
+                    &format_args!("say \"hi\"\n"),
+                    b!("status" => 503, "ratio" => 0.5, "path" => "/index",
+                       "bad key" => true)
+                ),
+                &o!("id" => 7, "nan" => f64::NAN).into(),
+            )
+            .unwrap();
+
+        let mut buf = [0; 1024];
+        let n = server.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..n]).unwrap();
+        let fields = message
+            .strip_prefix(
+                "{\"version\":\"1.1\",\"host\":\"web-1\",\
+                 \"short_message\":\"say \\\"hi\\\"\\n\",\"level\":4,\
+                 \"_tag\":\"myapp\"",
+            )
+            .and_then(|fields| fields.strip_suffix('}'))
+            .unwrap_or_else(|| panic!("{}", message));
+        // slog doesn't guarantee the order of the key-values
+        let expected = [
+            ",\"__id\":7",
+            ",\"_nan\":\"NaN\"",
+            ",\"_status\":503",
+            ",\"_ratio\":0.5",
+            ",\"_path\":\"/index\"",
+            ",\"_bad_key\":\"true\"",
+        ];
+        for field in &expected {
+            assert!(fields.contains(field), "{} in {}", field, message);
+        }
+        assert_eq!(
+            fields.len(),
+            expected.iter().map(|field| field.len()).sum::<usize>(),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn large_messages_are_chunked() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let drain = drain_to(&server).chunk_size(64);
+        // Send a record and return the message sent
+        let log = |msg: &str| {
+            let send = |record: &Record| -> io::Result<String> {
+                drain.log(record, &o!().into())?;
+                Ok(drain.format(record, &o!().into()).unwrap())
+            };
+            send(&record!(Level::Info, "", &format_args!("{}", msg), b!()))
+        };
+        let expected = log(&"x".repeat(300)).unwrap();
+
+        let count = expected.len().div_ceil(52);
+        let mut buf = [0; 128];
+        let mut message = Vec::new();
+        let mut id = None;
+        for seq in 0..count {
+            let n = server.recv(&mut buf).unwrap();
+            assert!(n <= 64);
+            assert_eq!(buf[..2], CHUNK_MAGIC);
+            assert_eq!(*id.get_or_insert(buf[2..10].to_vec()), buf[2..10]);
+            assert_eq!(buf[10] as usize, seq);
+            assert_eq!(buf[11] as usize, count);
+            message.extend_from_slice(&buf[12..n]);
+        }
+        assert_eq!(String::from_utf8(message).unwrap(), expected);
+
+        assert_eq!(
+            log(&"x".repeat(128 * 52 + 1)).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -43,6 +43,10 @@
 //!    1 // exit code to return
 //! }
 //! ```
+//!
+//! Alternatively, with the `registry` feature, `Async::install_exit_guard`
+//! flushes all the live drains when the process exits, `std::process::exit`
+//! included.
 // }}}
 
 // {{{ Imports & meta
@@ -50,52 +54,316 @@
 
 #[macro_use]
 extern crate slog;
+#[cfg(feature = "arrow")]
+extern crate arrow_array;
+#[cfg(feature = "arrow")]
+extern crate arrow_schema;
 extern crate crossbeam_channel;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(feature = "otel")]
+extern crate opentelemetry;
+#[cfg(feature = "arrow")]
+extern crate parquet;
 extern crate take_mut;
 extern crate thread_local;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tokio")]
+extern crate tokio_stream;
 
-use crossbeam_channel::Sender;
+use crossbeam_channel::{
+    Receiver, RecvTimeoutError, Select, SendTimeoutError, Sender, TryRecvError,
+};
 
 use slog::{BorrowedKV, Level, Record, RecordStatic, SingleKV, KV};
 use slog::{Key, OwnedKVList, Serializer};
 
 use slog::Drain;
+use std::any::Any;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::hash_map::Entry;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::{TryFrom, TryInto};
+use std::env;
 use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{io, thread};
 
-use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::Arc;
+use std::sync::{Condvar, Mutex, OnceLock};
 use take_mut::take;
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "gelf")]
+pub mod gelf;
+#[cfg(feature = "logfmt")]
+pub mod logfmt;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(feature = "otel")]
+pub mod otel;
+#[cfg(feature = "registry")]
+pub mod registry;
+#[cfg(feature = "syslog")]
+pub mod syslog;
+#[cfg(feature = "testing")]
+pub mod testing;
 // }}}
 
 /// This is the key given to the logger to filter based on pid.
 pub const PID_KEY: &'static str = "pid";
 
+/// Key of the fraction of records to keep, for sampling per call site.
+///
+/// A record carrying eg. `"sample_rate" => 0.1`, itself or through its
+/// logger, is written with a probability of 10% by the worker thread. The
+/// rate goes from `0.0`, dropping every record, to `1.0`, keeping them all;
+/// values outside are clamped, and records with a value that isn't a number
+/// are all kept. The rate is looked up last, once a record passed the other
+/// filters, `sample_by_key` and `transform`, and can't be a `lazy_kv` value.
+pub const SAMPLE_RATE_KEY: &str = "sample_rate";
+
+/// Key of the record format version set by `AsyncBuilder::schema_version`.
+pub const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// Tag of the record written by `AsyncBuilder::probe_on_build`.
+pub const PROBE_TAG: &str = "slog-async-probe";
+
+/// Default tag of the reports of dropped records, see
+/// `AsyncBuilder::dropped_report_tag`.
+pub const DROPPED_REPORT_TAG: &str = "slog-async";
+
 /// Allows the user to enable/disable logs for processes
-pub struct PIDLogControl(Sender<AsyncMsg>);
+pub struct PIDLogControl {
+    sender: Sender<AsyncMsg>,
+    overflow_strategy: Arc<SharedOverflowStrategy>,
+    drop_reporting: Arc<AtomicBool>,
+}
 
 impl PIDLogControl {
-    fn new(sender: Sender<AsyncMsg>) -> Self {
-        PIDLogControl(sender)
+    fn new(
+        sender: Sender<AsyncMsg>,
+        overflow_strategy: Arc<SharedOverflowStrategy>,
+        drop_reporting: Arc<AtomicBool>,
+    ) -> Self {
+        PIDLogControl {
+            sender,
+            overflow_strategy,
+            drop_reporting,
+        }
     }
 
     /// Disables emitting logs for a specific PID.
     pub fn disable(&self, pid: usize) -> Result<(), ()> {
         // blocking task
-        self.0.send(AsyncMsg::DisablePID(pid)).map_err(|_| ())
+        self.sender.send(AsyncMsg::DisablePID(pid)).map_err(|_| ())
     }
 
     /// Enables emitting logs for a specific PID.
     pub fn enable(&self, pid: usize) -> Result<(), ()> {
         // blocking task
-        self.0.send(AsyncMsg::EnablePID(pid)).map_err(|_| ())
+        self.sender.send(AsyncMsg::EnablePID(pid)).map_err(|_| ())
+    }
+
+    /// Replaces the set of enabled PIDs at once.
+    ///
+    /// The worker thread switches to the new set in one step, so no record
+    /// is filtered by a mix of the old and the new set, eg. while reloading
+    /// the configuration.
+    pub fn set_enabled(&self, pids: HashSet<usize>) -> AsyncResult<()> {
+        self.sender.send(AsyncMsg::SetEnabledPIDs(pids))?;
+        Ok(())
     }
 
     /// Sets the emitted log level
+    ///
+    /// Same as `level_at_least`.
     pub fn log_level(&self, level: slog::Level) -> Result<(), ()> {
-        self.0.send(AsyncMsg::LogLevel(level)).map_err(|_| ())
+        self.level_at_least(level).map_err(|_| ())
+    }
+
+    /// Emits the records at least as severe as `level`.
+    ///
+    /// `Level::Info` emits `Critical`, `Error`, `Warning` and `Info` records,
+    /// and drops `Debug` and `Trace` ones.
+    pub fn level_at_least(&self, level: slog::Level) -> AsyncResult<()> {
+        self.sender
+            .send(AsyncMsg::LogLevel(LevelFilter::AtLeast(level)))?;
+        Ok(())
+    }
+
+    /// Emits the records at most as severe as `level`.
+    ///
+    /// `Level::Debug` emits `Debug` and `Trace` records, and drops the more
+    /// severe ones, eg. for a drain only taking the verbose output.
+    pub fn level_at_most(&self, level: slog::Level) -> AsyncResult<()> {
+        self.sender
+            .send(AsyncMsg::LogLevel(LevelFilter::AtMost(level)))?;
+        Ok(())
+    }
+
+    /// Writes the records of a specific PID to `drain`, see `PidRouter`.
+    pub fn route<D>(&self, pid: usize, drain: D) -> AsyncResult<()>
+    where
+        D: Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        self.sender.send(AsyncMsg::RoutePID(pid, Box::new(drain)))?;
+        Ok(())
+    }
+
+    /// Writes the records of a specific PID to the default drain again.
+    pub fn unroute(&self, pid: usize) -> AsyncResult<()> {
+        self.sender.send(AsyncMsg::UnroutePID(pid))?;
+        Ok(())
+    }
+
+    /// Replaces the overflow strategy of the `Async` drain.
+    ///
+    /// See `Async::set_overflow_strategy`.
+    pub fn set_overflow_strategy(
+        &self,
+        overflow_strategy: OverflowStrategy,
+    ) -> AsyncResult<()> {
+        self.overflow_strategy.store(overflow_strategy)
+    }
+
+    /// Turns the reports of dropped records of the `Async` drain on or off.
+    ///
+    /// Takes effect right away, eg. to keep a known bulk import window from
+    /// flooding the logs with drop reports. Dropped records are still
+    /// counted meanwhile, see `Async::stats` and `Async::drop_stats`: once
+    /// reporting is turned back on, the next report includes them, unless
+    /// taken with `Async::take_dropped_count` in between.
+    pub fn set_drop_reporting(&self, enabled: bool) {
+        self.drop_reporting.store(enabled, Ordering::Relaxed);
+    }
+}
+
+/// Message for the worker thread, see `ControlSender`
+///
+/// Wraps the messages behind `PIDLogControl` and the runtime configuration
+/// methods of `AsyncCore`, for custom control layers. The internal message
+/// type stays private so it can change: new constructors may be added, and
+/// the existing ones keep their meaning.
+pub struct ControlMsg(AsyncMsg);
+
+impl ControlMsg {
+    /// See `PIDLogControl::enable`.
+    pub fn enable_pid(pid: usize) -> Self {
+        ControlMsg(AsyncMsg::EnablePID(pid))
+    }
+
+    /// See `PIDLogControl::disable`.
+    pub fn disable_pid(pid: usize) -> Self {
+        ControlMsg(AsyncMsg::DisablePID(pid))
+    }
+
+    /// See `PIDLogControl::set_enabled`.
+    pub fn set_enabled_pids(pids: HashSet<usize>) -> Self {
+        ControlMsg(AsyncMsg::SetEnabledPIDs(pids))
+    }
+
+    /// See `PIDLogControl::level_at_least`.
+    pub fn level_at_least(level: Level) -> Self {
+        ControlMsg(AsyncMsg::LogLevel(LevelFilter::AtLeast(level)))
+    }
+
+    /// See `PIDLogControl::level_at_most`.
+    pub fn level_at_most(level: Level) -> Self {
+        ControlMsg(AsyncMsg::LogLevel(LevelFilter::AtMost(level)))
+    }
+
+    /// See `PIDLogControl::route`.
+    pub fn route_pid<D>(pid: usize, drain: D) -> Self
+    where
+        D: Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        ControlMsg(AsyncMsg::RoutePID(pid, Box::new(drain)))
+    }
+
+    /// See `PIDLogControl::unroute`.
+    pub fn unroute_pid(pid: usize) -> Self {
+        ControlMsg(AsyncMsg::UnroutePID(pid))
+    }
+
+    /// Like `AsyncCore::migrate_to`, but without waiting for the records
+    /// already queued: the worker thread writes them to `drain` too.
+    pub fn replace_drain<D>(drain: D) -> Self
+    where
+        D: Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        ControlMsg(AsyncMsg::ReplaceDrain(Box::new(drain)))
+    }
+
+    /// See `AsyncCore::register_sink`.
+    pub fn register_sink<D>(name: &str, drain: D) -> Self
+    where
+        D: Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        ControlMsg(AsyncMsg::RegisterSink(name.into(), Box::new(drain)))
+    }
+
+    /// See `AsyncCore::unregister_sink`.
+    pub fn unregister_sink(name: &str) -> Self {
+        ControlMsg(AsyncMsg::UnregisterSink(name.into()))
+    }
+}
+
+/// Sends `ControlMsg`s to the worker thread, see `AsyncCore::control_sender`
+#[derive(Clone)]
+pub struct ControlSender {
+    sender: Sender<AsyncMsg>,
+}
+
+impl ControlSender {
+    /// Send `msg` to the worker thread.
+    ///
+    /// Never blocks. Fails with an error for which
+    /// `AsyncError::is_worker_gone` holds once the worker thread ended.
+    pub fn send(&self, msg: ControlMsg) -> AsyncResult<()> {
+        self.sender.send(msg.0)?;
+        Ok(())
+    }
+}
+
+/// Per-PID drains of the worker thread
+///
+/// Records with a PID routed to a drain are written to it instead of the
+/// drain wrapped by `AsyncCore`, which stays the default for the others, eg.
+/// for a log file per worker process of a prefork server. Routes can be
+/// changed at runtime with `PIDLogControl::route` and
+/// `PIDLogControl::unroute`. Records still have to be enabled with
+/// `PIDLogControl::enable`. See `AsyncCoreBuilder::pid_router`.
+#[derive(Default)]
+pub struct PidRouter {
+    routes: HashMap<usize, BoxedDrain>,
+}
+
+impl PidRouter {
+    /// New `PidRouter` without routes
+    pub fn new() -> Self {
+        PidRouter::default()
+    }
+
+    /// Write the records of `pid` to `drain`.
+    pub fn route<D>(mut self, pid: usize, drain: D) -> Self
+    where
+        D: Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        self.routes.insert(pid, Box::new(drain));
+        self
     }
 }
 
@@ -121,99 +389,237 @@ impl Serializer for PidSerializer {
     }
 }
 
+/// Search `logger_values` for a PID.
+fn find_pid(record: &Record, logger_values: &OwnedKVList) -> Option<usize> {
+    let mut pid = PidSerializer { pid: None };
+    logger_values
+        .serialize(record, &mut pid)
+        .expect("Cannot fail");
+    pid.pid
+}
+
+/// Serialize a KV to find the value of a given key, formatted as a string.
+struct FindValueSerializer {
+    key: &'static str,
+    value: Option<String>,
+}
+
+impl FindValueSerializer {
+    fn new(key: &'static str) -> Self {
+        FindValueSerializer { key, value: None }
+    }
+}
+
+impl Serializer for FindValueSerializer {
+    fn emit_arguments(
+        &mut self,
+        key: Key,
+        val: &fmt::Arguments,
+    ) -> slog::Result {
+        if key == static_key(self.key) {
+            self.value = Some(format!("{}", val));
+        }
+        Ok(())
+    }
+}
+
+/// How record key-values are kept when a record is serialized to be sent to
+/// the worker thread, see `AsyncBuilder::value_policy`.
+///
+/// Only applies to the key-values of the record itself: the logger's ones are
+/// sent as they are.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ValuePolicy {
+    /// Keep the type of values, so eg. numbers reach the drain as numbers.
+    /// Values formatted with `Display` or `Debug` become strings.
+    #[default]
+    KeepTyped,
+    /// Turn every value into a string, so the drain sees uniform values.
+    StringifyAll,
+    /// Like `KeepTyped`, but replace the value of the listed keys with
+    /// `"[redacted]"`.
+    Redact(&'static [&'static str]),
+}
+
+const REDACTED: &str = "[redacted]";
+
+/// Key of the number of key-values left out by `AsyncBuilder::max_kv_pairs`.
+pub const KV_TRUNCATED_KEY: &str = "kv_truncated";
+
+/// Appended to values cut by `AsyncBuilder::max_value_len`.
+pub const VALUE_TRUNCATED_MARKER: &str = "...[truncated]";
+
 struct ToSendSerializer {
     kv: Box<dyn KV + Send>,
+    // Estimated number of bytes allocated for `kv`
+    size: usize,
+    policy: ValuePolicy,
+    max_pairs: Option<usize>,
+    pairs: usize,
+    max_value_len: Option<usize>,
 }
 
 impl ToSendSerializer {
-    fn new() -> Self {
-        ToSendSerializer { kv: Box::new(()) }
+    fn new(
+        policy: ValuePolicy,
+        max_pairs: Option<usize>,
+        max_value_len: Option<usize>,
+    ) -> Self {
+        ToSendSerializer {
+            kv: Box::new(()),
+            size: 0,
+            policy,
+            max_pairs,
+            pairs: 0,
+            max_value_len,
+        }
+    }
+
+    /// Where to cut `val` to fit `max_value_len`, at a char boundary, if it
+    /// doesn't.
+    fn value_end(&self, val: &str) -> Option<usize> {
+        let max = self.max_value_len?;
+        if val.len() <= max {
+            return None;
+        }
+        let mut end = max;
+        while !val.is_char_boundary(end) {
+            end -= 1;
+        }
+        Some(end)
+    }
+
+    // `Key` can't be looked up in `keys` with the `dynamic-keys` feature
+    #[allow(clippy::manual_contains)]
+    fn push<V>(&mut self, key: Key, val: V) -> slog::Result
+    where
+        V: slog::Value + Send + 'static,
+    {
+        self.pairs += 1;
+        if matches!(self.max_pairs, Some(max) if self.pairs > max) {
+            return Ok(());
+        }
+        if let ValuePolicy::Redact(keys) = self.policy {
+            if keys.iter().any(|&k| key == k) {
+                return self.push_kv(key, REDACTED);
+            }
+        }
+        self.push_kv(key, val)
+    }
+
+    /// Push a value stringified under `ValuePolicy::StringifyAll`.
+    fn push_typed<V>(&mut self, key: Key, val: V) -> slog::Result
+    where
+        V: slog::Value + fmt::Display + Send + 'static,
+    {
+        if self.policy == ValuePolicy::StringifyAll {
+            let val = val.to_string();
+            self.size += val.len();
+            return self.push(key, val);
+        }
+        self.push(key, val)
     }
 
-    fn finish(self) -> Box<dyn KV + Send> {
-        self.kv
+    fn push_kv<V>(&mut self, key: Key, val: V) -> slog::Result
+    where
+        V: slog::Value + Send + 'static,
+    {
+        self.size += mem::size_of::<(Box<dyn KV + Send>, SingleKV<V>)>();
+        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
+        Ok(())
+    }
+
+    fn finish(mut self) -> (Box<dyn KV + Send>, usize) {
+        if let Some(max) = self.max_pairs {
+            if self.pairs > max {
+                let truncated = self.pairs - max;
+                let _ = self.push_kv(static_key(KV_TRUNCATED_KEY), truncated);
+            }
+        }
+        (self.kv, self.size)
     }
 }
 
 impl Serializer for ToSendSerializer {
     fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_unit(&mut self, key: Key) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, ()))));
-        Ok(())
+        match LAZY_VALUE.with(|lazy| lazy.borrow_mut().take()) {
+            Some(f) => {
+                self.push(key, LazyValue(Mutex::new(Some(Lazy::Pending(f)))))
+            }
+            None if self.policy == ValuePolicy::StringifyAll => {
+                self.push(key, "()")
+            }
+            None => self.push(key, ()),
+        }
     }
     fn emit_none(&mut self, key: Key) -> slog::Result {
-        let val: Option<()> = None;
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        if self.policy == ValuePolicy::StringifyAll {
+            return self.push(key, "None");
+        }
+        self.push(key, None::<()>)
     }
     fn emit_char(&mut self, key: Key, val: char) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_u8(&mut self, key: Key, val: u8) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_i8(&mut self, key: Key, val: i8) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_u16(&mut self, key: Key, val: u16) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_i16(&mut self, key: Key, val: i16) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_f32(&mut self, key: Key, val: f32) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_isize(&mut self, key: Key, val: isize) -> slog::Result {
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push_typed(key, val)
     }
     fn emit_str(&mut self, key: Key, val: &str) -> slog::Result {
-        let val = val.to_owned();
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        let val = match self.value_end(val) {
+            Some(end) => format!("{}{}", &val[..end], VALUE_TRUNCATED_MARKER),
+            None => val.to_owned(),
+        };
+        self.size += val.len();
+        self.push(key, val)
     }
     fn emit_arguments(
         &mut self,
         key: Key,
         val: &fmt::Arguments,
     ) -> slog::Result {
-        let val = fmt::format(*val);
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        let mut val = fmt::format(*val);
+        if let Some(end) = self.value_end(&val) {
+            val.truncate(end);
+            val.push_str(VALUE_TRUNCATED_MARKER);
+        }
+        self.size += val.len();
+        self.push(key, val)
     }
 
     #[cfg(feature = "nested-values")]
@@ -223,684 +629,11077 @@ impl Serializer for ToSendSerializer {
         value: &slog::SerdeValue,
     ) -> slog::Result {
         let val = value.to_sendable();
-        take(&mut self.kv, |kv| Box::new((kv, SingleKV(key, val))));
-        Ok(())
+        self.push(key, val)
     }
 }
 // }}}
 
-// {{{ Async
-// {{{ AsyncError
-/// Errors reported by `Async`
-#[derive(Debug)]
-pub enum AsyncError {
-    /// Could not send record to worker thread due to full queue
-    Full,
-    /// Fatal problem - mutex or channel poisoning issue
-    Fatal(Box<dyn std::error::Error>),
+// {{{ Hash chain
+/// Chain of record hashes, see `AsyncCoreBuilder::hash_chain`
+///
+/// Each record carries, under the chain's key, the 64-bit FNV-1a hash of:
+///
+/// * the hash of the previous record as 8 big-endian bytes, all zeroes for
+///   the first record,
+/// * followed by the record content: the short level name, the tag and the
+///   message separated by spaces, then ` key=value` for each logger and
+///   record key-value but the chain's own, in serialization order.
+///
+/// The hash is written as 16 lowercase hexadecimal digits. It detects
+/// records lost, reordered or modified by accident or by a third party
+/// without access to the logging process, not forgeries: FNV-1a isn't a
+/// cryptographic hash, and the chain isn't keyed.
+///
+/// Verify a chain by passing the records, in order, to `verify`, eg. from a
+/// drain reading them back.
+pub struct HashChain {
+    key: &'static str,
+    prev: u64,
 }
 
-impl<T> From<crossbeam_channel::TrySendError<T>> for AsyncError {
-    fn from(_: crossbeam_channel::TrySendError<T>) -> AsyncError {
-        AsyncError::Full
+impl HashChain {
+    /// Start a chain of hashes stored under `key`.
+    pub fn new(key: &'static str) -> Self {
+        HashChain { key, prev: 0 }
     }
-}
 
-impl<T> From<crossbeam_channel::SendError<T>> for AsyncError {
-    fn from(_: crossbeam_channel::SendError<T>) -> AsyncError {
-        AsyncError::Fatal(Box::new(io::Error::new(
-            io::ErrorKind::BrokenPipe,
-            "The logger thread terminated",
-        )))
+    /// Check the hash carried by the next record of the chain.
+    ///
+    /// Returns `false` if the record has no valid hash under the key, or if
+    /// it doesn't chain to the previous record. The chain then continues
+    /// from the hash the record carries, so a missing record only fails the
+    /// record following it.
+    pub fn verify(
+        &mut self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> bool {
+        let mut ser = FindValueSerializer::new(self.key);
+        let _ = logger_values.serialize(record, &mut ser);
+        let _ = record.kv().serialize(record, &mut ser);
+        let claimed = ser
+            .value
+            .and_then(|value| u64::from_str_radix(&value, 16).ok());
+        let hash = self.hash(record, logger_values);
+        match claimed {
+            Some(claimed) => {
+                self.prev = claimed;
+                claimed == hash
+            }
+            None => false,
+        }
     }
-}
 
-impl<T> From<std::sync::PoisonError<T>> for AsyncError {
-    fn from(err: std::sync::PoisonError<T>) -> AsyncError {
-        AsyncError::Fatal(Box::new(io::Error::new(
-            io::ErrorKind::BrokenPipe,
-            err.to_string(),
-        )))
+    /// Hash the next record and advance the chain, returning the hash to
+    /// store.
+    fn link(&mut self, record: &Record, logger_values: &OwnedKVList) -> String {
+        self.prev = self.hash(record, logger_values);
+        format!("{:016x}", self.prev)
     }
-}
 
-/// `AsyncResult` alias
-pub type AsyncResult<T> = std::result::Result<T, AsyncError>;
+    fn hash(&self, record: &Record, logger_values: &OwnedKVList) -> u64 {
+        let mut content = format!(
+            "{} {} {}",
+            record.level().as_short_str(),
+            record.tag(),
+            record.msg()
+        );
+        let mut ser = ContentSerializer {
+            skip: self.key,
+            content: &mut content,
+        };
+        let _ = logger_values.serialize(record, &mut ser);
+        let _ = record.kv().serialize(record, &mut ser);
+        fnv1a(self.prev.to_be_bytes().iter().chain(content.as_bytes()))
+    }
+}
 
-// }}}
+/// 64-bit FNV-1a hash of `bytes`.
+fn fnv1a<'a, I: IntoIterator<Item = &'a u8>>(bytes: I) -> u64 {
+    bytes
+        .into_iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
 
-// {{{ AsyncCore
-/// `AsyncCore` builder
-pub struct AsyncCoreBuilder<D>
-where
-    D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
-{
-    chan_size: usize,
-    blocking: bool,
-    drain: D,
-    thread_name: Option<String>,
+/// Appends ` key=value` pairs but the one of `skip` to the content hashed by
+/// `HashChain`.
+struct ContentSerializer<'a> {
+    skip: &'static str,
+    content: &'a mut String,
 }
 
-impl<D> AsyncCoreBuilder<D>
-where
-    D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
-{
-    fn new(drain: D) -> Self {
-        AsyncCoreBuilder {
-            chan_size: 128,
-            blocking: false,
-            drain,
-            thread_name: None,
+impl<'a> Serializer for ContentSerializer<'a> {
+    fn emit_arguments(
+        &mut self,
+        key: Key,
+        val: &fmt::Arguments,
+    ) -> slog::Result {
+        if key != static_key(self.skip) {
+            fmt::Write::write_fmt(
+                self.content,
+                format_args!(" {}={}", key, val),
+            )?;
         }
+        Ok(())
     }
+}
+// }}}
 
-    /// Configure a name to be used for the background thread.
-    ///
-    /// The name must not contain '\0'.
-    ///
-    /// # Panics
-    ///
-    /// If a name with '\0' is passed.
-    pub fn thread_name(mut self, name: String) -> Self {
-        assert!(name.find('\0').is_none(), "Name with \\'0\\' in it passed");
-        self.thread_name = Some(name);
-        self
+// {{{ Entry formatting
+/// Format a record as its short level name and message, followed by logger
+/// and record key-values as ` key=value` pairs.
+pub(crate) fn format_entry(
+    record: &Record,
+    logger_values: &OwnedKVList,
+) -> String {
+    let mut entry =
+        format!("{} {}", record.level().as_short_str(), record.msg());
+    let mut ser = PairSerializer(&mut entry);
+    // `PairSerializer` only fails if a value fails to format
+    let _ = logger_values.serialize(record, &mut ser);
+    let _ = record.kv().serialize(record, &mut ser);
+    entry
+}
+
+/// Appends ` key=value` pairs to a string.
+pub(crate) struct PairSerializer<'a>(pub(crate) &'a mut String);
+
+impl<'a> Serializer for PairSerializer<'a> {
+    fn emit_arguments(
+        &mut self,
+        key: Key,
+        val: &fmt::Arguments,
+    ) -> slog::Result {
+        fmt::Write::write_fmt(self.0, format_args!(" {}={}", key, val))?;
+        Ok(())
     }
+}
 
-    /// Set channel size used to send logging records to worker thread. When
-    /// buffer is full `AsyncCore` will start returning `AsyncError::Full` or block, depending on
-    /// the `blocking` configuration.
-    pub fn chan_size(mut self, s: usize) -> Self {
-        self.chan_size = s;
-        self
+/// Last records written, see `AsyncCoreBuilder::keep_recent`
+struct RecentRecords {
+    capacity: usize,
+    lines: VecDeque<String>,
+}
+
+impl RecentRecords {
+    fn new(capacity: usize) -> Self {
+        RecentRecords {
+            capacity,
+            lines: VecDeque::with_capacity(capacity),
+        }
     }
 
-    /// Should the logging call be blocking if the channel is full?
-    ///
-    /// Default is false, in which case it'll return `AsyncError::Full`.
-    pub fn blocking(mut self, blocking: bool) -> Self {
-        self.blocking = blocking;
-        self
+    fn push(&mut self, r: &AsyncRecord) {
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        let mut line = String::new();
+        r.as_record_values(|record, logger_values| {
+            line = format_entry(record, logger_values);
+        });
+        self.lines.push_back(line);
     }
+}
+// }}}
 
-    fn spawn_thread(self) -> (thread::JoinHandle<()>, Sender<AsyncMsg>) {
-        let (tx, rx) = crossbeam_channel::bounded(self.chan_size);
-        let mut builder = thread::Builder::new();
-        if let Some(thread_name) = self.thread_name {
-            builder = builder.name(thread_name);
-        }
-        let drain = self.drain;
-        let join = builder
-            .spawn(move || {
-                let mut enabled_pids = std::collections::HashSet::new();
-                let mut emit_log_level = None;
-                loop {
-                    match rx.recv().unwrap() {
-                        AsyncMsg::Record(r) => {
-                            if let Some(pid) = r.pid {
-                                if !enabled_pids.contains(&pid) {
-                                    continue;
-                                }
-                            }
-                            // This is a log we want to process, if its level is sufficiently high
-                            if let Some(level) = emit_log_level {
-                                if r.level <= level {
-                                    r.log_to(&drain).unwrap();
-                                }
-                            }
-                        }
-                        AsyncMsg::EnablePID(pid) => {
-                            enabled_pids.insert(pid);
-                        }
-                        AsyncMsg::DisablePID(pid) => {
-                            enabled_pids.remove(&pid);
-                        }
-                        AsyncMsg::LogLevel(level) => {
-                            emit_log_level = Some(level);
-                        }
-                        AsyncMsg::Finish => return,
-                    }
-                }
-            })
-            .unwrap();
+// {{{ Lazy KV
+/// Key-value pair computed only if the record gets written
+///
+/// See `lazy_kv`.
+pub struct LazyKV {
+    key: &'static str,
+    f: Mutex<Option<Box<LazyFn>>>,
+}
 
-        (join, tx)
-    }
+type LazyFn = dyn FnOnce() -> String + Send;
 
-    /// Build `AsyncCore`
-    pub fn build(self) -> AsyncCore {
-        self.build_no_guard()
+/// Create a key-value pair whose value is computed by `f` on the worker
+/// thread, only if the record passes the filtering there.
+///
+/// Use it for values that are expensive to compute:
+///
+/// ```ignore
+/// debug!(log, "request"; slog_async::lazy_kv("dump", move || dump(&req)));
+/// ```
+///
+/// `f` is called at most once: use `lazy_kv` with records rather than in
+/// `o!` logger values, which get serialized again for every record.
+/// Through other drains than `Async` and `AsyncCore` the value is computed
+/// right away.
+pub fn lazy_kv<F>(key: &'static str, f: F) -> LazyKV
+where
+    F: FnOnce() -> String + Send + 'static,
+{
+    LazyKV {
+        key,
+        f: Mutex::new(Some(Box::new(f))),
     }
+}
 
-    /// Build `AsyncCore`
-    pub fn build_no_guard(self) -> AsyncCore {
-        let blocking = self.blocking;
-        let (join, tx) = self.spawn_thread();
+thread_local! {
+    /// Set while `AsyncRecord::from` serializes a record
+    static SENDING: Cell<bool> = const { Cell::new(false) };
+    /// Set while the worker thread looks up a key-value, see
+    /// `AsyncRecord::peek_value`
+    static PEEKING: Cell<bool> = const { Cell::new(false) };
+    /// Hands a `LazyKV` closure over to `ToSendSerializer::emit_unit`
+    static LAZY_VALUE: RefCell<Option<Box<LazyFn>>> =
+        const { RefCell::new(None) };
+    /// Pool senders bound by `AsyncCore::bind_sender`, by pool id
+    static BOUND_SENDERS: RefCell<Vec<(u64, usize)>> =
+        const { RefCell::new(Vec::new()) };
+}
 
-        AsyncCore {
-            ref_sender: tx,
-            tl_sender: thread_local::ThreadLocal::new(),
-            join: Mutex::new(Some(join)),
-            blocking,
+/// Id of the next `AsyncCore` built with `pool_senders`
+static NEXT_POOL_ID: AtomicU64 = AtomicU64::new(0);
+
+impl KV for LazyKV {
+    fn serialize(
+        &self,
+        _record: &Record,
+        serializer: &mut dyn Serializer,
+    ) -> slog::Result {
+        let f = match self.f.lock() {
+            Ok(mut f) => f.take(),
+            Err(_) => None,
+        };
+        match f {
+            Some(f) if SENDING.with(Cell::get) => {
+                LAZY_VALUE.with(|lazy| *lazy.borrow_mut() = Some(f));
+                serializer.emit_unit(static_key(self.key))
+            }
+            Some(f) => serializer.emit_str(static_key(self.key), &f()),
+            None => serializer.emit_none(static_key(self.key)),
         }
     }
+}
 
-    /// Build `AsyncCore` with `AsyncGuard`
-    ///
-    /// See `AsyncGuard` for more information.
-    pub fn build_with_guard(self) -> (AsyncCore, AsyncGuard) {
-        let blocking = self.blocking;
-        let (join, tx) = self.spawn_thread();
+// `Key` is only `&'static str` without the `dynamic-keys` feature
+#[allow(clippy::useless_conversion)]
+fn static_key(key: &'static str) -> Key {
+    key.into()
+}
 
-        (
-            AsyncCore {
-                ref_sender: tx.clone(),
-                tl_sender: thread_local::ThreadLocal::new(),
-                join: Mutex::new(None),
-                blocking,
-            },
-            AsyncGuard {
-                join: Some(join),
-                tx,
-            },
-        )
-    }
+enum Lazy {
+    Pending(Box<LazyFn>),
+    Computed(String),
 }
 
-/// Async guard
+/// `LazyKV` value sent to the worker thread
 ///
-/// All `Drain`s are reference-counted by every `Logger` that uses them.
-/// `Async` drain runs a worker thread and sends a termination (and flushing)
-/// message only when being `drop`ed. Because of that it's actually
-/// quite easy to have a left-over reference to a `Async` drain, when
-/// terminating: especially on `panic`s or similar unwinding event. Typically
-/// it's caused be a leftover reference like `Logger` in thread-local variable,
-/// global variable, or a thread that is not being joined on. It might be a
-/// program bug, but logging should work reliably especially in case of bugs.
-///
-/// `AsyncGuard` is a remedy: it will send a flush and termination message to
-/// a `Async` worker thread, and wait for it to finish on it's own `drop`. Using it
-/// is a simplest way to guarantee log flushing when using `slog_async`.
-pub struct AsyncGuard {
-    // Should always be `Some`. `None` only
-    // after `drop`
-    join: Option<thread::JoinHandle<()>>,
-    tx: Sender<AsyncMsg>,
-}
+/// `None` once the closure panicked.
+struct LazyValue(Mutex<Option<Lazy>>);
 
-impl Drop for AsyncGuard {
-    fn drop(&mut self) {
-        let _err: Result<(), Box<dyn std::error::Error>> = {
-            || {
-                let _ = self.tx.send(AsyncMsg::Finish);
-                let join = self.join.take().unwrap();
-                if join.thread().id() != thread::current().id() {
-                    // See AsyncCore::drop for rationale of this branch.
-                    join.join().map_err(|_| {
-                        io::Error::new(
-                            io::ErrorKind::BrokenPipe,
-                            "Logging thread worker join error",
-                        )
-                    })?;
-                }
-                Ok(())
-            }
-        }();
+impl slog::Value for LazyValue {
+    fn serialize(
+        &self,
+        _record: &Record,
+        key: Key,
+        serializer: &mut dyn Serializer,
+    ) -> slog::Result {
+        if PEEKING.with(Cell::get) {
+            return Ok(());
+        }
+        let mut lazy = match self.0.lock() {
+            Ok(lazy) => lazy,
+            Err(_) => return serializer.emit_none(key),
+        };
+        // The closure runs with the value taken out, so a panic unwinds to
+        // the drain's caller, leaving it `None`
+        let value = match lazy.take() {
+            Some(Lazy::Pending(f)) => f(),
+            Some(Lazy::Computed(value)) => value,
+            None => return serializer.emit_none(key),
+        };
+        let res = serializer.emit_str(key, &value);
+        *lazy = Some(Lazy::Computed(value));
+        res
     }
 }
+// }}}
 
-/// Core of `Async` drain
-///
-/// See `Async` for documentation.
-///
-/// Wrapping `AsyncCore` allows implementing custom overflow (and other errors)
-/// handling strategy.
-///
-/// Note: On drop `AsyncCore` waits for it's worker-thread to finish (after
-/// handling all previous `Record`s sent to it). If you can't tolerate the
-/// delay, make sure you drop it eg. in another thread.
-pub struct AsyncCore {
-    ref_sender: Sender<AsyncMsg>,
-    tl_sender: thread_local::ThreadLocal<Sender<AsyncMsg>>,
-    join: Mutex<Option<thread::JoinHandle<()>>>,
-    blocking: bool,
+// {{{ Async
+// {{{ AsyncError
+/// Errors reported by `Async`
+#[derive(Debug)]
+pub enum AsyncError {
+    /// Could not send record to worker thread due to full queue
+    Full,
+    /// Fatal problem - mutex or channel poisoning issue
+    ///
+    /// Also reported once the worker thread terminated, see
+    /// `AsyncError::is_worker_gone`.
+    Fatal(Box<dyn std::error::Error>),
 }
 
-impl AsyncCore {
-    /// New `AsyncCore` with default parameters
-    pub fn new<D>(drain: D) -> Self
-    where
-        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
-        D: std::panic::RefUnwindSafe,
-    {
-        AsyncCoreBuilder::new(drain).build()
+impl AsyncError {
+    /// The worker thread terminated, eg. after `AsyncGuard` was dropped while
+    /// a `Logger` still referenced the drain: the record was not logged
+    ///
+    /// See `AsyncBuilder::worker_gone_fallback`.
+    pub fn is_worker_gone(&self) -> bool {
+        match *self {
+            AsyncError::Fatal(ref err) => err
+                .downcast_ref::<io::Error>()
+                .and_then(io::Error::get_ref)
+                .is_some_and(|err| err.is::<WorkerGone>()),
+            AsyncError::Full => false,
+        }
     }
 
-    /// Build `AsyncCore` drain with custom parameters
-    pub fn custom<
-        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
-    >(
-        drain: D,
-    ) -> AsyncCoreBuilder<D> {
-        AsyncCoreBuilder::new(drain)
+    fn worker_gone() -> AsyncError {
+        AsyncError::Fatal(Box::new(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            WorkerGone,
+        )))
     }
-    fn get_sender(
-        &self,
-    ) -> Result<
-        &crossbeam_channel::Sender<AsyncMsg>,
-        std::sync::PoisonError<
-            sync::MutexGuard<crossbeam_channel::Sender<AsyncMsg>>,
-        >,
-    > {
-        self.tl_sender.get_or_try(|| Ok(self.ref_sender.clone()))
+}
+
+/// Cause of the error reported once the worker thread terminated
+#[derive(Debug)]
+struct WorkerGone;
+
+impl fmt::Display for WorkerGone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("The logger thread terminated")
     }
+}
 
-    /// Send `AsyncRecord` to a worker thread.
-    fn send(&self, r: AsyncRecord) -> AsyncResult<()> {
-        let sender = self.get_sender()?;
+impl std::error::Error for WorkerGone {}
 
-        if self.blocking {
-            sender.send(AsyncMsg::Record(r))?;
-        } else {
-            sender.try_send(AsyncMsg::Record(r))?;
-        }
+impl<T> From<crossbeam_channel::TrySendError<T>> for AsyncError {
+    fn from(_: crossbeam_channel::TrySendError<T>) -> AsyncError {
+        AsyncError::Full
+    }
+}
 
-        Ok(())
+impl<T> From<crossbeam_channel::SendError<T>> for AsyncError {
+    fn from(_: crossbeam_channel::SendError<T>) -> AsyncError {
+        AsyncError::worker_gone()
     }
 }
 
-impl Drain for AsyncCore {
-    type Ok = ();
-    type Err = AsyncError;
+impl From<crossbeam_channel::RecvError> for AsyncError {
+    fn from(_: crossbeam_channel::RecvError) -> AsyncError {
+        AsyncError::worker_gone()
+    }
+}
 
-    fn log(
-        &self,
-        record: &Record,
-        logger_values: &OwnedKVList,
-    ) -> AsyncResult<()> {
-        self.send(AsyncRecord::from(record, logger_values))
+impl<T> From<std::sync::PoisonError<T>> for AsyncError {
+    fn from(err: std::sync::PoisonError<T>) -> AsyncError {
+        AsyncError::Fatal(Box::new(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            err.to_string(),
+        )))
     }
 }
 
-/// Serialized record.
-pub struct AsyncRecord {
-    msg: String,
-    level: Level,
-    location: Box<slog::RecordLocation>,
-    tag: String,
-    logger_values: OwnedKVList,
-    kv: Box<dyn KV + Send>,
-    pid: Option<usize>,
+/// `AsyncResult` alias
+pub type AsyncResult<T> = std::result::Result<T, AsyncError>;
+
+/// Convert the error of a flush into the one `Drain::flush` reports.
+fn flush_error(err: AsyncError) -> slog::FlushError {
+    let err = match err {
+        AsyncError::Full => io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "slog-async: channel full",
+        ),
+        ref err if err.is_worker_gone() => io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "slog-async: worker thread terminated",
+        ),
+        AsyncError::Fatal(err) => io::Error::other(err.to_string()),
+    };
+    slog::FlushError::Io(err)
 }
 
-impl AsyncRecord {
-    /// Serializes a `Record` and an `OwnedKVList`.
-    pub fn from(record: &Record, logger_values: &OwnedKVList) -> Self {
-        let mut ser = ToSendSerializer::new();
-        record
-            .kv()
-            .serialize(record, &mut ser)
-            .expect("`ToSendSerializer` can't fail");
+/// Convert a worker thread panic payload into an error describing it.
+fn join_error(payload: Box<dyn Any + Send>) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        format!(
+            "Logging thread worker join error: {}",
+            panic_message(&*payload)
+        ),
+    )
+}
 
-        // Search for a PID
-        let mut pid = PidSerializer { pid: None };
-        logger_values
-            .serialize(record, &mut pid)
-            .expect("Cannot fail");
+/// Message of a panic payload, if it's a string.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.as_str()
+    } else {
+        "unknown panic payload"
+    }
+}
 
-        AsyncRecord {
-            msg: fmt::format(*record.msg()),
-            level: record.level(),
-            location: Box::new(*record.location()),
-            tag: String::from(record.tag()),
-            logger_values: logger_values.clone(),
-            pid: pid.pid,
-            kv: ser.finish(),
+/// Minimum time between two repeated diagnostics printed to stderr.
+const DIAGNOSTIC_INTERVAL: Duration = Duration::from_secs(1);
+
+static DIAGNOSTICS: DiagnosticGate = DiagnosticGate::new(DIAGNOSTIC_INTERVAL);
+
+/// Rate limit for diagnostics that can repeat, so a failure storm doesn't
+/// flood stderr.
+struct DiagnosticGate {
+    interval: Duration,
+    // Time of the last diagnostic let through, and number suppressed since
+    state: Mutex<(Option<Instant>, usize)>,
+}
+
+impl DiagnosticGate {
+    const fn new(interval: Duration) -> Self {
+        DiagnosticGate {
+            interval,
+            state: Mutex::new((None, 0)),
         }
     }
 
-    /// Writes the record to a `Drain`.
-    pub fn log_to<D: Drain>(self, drain: &D) -> Result<D::Ok, D::Err> {
-        let rs = RecordStatic {
-            location: &*self.location,
-            level: self.level,
-            tag: &self.tag,
+    /// Whether a diagnostic may be printed at `now`, with the number of the
+    /// ones suppressed since the previous one.
+    fn check(&self, now: Instant) -> Option<usize> {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(e) => e.into_inner(),
         };
+        let (ref mut last, ref mut suppressed) = *state;
+        match *last {
+            Some(last)
+                if now.saturating_duration_since(last) < self.interval =>
+            {
+                *suppressed += 1;
+                None
+            }
+            _ => {
+                *last = Some(now);
+                Some(mem::replace(suppressed, 0))
+            }
+        }
+    }
+}
 
-        drain.log(
-            &Record::new(
-                &rs,
-                &format_args!("{}", self.msg),
-                BorrowedKV(&self.kv),
-            ),
-            &self.logger_values,
-        )
+/// Print a repeatable diagnostic to stderr, at most once per
+/// `DIAGNOSTIC_INTERVAL`.
+fn diagnostic(args: fmt::Arguments) {
+    match DIAGNOSTICS.check(Instant::now()) {
+        Some(0) => eprintln!("slog-async: {}", args),
+        Some(suppressed) => eprintln!(
+            "slog-async: {} ({} earlier diagnostics suppressed)",
+            args, suppressed
+        ),
+        None => {}
     }
+}
 
-    /// Deconstruct this `AsyncRecord` into a record and `OwnedKVList`.
-    pub fn as_record_values(&self, mut f: impl FnMut(&Record, &OwnedKVList)) {
+// }}}
+
+// {{{ AsyncCore
+/// `AsyncCore` builder
+pub struct AsyncCoreBuilder<D>
+where
+    D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+{
+    chan_size: usize,
+    blocking: bool,
+    thread_local_senders: bool,
+    pool_senders: usize,
+    probe_on_build: bool,
+    reserved_capacity: Option<(usize, Level)>,
+    sync_checkpoint_level: Option<Level>,
+    overflow_handler: Option<AssertUnwindSafe<Box<OverflowHandlerFn>>>,
+    overflow_with: Option<AssertUnwindSafe<Box<OverflowWithFn>>>,
+    sync_escape_after: Option<Duration>,
+    measure_send_latency: bool,
+    drain: D,
+    thread_name: Option<String>,
+    error_context: Option<ErrorContext>,
+    slow_serialization: Option<SlowSerialization>,
+    catch_serialization_panics: bool,
+    elapsed_field: Option<&'static str>,
+    queue_time_key: Option<&'static str>,
+    schema_version: Option<u32>,
+    hash_chain: Option<&'static str>,
+    keep_recent: Option<usize>,
+    level_control: Option<Arc<AtomicUsize>>,
+    sampling_seed: Option<u64>,
+    lifecycle_events: Option<Sender<LifecycleEvent>>,
+    value_policy: ValuePolicy,
+    max_kv_pairs: Option<usize>,
+    max_value_len: Option<usize>,
+    logger_values_cache: usize,
+    worker_gone_fallback: Option<Mutex<BoxedDrain>>,
+    full_transitions: FullTransitions,
+    memory_budget: Option<Arc<MemoryBudget>>,
+    batch: Option<Batcher>,
+    chunked: Option<Box<dyn ChunkedDrain>>,
+    output_chunk: usize,
+    #[cfg(feature = "tokio")]
+    stream: Option<tokio::sync::mpsc::UnboundedSender<AsyncRecord>>,
+    sampler: Option<Sampler>,
+    self_report: Option<Duration>,
+    lifo: bool,
+    inter_record_delay: Option<Duration>,
+    strict_order: Option<usize>,
+    max_restarts: Option<u32>,
+    retry_queue: Option<usize>,
+    spawner: Option<Box<SpawnFn>>,
+    on_thread_start: Option<Box<ThreadStartFn>>,
+    transform: Option<Box<TransformFn>>,
+    pid_router: PidRouter,
+}
+
+impl<D> AsyncCoreBuilder<D>
+where
+    D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+{
+    fn new(drain: D) -> Self {
+        AsyncCoreBuilder {
+            chan_size: 128,
+            blocking: false,
+            thread_local_senders: true,
+            pool_senders: 0,
+            reserved_capacity: None,
+            sync_checkpoint_level: None,
+            probe_on_build: false,
+            overflow_handler: None,
+            overflow_with: None,
+            sync_escape_after: None,
+            measure_send_latency: false,
+            drain,
+            thread_name: None,
+            error_context: None,
+            slow_serialization: None,
+            catch_serialization_panics: false,
+            elapsed_field: None,
+            queue_time_key: None,
+            schema_version: None,
+            hash_chain: None,
+            keep_recent: None,
+            level_control: None,
+            sampling_seed: None,
+            lifecycle_events: None,
+            value_policy: ValuePolicy::default(),
+            max_kv_pairs: None,
+            max_value_len: None,
+            logger_values_cache: 0,
+            worker_gone_fallback: None,
+            full_transitions: FullTransitions::default(),
+            memory_budget: None,
+            batch: None,
+            chunked: None,
+            output_chunk: DEFAULT_OUTPUT_CHUNK,
+            #[cfg(feature = "tokio")]
+            stream: None,
+            sampler: None,
+            self_report: None,
+            lifo: false,
+            inter_record_delay: None,
+            strict_order: None,
+            max_restarts: None,
+            retry_queue: None,
+            spawner: None,
+            on_thread_start: None,
+            transform: None,
+            pid_router: PidRouter::new(),
+        }
+    }
+
+    /// Configure a name to be used for the background thread.
+    ///
+    /// The name must not be empty nor contain '\0'. Names longer than
+    /// `MAX_THREAD_NAME_LEN` bytes are cut to that length with a warning on
+    /// stderr, as the OS would otherwise do silently; `try_build` rejects
+    /// them instead.
+    ///
+    /// # Panics
+    ///
+    /// If an empty name, or a name with '\0' is passed.
+    pub fn thread_name(mut self, name: String) -> Self {
+        assert!(!name.is_empty(), "Empty thread name passed");
+        assert!(name.find('\0').is_none(), "Name with \\'0\\' in it passed");
+        self.thread_name = Some(name);
+        self
+    }
+
+    /// Cache a sender per logging thread, enabled by default.
+    ///
+    /// Each thread logging through the drain gets its own clone of the
+    /// channel's sender, kept until the drain is dropped. That spares
+    /// threads from contending on a shared one, but costs memory for every
+    /// thread that ever logged: disable it when threads are created and
+    /// destroyed all the time, eg. with thread pools spawning a thread per
+    /// task. All threads then share a single sender.
+    pub fn thread_local_senders(mut self, enabled: bool) -> Self {
+        self.thread_local_senders = enabled;
+        self
+    }
+
+    /// Write a probe record when building with `try_build`, failing the
+    /// build if the drain panics on it.
+    ///
+    /// Surfaces drains that only fail on their first write, eg. opening a
+    /// bad file path lazily, at startup rather than in the worker thread.
+    /// The probe is a `Trace` record tagged `PROBE_TAG`, written to the
+    /// drain bypassing any filtering; drains that must not see it can skip
+    /// that tag. Only a plain drain is probed, not the sinks of
+    /// `custom_batch`, `custom_chunked` or `custom_stream`. The other build
+    /// methods ignore this.
+    pub fn probe_on_build(mut self, enabled: bool) -> Self {
+        self.probe_on_build = enabled;
+        self
+    }
+
+    /// Clone `count` senders up front, for thread pools to share.
+    ///
+    /// Pool threads call `AsyncCore::bind_sender` with a pool-provided index
+    /// when they start, and then log through the pre-cloned sender of that
+    /// index instead of caching one of their own. Suits pools whose threads
+    /// come and go, where `thread_local_senders` would keep growing, while
+    /// still spreading the threads over several senders.
+    pub fn pool_senders(mut self, count: usize) -> Self {
+        self.pool_senders = count;
+        self
+    }
+
+    /// Use `spawn` to start the worker thread.
+    ///
+    /// `spawn` gets the worker loop and must run it on a new thread, eg.
+    /// through a runtime's own thread management. `thread_name` is ignored
+    /// then, name the thread in `spawn` instead. By default the worker is
+    /// started with `std::thread::Builder`.
+    pub fn spawn_with<F>(mut self, spawn: F) -> Self
+    where
+        F: FnOnce(Box<dyn FnOnce() + Send>) -> thread::JoinHandle<()> + 'static,
+    {
+        self.spawner = Some(Box::new(spawn));
+        self
+    }
+
+    /// Call `f` on the worker thread before it processes any record.
+    ///
+    /// Meant for initialization that has to happen on the worker thread, eg.
+    /// opening the file the drain writes to. Until `f` returns, records are
+    /// queued in the channel and sending blocks instead of failing with
+    /// `AsyncError::Full`, so early records are not lost however long `f`
+    /// takes. See `AsyncCore::wait_ready`. This does not hold for
+    /// `build_manual`, where sending never waits for the worker to start.
+    pub fn on_thread_start<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.on_thread_start = Some(Box::new(f));
+        self
+    }
+
+    /// Pass each record through `f` on the worker thread before writing it.
+    ///
+    /// For generic preprocessing, eg. renaming keys or adding computed
+    /// fields, see the `AsyncRecord` accessors and mutators. `f` returns the
+    /// record to write, or `None` to drop it. Only called for the records
+    /// passing the emitted log level.
+    pub fn transform<F>(mut self, f: F) -> Self
+    where
+        F: Fn(AsyncRecord) -> Option<AsyncRecord> + Send + 'static,
+    {
+        self.transform = Some(Box::new(f));
+        self
+    }
+
+    /// Write the records of some PIDs to their own drains.
+    ///
+    /// See `PidRouter`.
+    pub fn pid_router(mut self, router: PidRouter) -> Self {
+        self.pid_router = router;
+        self
+    }
+
+    /// Set channel size used to send logging records to worker thread. When
+    /// buffer is full `AsyncCore` will start returning `AsyncError::Full` or block, depending on
+    /// the `blocking` configuration.
+    ///
+    /// The channel is allocated upfront, taking over 100 bytes per slot, up
+    /// to `MAX_PREALLOCATED_CHAN_SIZE` slots. Larger channels grow as needed
+    /// instead, so a huge size doesn't allocate gigabytes at construction;
+    /// the size is still enforced by counting the records queued, blocking
+    /// sends waiting for one to be taken by the worker thread.
+    pub fn chan_size(mut self, s: usize) -> Self {
+        self.chan_size = s;
+        self
+    }
+
+    /// Keep the last `slots` slots of the channel for records at or above
+    /// `level`.
+    ///
+    /// Records below `level` are rejected once fewer than `slots` slots are
+    /// free, so eg. audit or error records still find room while the channel
+    /// is busy with debug records. Only applies when not blocking: blocking
+    /// sends wait for any free slot.
+    pub fn reserved_capacity(mut self, slots: usize, level: Level) -> Self {
+        self.reserved_capacity = Some((slots, level));
+        self
+    }
+
+    /// Should the logging call be blocking if the channel is full?
+    ///
+    /// Default is false, in which case it'll return `AsyncError::Full`.
+    pub fn blocking(mut self, blocking: bool) -> Self {
+        self.blocking = blocking;
+        self
+    }
+
+    /// Measure how long serializing each record takes on the logging thread
+    /// and call `report` for records taking longer than `threshold`.
+    ///
+    /// Serialization can't be interrupted, but this helps finding log
+    /// statements with expensive `Display` or `Debug` implementations. Off by
+    /// default, as it adds two clock reads to every logging call.
+    pub fn slow_serialization<F>(
+        mut self,
+        threshold: Duration,
+        report: F,
+    ) -> Self
+    where
+        F: Fn(&Record, Duration) + Send + Sync + 'static,
+    {
+        self.slow_serialization = Some(SlowSerialization {
+            threshold,
+            report: AssertUnwindSafe(Box::new(report)),
+        });
+        self
+    }
+
+    /// Catch panics while serializing records on the logging thread.
+    ///
+    /// A `Display`, `Debug` or `serde` implementation panicking in a logged
+    /// value or message otherwise unwinds the thread that logged it. With
+    /// this enabled, the record is replaced with a `log serialization
+    /// panicked` message instead, keeping its level, location, tag and
+    /// logger values. The panic hook still runs. Off by default.
+    pub fn catch_serialization_panics(mut self, enabled: bool) -> Self {
+        self.catch_serialization_panics = enabled;
+        self
+    }
+
+    /// Add the milliseconds elapsed since the drain was built to every
+    /// record, under `key`.
+    ///
+    /// Gives monotonic relative timing, eg. for tests and short-lived tools,
+    /// without wall-clock timestamps. The time is taken on the logging
+    /// thread, when the record is logged.
+    pub fn elapsed_field(mut self, key: &'static str) -> Self {
+        self.elapsed_field = Some(key);
+        self
+    }
+
+    /// Add the time each record spent queued, in microseconds, under `key`.
+    ///
+    /// Measured from sending the record to the worker thread to writing it
+    /// to the drain, so a backlog shows up directly in the logs. Adds a clock
+    /// read on the logging thread to every logging call.
+    pub fn annotate_queue_time(mut self, key: &'static str) -> Self {
+        self.queue_time_key = Some(key);
+        self
+    }
+
+    /// Add `version` under `SCHEMA_VERSION_KEY` to every record.
+    ///
+    /// Lets downstream consumers of long-lived log pipelines tell formats
+    /// apart as they evolve. Added by the worker thread, to the records
+    /// written to any drain as well as to `self_report` records.
+    pub fn schema_version(mut self, version: u32) -> Self {
+        self.schema_version = Some(version);
+        self
+    }
+
+    /// Limit the chunks handed to a `ChunkedDrain` to `bytes`, header
+    /// included.
+    ///
+    /// Only used by `custom_chunked`. Defaults to `DEFAULT_OUTPUT_CHUNK`.
+    ///
+    /// # Panics
+    ///
+    /// If `bytes` leaves no room for data after the `OutputChunk` header.
+    pub fn max_output_chunk(mut self, bytes: usize) -> Self {
+        assert!(
+            bytes > OutputChunk::HEADER_LEN,
+            "max_output_chunk must exceed the chunk header"
+        );
+        self.output_chunk = bytes;
+        self
+    }
+
+    /// Emit the records up to the level stored in `max_level`.
+    ///
+    /// The worker thread reads `max_level` for every record, so changing the
+    /// verbosity takes a single atomic store, eg. from a signal handler,
+    /// effective for the records processed right after. It replaces the
+    /// emitted log level set through `PIDLogControl` and `ControlSender`,
+    /// which is ignored then.
+    ///
+    /// Levels are encoded as by `Level::as_usize`, from `1` for `Critical`
+    /// to `6` for `Trace`: records at a level whose number is at most the
+    /// stored one are emitted, so `0` emits none and `Level::Trace.as_usize()`
+    /// all of them.
+    pub fn level_control(mut self, max_level: Arc<AtomicUsize>) -> Self {
+        self.level_control = Some(max_level);
+        self
+    }
+
+    /// Seed the generator deciding which records to keep by
+    /// `SAMPLE_RATE_KEY`.
+    ///
+    /// Given the same seed and the same records, the worker thread keeps the
+    /// same ones, to reproduce sampling in tests or while debugging. By
+    /// default the seed is random, so different records are kept in every
+    /// run.
+    pub fn sampling_seed(mut self, seed: u64) -> Self {
+        self.sampling_seed = Some(seed);
+        self
+    }
+
+    /// Add a hash chaining each written record to the previous one under
+    /// `key`.
+    ///
+    /// Makes audit logs tamper-evident: a record removed, inserted or
+    /// modified breaks the chain at that point, see `HashChain` for the
+    /// scheme and for verifying it. The worker thread hashes records as it
+    /// writes them, to whichever drain, `self_report` records included.
+    pub fn hash_chain(mut self, key: &'static str) -> Self {
+        self.hash_chain = Some(key);
+        self
+    }
+
+    /// Keep the last `count` records written, formatted, for `checkpoint`.
+    ///
+    /// Records are kept as written by the worker thread, to whichever
+    /// drain, formatted as their short level name and message followed by
+    /// ` key=value` pairs. `count` is at least 1.
+    pub fn keep_recent(mut self, count: usize) -> Self {
+        self.keep_recent = Some(count.max(1));
+        self
+    }
+
+    /// Wait at least `delay` between writing two records.
+    ///
+    /// Smooths out bursts for a downstream system that doesn't cope with
+    /// them well, at the cost of latency: records queue up meanwhile, and
+    /// are dropped or block according to the overflow strategy once the
+    /// channel is full. The first record after a quiet period is written
+    /// right away.
+    pub fn inter_record_delay(mut self, delay: Duration) -> Self {
+        self.inter_record_delay = Some(delay);
+        self
+    }
+
+    /// Wait for records at or above `level` to be written before returning
+    /// from logging them.
+    ///
+    /// Makes the most severe records durable: once `log` returns, the record
+    /// and everything the worker got before it were written to the drain,
+    /// pending batches included, as with `flush`. Such records wait for room
+    /// in the channel rather than being dropped when it is full. Records
+    /// below `level` are not affected.
+    pub fn sync_checkpoint_level(mut self, level: Level) -> Self {
+        self.sync_checkpoint_level = Some(level);
+        self
+    }
+
+    /// Write records in the order they were logged in, across all threads.
+    ///
+    /// Records are numbered when logged, before serialization, and the
+    /// worker thread holds back records arriving ahead of their turn in a
+    /// buffer of up to `buffer_size` records. Without it, records logged
+    /// concurrently can reach the channel in a different order than their
+    /// `log` calls started in.
+    ///
+    /// This adds latency: a record waits for all the records logged before
+    /// it. A record that never arrives, eg. dropped on overflow, holds back
+    /// the following ones until the buffer is full, the drain is flushed or
+    /// a `sync_checkpoint_level` record is held back, which releases the
+    /// buffer so its `log` call returns. Records logged by the drain itself
+    /// and reports of `Async` are not numbered and written right away. Has
+    /// no effect with `lifo`.
+    pub fn strict_order(mut self, buffer_size: usize) -> Self {
+        self.strict_order = Some(buffer_size);
+        self
+    }
+
+    /// Experimental: write the queued records newest first.
+    ///
+    /// For forensic dumps only, eg. a crash handler draining a large backlog
+    /// where the most recent context matters most: every time the worker
+    /// thread picks up a record, the records queued behind it are written in
+    /// reverse order. This breaks the ordering of the log stream, don't use
+    /// it for normal logging. Other messages, like `flush`, keep their place
+    /// relative to the records.
+    pub fn lifo(mut self, lifo: bool) -> Self {
+        self.lifo = lifo;
+        self
+    }
+
+    /// Send `LifecycleEvent`s of the worker thread to `events`.
+    ///
+    /// Lets a supervisor react to the logger starting, stopping or getting
+    /// degraded, separately from the log stream. Events are sent without
+    /// blocking the worker: they are lost if `events` is full.
+    pub fn lifecycle_events(mut self, events: Sender<LifecycleEvent>) -> Self {
+        self.lifecycle_events = Some(events);
+        self
+    }
+
+    /// Set how record key-values are kept when serialized for the worker
+    /// thread. Default is `ValuePolicy::KeepTyped`.
+    pub fn value_policy(mut self, policy: ValuePolicy) -> Self {
+        self.value_policy = policy;
+        self
+    }
+
+    /// Keep at most `max` key-values of each record.
+    ///
+    /// Bounds the work spent on records with excessive numbers of fields:
+    /// serialized key-values form a nested chain the drain walks
+    /// recursively, so a huge one is slow and can overflow the worker
+    /// thread's stack. The number of key-values left out is added to
+    /// truncated records under `KV_TRUNCATED_KEY`. Logger values aren't
+    /// limited.
+    pub fn max_kv_pairs(mut self, max: usize) -> Self {
+        self.max_kv_pairs = Some(max);
+        self
+    }
+
+    /// Cut string and formatted record values to at most `max` bytes.
+    ///
+    /// Bounds the memory and output taken by a single oversized value, eg.
+    /// a blob logged by mistake, while keeping the rest of the record. Cut
+    /// values end with `VALUE_TRUNCATED_MARKER`, which isn't counted in
+    /// `max`. Typed values, eg. numbers, and logger values aren't limited.
+    pub fn max_value_len(mut self, max: usize) -> Self {
+        self.max_value_len = Some(max);
+        self
+    }
+
+    /// Reuse the serialized logger values of up to `capacity` loggers.
+    ///
+    /// Records are logged as if with `AsyncCore::log_with_handle`, with a
+    /// handle looked up by the identity of the logger values: clones of a
+    /// `Logger` share it, child loggers don't. Once `capacity` is reached the
+    /// least recently used handle is evicted. The values are serialized with
+    /// a placeholder record, see `AsyncCore::precompute_logger_values`.
+    /// Values of loggers used as drains are chained anew for every record
+    /// and never found in the cache. 0, the default, disables the cache.
+    pub fn logger_values_cache(mut self, capacity: usize) -> Self {
+        self.logger_values_cache = capacity;
+        self
+    }
+
+    /// Write records logged after the worker thread terminated to `drain`.
+    ///
+    /// Once the worker is gone, eg. because `AsyncGuard` was dropped while a
+    /// `Logger` clone is still around, records are rejected with an error
+    /// for which `AsyncError::is_worker_gone` holds. With a fallback, they
+    /// are written to it instead, synchronously in the logging thread.
+    pub fn worker_gone_fallback<F>(mut self, drain: F) -> Self
+    where
+        F: Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        self.worker_gone_fallback = Some(Mutex::new(Box::new(drain)));
+        self
+    }
+
+    /// Call `f` whenever the channel becomes full.
+    ///
+    /// Only called once per transition: after the channel got full, `f` isn't
+    /// called again before it has recovered (see `on_recovered`). Fullness is
+    /// detected on non-blocking sends only.
+    pub fn on_full<F>(mut self, f: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.full_transitions.on_full = Some(AssertUnwindSafe(Box::new(f)));
+        self
+    }
+
+    /// Let `handler` decide what happens to each record finding the channel
+    /// full.
+    ///
+    /// Called on the logging thread, instead of rejecting the record with
+    /// `AsyncError::Full`:
+    ///
+    /// * `OverflowDecision::Drop` rejects it as usual, so `Async` drops it
+    ///   according to its `OverflowStrategy`,
+    /// * `OverflowDecision::Block` waits for room in the channel,
+    /// * `OverflowDecision::Redirect` writes it to another drain, right away
+    ///   on the logging thread.
+    ///
+    /// Records are only checked against a full channel on non-blocking
+    /// sends, so the handler is not called with `OverflowStrategy::Block`,
+    /// nor for records rejected by `memory_budget`.
+    pub fn overflow_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&AsyncRecord) -> OverflowDecision + Send + Sync + 'static,
+    {
+        self.overflow_handler = Some(AssertUnwindSafe(Box::new(handler)));
+        self
+    }
+
+    /// Hand each record finding the channel full to `f` instead of
+    /// rejecting it.
+    ///
+    /// Called on the logging thread with the serialized record, eg. to write
+    /// it synchronously to a secondary drain with `AsyncRecord::log_to`.
+    /// Records sent while there's room in the channel are unaffected. With
+    /// an `overflow_handler`, only records it decides to drop are handed to
+    /// `f`. As with `overflow_handler`, `f` is not called with
+    /// `OverflowStrategy::Block`, nor for records rejected by
+    /// `memory_budget`.
+    pub fn overflow_with<F>(mut self, f: F) -> Self
+    where
+        F: Fn(AsyncRecord) + Send + Sync + 'static,
+    {
+        self.overflow_with = Some(AssertUnwindSafe(Box::new(f)));
+        self
+    }
+
+    /// Wait for room in the channel instead of rejecting records, once it
+    /// stayed full for `duration`.
+    ///
+    /// A last resort against losing records under sustained overload: past
+    /// `duration`, each record finding the channel full is sent blocking and
+    /// the logging thread waits until it is written, as with
+    /// `sync_checkpoint_level`. Logging is slowed down to the pace of the
+    /// drain until a record finds room again. Takes precedence over
+    /// `overflow_handler`.
+    pub fn sync_escape_after(mut self, duration: Duration) -> Self {
+        self.sync_escape_after = Some(duration);
+        self
+    }
+
+    /// Measure the time sending each record to the channel takes.
+    ///
+    /// Tells apart logging slowed down by contention on the channel from
+    /// logging slowed down by a worker thread falling behind, see
+    /// `AsyncCore::send_latency_percentiles`. Disabled by default, as it
+    /// adds two clock reads to every logging call.
+    pub fn measure_send_latency(mut self, enabled: bool) -> Self {
+        self.measure_send_latency = enabled;
+        self
+    }
+
+    /// Call `f` whenever a record is sent again after the channel was full.
+    pub fn on_recovered<F>(mut self, f: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.full_transitions.on_recovered =
+            Some(AssertUnwindSafe(Box::new(f)));
+        self
+    }
+
+    /// Keep the last `ring_size` records and write them to `context_drain`
+    /// whenever a record at or above `threshold` is processed.
+    ///
+    /// Records are kept regardless of the emitted log level, so
+    /// `context_drain` gets the verbose context leading up to an error while
+    /// the main drain stays terse. The triggering record is written to
+    /// `context_drain` right after its context. A panic of `context_drain`
+    /// is handled like one of the main drain, see `max_restarts`.
+    pub fn context_on_error<C>(
+        mut self,
+        threshold: Level,
+        ring_size: usize,
+        context_drain: C,
+    ) -> Self
+    where
+        C: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        self.error_context = Some(ErrorContext {
+            threshold,
+            ring_size,
+            ring: VecDeque::with_capacity(ring_size),
+            drain: Box::new(context_drain),
+        });
+        self
+    }
+
+    /// Keep the worker thread going when the drain panics, up to `max` times.
+    ///
+    /// The record being written when the drain panicked is lost, which is
+    /// reported on stderr, at most once per second. Panics of the
+    /// `context_on_error` drain count too. After `max + 1` panics, the
+    /// worker thread gives up on the drains: async logging is disabled for
+    /// good, with a message printed to stderr, and records are discarded
+    /// from then on. See `AsyncCore::is_degraded`.
+    ///
+    /// By default a panicking drain ends the worker thread.
+    pub fn max_restarts(mut self, max: u32) -> Self {
+        self.max_restarts = Some(max);
+        self
+    }
+
+    /// Keep up to `capacity` records the drain failed to write, and write
+    /// them again once it recovers.
+    ///
+    /// Meant for flaky sinks, eg. a network drain. A write fails when the
+    /// drain returns an error, for drains wrapped in `RetryOnErr`, eg. by
+    /// `AsyncCore::custom_retrying`, or when it panics, eg. a drain wrapped
+    /// in `Drain::fuse`. The record is queued instead of lost, and the
+    /// worker thread doesn't end. Queued records are retried, oldest first,
+    /// before writing the next record and on `AsyncCore::flush`: the next
+    /// record is queued too as long as any of them still fails, so the drain
+    /// gets records in order. Once `capacity` records are queued, the oldest
+    /// one is dropped, which is reported on stderr, at most once per second.
+    ///
+    /// Only the drain passed to the builder is covered, not the ones of
+    /// `register_sink` or `PidRouter`. Failed writes still count towards
+    /// `max_restarts` if set. `capacity` is at least 1.
+    pub fn retry_queue(mut self, capacity: usize) -> Self {
+        self.retry_queue = Some(capacity.max(1));
+        self
+    }
+
+    /// Write only the first record for each value of `key` within `window`.
+    ///
+    /// Meant for high-cardinality events, eg. logging an error once per user
+    /// id. Both the record's and the logger's key-values are searched, but
+    /// not `lazy_kv` values, and records without `key` are not sampled. For
+    /// every value with repeats suppressed, a summary record at the level of
+    /// the first one is written once the window is over, with the message
+    /// `slog-async: suppressed repeated records`, tagged `slog-async` and
+    /// carrying:
+    ///
+    /// * `sampled_key` - `key`, a string,
+    /// * `sampled_value` - the value of `key`, formatted,
+    /// * `suppressed` - the number of records suppressed in the window, an
+    ///   unsigned integer,
+    /// * `sample_msg` - the message of the first suppressed record,
+    /// * `first_suppressed` and `last_suppressed` - when the worker thread
+    ///   got the first and the last suppressed record, in milliseconds since
+    ///   the Unix epoch, unsigned integers.
+    ///
+    /// Logger key-values of the suppressed records are not carried over.
+    pub fn sample_by_key(
+        mut self,
+        key: &'static str,
+        window: Duration,
+    ) -> Self {
+        self.sampler = Some(Sampler::new(key, window));
+        self
+    }
+
+    /// Write a record with the state of the channel every `interval`.
+    ///
+    /// For operators who can't scrape metrics: the worker thread reports its
+    /// health in-band, with a record at `Info` tagged `slog-async-stats`,
+    /// written regardless of the emitted log level and carrying:
+    ///
+    /// * `depth` - the number of messages waiting in the channel,
+    /// * `capacity` - the channel size,
+    /// * `utilization` - `depth` divided by `capacity`,
+    /// * `dropped` - the number of records rejected because the channel was
+    ///   full since the previous report,
+    /// * `processed` - the number of records written so far.
+    pub fn self_report(mut self, interval: Duration) -> Self {
+        self.self_report = Some(interval);
+        self
+    }
+
+    /// Limit the memory used by records waiting for the worker thread to
+    /// `bytes`.
+    ///
+    /// The budget covers, using an estimate of their size, the records
+    /// queued in the main and the control channel, eg. drop reports, as well
+    /// as the ones the worker thread holds on to: kept for
+    /// `context_on_error`, waiting in the `retry_queue`, in a pending batch
+    /// or held back by `strict_order`.
+    ///
+    /// It's enforced when a record is sent: the worker thread is asked to
+    /// make room for a record that doesn't fit, which is rejected with
+    /// `AsyncError::Full`, or when `blocking` waits until it fits. Only a
+    /// record larger than the whole budget is rejected even when `blocking`,
+    /// so the budget should leave room for the largest ones. It evicts the
+    /// oldest records kept for `context_on_error`, then the oldest ones in
+    /// the `retry_queue`, and if that's not enough writes the pending batch
+    /// and the records held back by `strict_order` early. A drop report that
+    /// doesn't fit is sent along with the next one instead.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(Arc::new(MemoryBudget::new(bytes)));
+        self
+    }
+
+    /// Keep track of the memory used by records waiting for the worker
+    /// thread, see `AsyncCore::estimated_memory_bytes`.
+    ///
+    /// Always done with a `memory_budget`. Disabled by default otherwise, as
+    /// it adds updates of a counter shared by all logging threads to every
+    /// logging call.
+    pub fn track_memory(mut self, enabled: bool) -> Self {
+        let untracked = self.memory_budget.is_none();
+        let unbounded = matches!(
+            self.memory_budget,
+            Some(ref budget) if budget.limit == usize::MAX
+        );
+        if enabled && untracked {
+            self.memory_budget = Some(Arc::new(MemoryBudget::new(usize::MAX)));
+        } else if !enabled && unbounded {
+            self.memory_budget = None;
+        }
+        self
+    }
+
+    /// Set up the worker, returning its loop to be run on a thread.
+    fn worker(self) -> PreparedWorker {
+        let (tx, rx) = if self.chan_size > MAX_PREALLOCATED_CHAN_SIZE {
+            crossbeam_channel::unbounded()
+        } else {
+            crossbeam_channel::bounded(self.chan_size)
+        };
+        let (ctrl_tx, ctrl_rx) = crossbeam_channel::unbounded();
+        let stats = Arc::new(WorkerStats::default());
+        stats.running.store(true, Ordering::Relaxed);
+        let sink = match (self.batch, self.chunked) {
+            (Some(batcher), _) => Sink::Batch(batcher),
+            (None, Some(drain)) => {
+                Sink::Chunked(Chunker::new(drain, self.output_chunk))
+            }
+            (None, None) => Sink::Drain(Box::new(self.drain)),
+        };
+        #[cfg(feature = "tokio")]
+        let sink = match self.stream {
+            Some(tx) => Sink::Stream(tx),
+            None => sink,
+        };
+        let mut worker = Worker::new(
+            sink,
+            self.error_context,
+            self.sampler,
+            self.memory_budget,
+            stats.clone(),
+        );
+        worker.max_restarts = self.max_restarts;
+        worker.retry = self.retry_queue.map(RetryQueue::new);
+        worker.on_thread_start = self.on_thread_start;
+        worker.transform = self.transform;
+        worker.pid_routes = self.pid_router.routes;
+        worker.queue_time_key = self.queue_time_key;
+        worker.schema_version = self.schema_version;
+        worker.hash_chain = self.hash_chain.map(HashChain::new);
+        worker.recent = self.keep_recent.map(RecentRecords::new);
+        worker.level_control = self.level_control;
+        if let Some(seed) = self.sampling_seed {
+            // xorshift gets stuck on 0
+            worker.rng = if seed == 0 {
+                0x9e37_79b9_7f4a_7c15
+            } else {
+                seed
+            };
+        }
+        worker.lifecycle_events = self.lifecycle_events;
+        worker.lifo = self.lifo;
+        worker.inter_record_delay = self.inter_record_delay;
+        worker.reorder = self.strict_order.map(Reorder::new);
+        let chan_size = self.chan_size;
+        worker.self_report = self
+            .self_report
+            .map(|interval| SelfReport::new(interval, chan_size));
+        let worker_thread = Arc::new(OnceLock::new());
+        let run = {
+            let worker_thread = worker_thread.clone();
+            Box::new(move || {
+                let _ = worker_thread.set(thread::current().id());
+                worker.run(rx, ctrl_rx)
+            })
+        };
+
+        (run, tx, ctrl_tx, stats, worker_thread)
+    }
+
+    /// Build `AsyncCore`
+    pub fn build(self) -> AsyncCore {
+        self.build_no_guard()
+    }
+
+    /// Build `AsyncCore`
+    pub fn build_no_guard(self) -> AsyncCore {
+        let (mut core, join) = self.build_core();
+        core.join = Mutex::new(Some(join));
+        core
+    }
+
+    /// Build `AsyncCore`, failing if the drain panics on the probe record
+    /// of `probe_on_build`
+    ///
+    /// Returns `AsyncError::Fatal` with the panic message then, as well as
+    /// for a `thread_name` longer than `MAX_THREAD_NAME_LEN` bytes.
+    pub fn try_build(self) -> AsyncResult<AsyncCore> {
+        if let Some(ref name) = self.thread_name {
+            if name.len() > MAX_THREAD_NAME_LEN {
+                return Err(AsyncError::Fatal(
+                    format!(
+                        "thread name {:?} longer than {} bytes",
+                        name, MAX_THREAD_NAME_LEN
+                    )
+                    .into(),
+                ));
+            }
+        }
+        let probe = self.probe_on_build;
+        let core = self.build_no_guard();
+        if probe {
+            core.probe()?;
+        }
+        Ok(core)
+    }
+
+    /// Build `AsyncCore` with `AsyncGuard`
+    ///
+    /// See `AsyncGuard` for more information.
+    pub fn build_with_guard(self) -> (AsyncCore, AsyncGuard) {
+        let (core, join) = self.build_core();
+        let tx = core.ref_sender.clone();
+
+        (
+            core,
+            AsyncGuard {
+                join: Some(join),
+                tx,
+            },
+        )
+    }
+
+    /// Build `AsyncCore` with an `AsyncWorker`, to run the worker loop on a
+    /// thread of the caller's own.
+    ///
+    /// No thread is spawned: `spawn_with` and `thread_name` are ignored.
+    /// Records are queued in the channel until `AsyncWorker::run` is called.
+    /// Unlike a spawned worker, sending does not wait for `run` or the
+    /// `on_thread_start` hook: once the channel is full, the overflow
+    /// strategy applies.
+    pub fn build_manual(self) -> (AsyncCore, AsyncWorker) {
+        let (mut core, run) = self.build_unstarted();
+        core.manual = true;
+        (core, AsyncWorker { run })
+    }
+
+    /// Spawn the worker thread and build an `AsyncCore` not joining on it.
+    fn build_core(mut self) -> (AsyncCore, thread::JoinHandle<()>) {
+        let spawner = self.spawner.take();
+        let thread_name = self.thread_name.take();
+        let (core, run) = self.build_unstarted();
+        let join = match spawner {
+            Some(spawn) => spawn(run),
+            None => {
+                let mut builder = thread::Builder::new();
+                if let Some(mut thread_name) = thread_name {
+                    if thread_name.len() > MAX_THREAD_NAME_LEN {
+                        let len = truncated_len(&thread_name);
+                        diagnostic(format_args!(
+                            "thread name {:?} cut to {:?}",
+                            thread_name,
+                            &thread_name[..len]
+                        ));
+                        thread_name.truncate(len);
+                    }
+                    builder = builder.name(thread_name);
+                }
+                builder.spawn(run).unwrap()
+            }
+        };
+        (core, join)
+    }
+
+    /// Build an `AsyncCore`, returning the worker loop to run.
+    fn build_unstarted(mut self) -> (AsyncCore, Box<dyn FnOnce() + Send>) {
+        let blocking = self.blocking;
+        let tl_sender = if self.thread_local_senders {
+            Some(thread_local::ThreadLocal::new())
+        } else {
+            None
+        };
+        let pool_senders = self.pool_senders;
+        let chan_size = self.chan_size;
+        let chan_slots = if chan_size > MAX_PREALLOCATED_CHAN_SIZE {
+            Some(Arc::new(ChanSlots::new(chan_size)))
+        } else {
+            None
+        };
+        let reserved_capacity = self.reserved_capacity;
+        let sync_checkpoint_level = self.sync_checkpoint_level;
+        let overflow_handler = self.overflow_handler.take();
+        let overflow_with = self.overflow_with.take();
+        let sync_escape = self.sync_escape_after.map(SyncEscape::new);
+        let send_latency = if self.measure_send_latency {
+            Some(LatencyHistogram::default())
+        } else {
+            None
+        };
+        let slow_serialization = self.slow_serialization.take();
+        let catch_serialization_panics = self.catch_serialization_panics;
+        let elapsed_field = self.elapsed_field.map(|key| (key, Instant::now()));
+        let value_policy = self.value_policy;
+        let max_kv_pairs = self.max_kv_pairs;
+        let max_value_len = self.max_value_len;
+        let logger_values_cache = match self.logger_values_cache {
+            0 => None,
+            capacity => Some(Mutex::new(LoggerValuesCache::new(capacity))),
+        };
+        let worker_gone_fallback = self.worker_gone_fallback.take();
+        let queue_time = self.queue_time_key.is_some();
+        let sequence = self.strict_order.map(|_| AtomicU64::new(0));
+        let full_transitions = mem::take(&mut self.full_transitions);
+        let memory_budget = self.memory_budget.clone();
+        let (run, tx, ctrl_tx, stats, worker_thread) = self.worker();
+        #[cfg(feature = "registry")]
+        let registration = registry::register(&tx, &ctrl_tx, &worker_thread);
+        let pool_senders = (0..pool_senders).map(|_| tx.clone()).collect();
+        let pool_id = NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed);
+
+        (
+            AsyncCore {
+                ref_sender: tx,
+                ctrl_sender: ctrl_tx,
+                worker_thread,
+                stats,
+                tl_sender,
+                pool_senders,
+                pool_id,
+                join: Mutex::new(None),
+                manual: false,
+                chan_size,
+                chan_slots,
+                blocking,
+                reserved_capacity,
+                sync_checkpoint_level,
+                overflow_handler,
+                overflow_with,
+                sync_escape,
+                send_latency,
+                slow_serialization,
+                catch_serialization_panics,
+                elapsed_field,
+                value_policy,
+                max_kv_pairs,
+                max_value_len,
+                logger_values_cache,
+                worker_gone_fallback,
+                fallback_count: AtomicU64::new(0),
+                fallback_nanos: AtomicU64::new(0),
+                queue_time,
+                sequence,
+                full_transitions,
+                memory_budget,
+                #[cfg(feature = "registry")]
+                _registration: registration,
+            },
+            run,
+        )
+    }
+}
+
+/// Channel size of `Async::production`.
+pub const PRODUCTION_CHAN_SIZE: usize = 4096;
+
+/// Largest channel size allocated upfront, see `AsyncCoreBuilder::chan_size`.
+pub const MAX_PREALLOCATED_CHAN_SIZE: usize = 1 << 16;
+
+/// Default `max_output_chunk`: the largest UDP payload over IPv4
+pub const DEFAULT_OUTPUT_CHUNK: usize = 65_507;
+
+/// Longest `thread_name` kept whole, in bytes: the limit of Linux.
+pub const MAX_THREAD_NAME_LEN: usize = 15;
+
+/// Length of the longest prefix of `name` fitting `MAX_THREAD_NAME_LEN`,
+/// without splitting a character.
+fn truncated_len(name: &str) -> usize {
+    let mut len = MAX_THREAD_NAME_LEN.min(name.len());
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
+
+/// Interval at which callers waiting for a `ChanSlot` check that the worker
+/// thread is still running.
+const CHAN_SLOTS_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Interval at which blocked callers check whether their record fits in the
+/// memory budget, see `AsyncCoreBuilder::memory_budget`.
+const MEMORY_BUDGET_POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+type SpawnFn = dyn FnOnce(Box<dyn FnOnce() + Send>) -> thread::JoinHandle<()>;
+
+type ThreadStartFn = dyn FnOnce() + Send;
+
+type TransformFn = dyn Fn(AsyncRecord) -> Option<AsyncRecord> + Send;
+
+type OverflowHandlerFn = dyn Fn(&AsyncRecord) -> OverflowDecision + Send + Sync;
+
+type OverflowWithFn = dyn Fn(AsyncRecord) + Send + Sync;
+
+type OnFullFn<'a> = dyn Fn(AsyncRecord) -> AsyncResult<()> + 'a;
+
+type PreparedWorker = (
+    Box<dyn FnOnce() + Send>,
+    Sender<AsyncMsg>,
+    Sender<AsyncMsg>,
+    Arc<WorkerStats>,
+    Arc<OnceLock<thread::ThreadId>>,
+);
+
+/// Worker loop of an `AsyncCore` built with
+/// `AsyncCoreBuilder::build_manual`, to be run on a thread of the caller's
+/// own
+///
+/// Lets applications pick the thread processing the records, eg. to set its
+/// name, priority or CPU affinity themselves, or dedicate one they already
+/// have.
+pub struct AsyncWorker {
+    run: Box<dyn FnOnce() + Send>,
+}
+
+impl AsyncWorker {
+    /// Run the worker loop on the current thread.
+    ///
+    /// Returns once the `AsyncCore`, or `Async`, is dropped and all the
+    /// records sent before were written. Dropping the `AsyncCore` doesn't
+    /// wait for the loop to finish, join the thread calling `run` for that.
+    pub fn run(self) {
+        (self.run)()
+    }
+}
+
+/// Async guard
+///
+/// All `Drain`s are reference-counted by every `Logger` that uses them.
+/// `Async` drain runs a worker thread and sends a termination (and flushing)
+/// message only when being `drop`ed. Because of that it's actually
+/// quite easy to have a left-over reference to a `Async` drain, when
+/// terminating: especially on `panic`s or similar unwinding event. Typically
+/// it's caused be a leftover reference like `Logger` in thread-local variable,
+/// global variable, or a thread that is not being joined on. It might be a
+/// program bug, but logging should work reliably especially in case of bugs.
+///
+/// `AsyncGuard` is a remedy: it will send a flush and termination message to
+/// a `Async` worker thread, and wait for it to finish on it's own `drop`. Using it
+/// is a simplest way to guarantee log flushing when using `slog_async`.
+///
+/// Records logged through leftover references once the guard is dropped are
+/// rejected, see `AsyncError::is_worker_gone`, or written to the
+/// `worker_gone_fallback` drain if one was set.
+pub struct AsyncGuard {
+    // Should always be `Some`. `None` only
+    // after `drop`
+    join: Option<thread::JoinHandle<()>>,
+    tx: Sender<AsyncMsg>,
+}
+
+impl Drop for AsyncGuard {
+    fn drop(&mut self) {
+        let _err: Result<(), Box<dyn std::error::Error>> = {
+            || {
+                let _ = self.tx.send(AsyncMsg::Finish);
+                let join = self.join.take().unwrap();
+                if join.thread().id() != thread::current().id() {
+                    // See AsyncCore::drop for rationale of this branch.
+                    join.join().map_err(join_error)?;
+                }
+                Ok(())
+            }
+        }();
+    }
+}
+
+/// Core of `Async` drain
+///
+/// See `Async` for documentation.
+///
+/// Wrapping `AsyncCore` allows implementing custom overflow (and other errors)
+/// handling strategy.
+///
+/// Note: On drop `AsyncCore` waits for it's worker-thread to finish (after
+/// handling all previous `Record`s sent to it). If you can't tolerate the
+/// delay, make sure you drop it eg. in another thread.
+pub struct AsyncCore {
+    ref_sender: Sender<AsyncMsg>,
+    // `None` if `thread_local_senders` is disabled
+    tl_sender: Option<thread_local::ThreadLocal<Sender<AsyncMsg>>>,
+    // Shared by pool threads, see `pool_senders`
+    pool_senders: Vec<Sender<AsyncMsg>>,
+    // Tells this core's entries in `BOUND_SENDERS` apart
+    pool_id: u64,
+    // Unbounded, read by the worker thread ahead of `ref_sender`
+    ctrl_sender: Sender<AsyncMsg>,
+    // Set by the worker thread once it runs
+    worker_thread: Arc<OnceLock<thread::ThreadId>>,
+    stats: Arc<WorkerStats>,
+    join: Mutex<Option<thread::JoinHandle<()>>>,
+    // Built with `build_manual`: no thread to join, but the worker loop still
+    // has to be told to finish
+    manual: bool,
+    chan_size: usize,
+    // Bound of a channel too large to be allocated upfront
+    chan_slots: Option<Arc<ChanSlots>>,
+    blocking: bool,
+    // Slots only usable by records at or above a level
+    reserved_capacity: Option<(usize, Level)>,
+    sync_checkpoint_level: Option<Level>,
+    overflow_handler: Option<AssertUnwindSafe<Box<OverflowHandlerFn>>>,
+    overflow_with: Option<AssertUnwindSafe<Box<OverflowWithFn>>>,
+    sync_escape: Option<SyncEscape>,
+    send_latency: Option<LatencyHistogram>,
+    slow_serialization: Option<SlowSerialization>,
+    catch_serialization_panics: bool,
+    // Key and start of the field added by `elapsed_field`
+    elapsed_field: Option<(&'static str, Instant)>,
+    value_policy: ValuePolicy,
+    max_kv_pairs: Option<usize>,
+    max_value_len: Option<usize>,
+    logger_values_cache: Option<Mutex<LoggerValuesCache>>,
+    worker_gone_fallback: Option<Mutex<BoxedDrain>>,
+    // Records written to `worker_gone_fallback`, and the time it took
+    fallback_count: AtomicU64,
+    fallback_nanos: AtomicU64,
+    // Whether records are timestamped for `annotate_queue_time`
+    queue_time: bool,
+    // Number of the next record logged, see `strict_order`
+    sequence: Option<AtomicU64>,
+    full_transitions: FullTransitions,
+    memory_budget: Option<Arc<MemoryBudget>>,
+    // Keeps this core in the registry until dropped
+    #[cfg(feature = "registry")]
+    _registration: Arc<registry::Registration>,
+}
+
+/// Reports records that took longer than `threshold` to serialize.
+struct SlowSerialization {
+    threshold: Duration,
+    report: AssertUnwindSafe<Box<SlowSerializationFn>>,
+}
+
+type SlowSerializationFn = dyn Fn(&Record, Duration) + Send + Sync;
+
+/// Edge-triggered callbacks for the channel becoming full and recovering.
+#[derive(Default)]
+struct FullTransitions {
+    full: AtomicBool,
+    on_full: Option<AssertUnwindSafe<Box<dyn Fn() + Send + Sync>>>,
+    on_recovered: Option<AssertUnwindSafe<Box<dyn Fn() + Send + Sync>>>,
+}
+
+impl FullTransitions {
+    fn full(&self) {
+        if !self.full.swap(true, Ordering::Relaxed) {
+            if let Some(ref on_full) = self.on_full {
+                (on_full.0)();
+            }
+        }
+    }
+
+    fn sent(&self) {
+        if self.full.load(Ordering::Relaxed)
+            && self.full.swap(false, Ordering::Relaxed)
+        {
+            if let Some(ref on_recovered) = self.on_recovered {
+                (on_recovered.0)();
+            }
+        }
+    }
+}
+
+/// Percentiles of the time sending records to the channel took, see
+/// `AsyncCore::send_latency_percentiles`
+///
+/// Latencies are counted in buckets of powers of two nanoseconds, so each
+/// percentile is rounded up to the next power of two: within a factor of two
+/// of the exact value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendLatencyPercentiles {
+    /// Number of sends measured
+    pub samples: u64,
+    /// Median latency
+    pub p50: Duration,
+    /// 90th percentile
+    pub p90: Duration,
+    /// 99th percentile
+    pub p99: Duration,
+    /// Highest latency
+    pub max: Duration,
+}
+
+/// Histogram of durations, in buckets of powers of two nanoseconds
+struct LatencyHistogram {
+    // Bucket `i` counts durations of at most `2^i` nanoseconds, above the
+    // previous bucket
+    buckets: [AtomicU64; 64],
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    fn record(&self, d: Duration) {
+        let nanos = duration_nanos(d);
+        let bucket = if nanos <= 1 {
+            0
+        } else {
+            (64 - (nanos - 1).leading_zeros() as usize).min(63)
+        };
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn percentiles(&self) -> SendLatencyPercentiles {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        let samples: u64 = counts.iter().sum();
+        // Upper bound of the bucket holding the `rank`th smallest duration
+        let at_rank = |rank: u64| {
+            let mut seen = 0;
+            for (bucket, count) in counts.iter().enumerate() {
+                seen += count;
+                if seen >= rank.max(1) {
+                    return Duration::from_nanos(1 << bucket);
+                }
+            }
+            Duration::from_nanos(0)
+        };
+        let percentile = |p: u64| at_rank((samples * p).div_ceil(100));
+        SendLatencyPercentiles {
+            samples,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: at_rank(samples),
+        }
+    }
+}
+
+/// Time the channel has been full for, see
+/// `AsyncCoreBuilder::sync_escape_after`
+struct SyncEscape {
+    after: Duration,
+    start: Instant,
+    // Nanoseconds from `start` to when a record first found the channel
+    // full, or `NOT_FULL`
+    full_since: AtomicU64,
+}
+
+const NOT_FULL: u64 = u64::MAX;
+
+impl SyncEscape {
+    fn new(after: Duration) -> Self {
+        SyncEscape {
+            after,
+            start: Instant::now(),
+            full_since: AtomicU64::new(NOT_FULL),
+        }
+    }
+
+    /// Whether the channel, found full by a record, stayed full for `after`.
+    fn due(&self) -> bool {
+        let now = duration_nanos(self.start.elapsed());
+        match self.full_since.compare_exchange(
+            NOT_FULL,
+            now,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => false,
+            Err(since) => {
+                now.saturating_sub(since) >= duration_nanos(self.after)
+            }
+        }
+    }
+
+    /// A record found room in the channel.
+    fn sent(&self) {
+        if self.full_since.load(Ordering::Relaxed) != NOT_FULL {
+            self.full_since.store(NOT_FULL, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Ceiling on the memory used by queued and buffered records.
+///
+/// Every record sent holds a `MemoryReservation` of its estimated size until
+/// the worker thread drops it, so records waiting in the channels and in the
+/// buffers of the worker thread are all accounted for.
+struct MemoryBudget {
+    limit: usize,
+    used: AtomicUsize,
+    // Size of the largest record rejected since the worker last made room
+    needed: AtomicUsize,
+}
+
+impl MemoryBudget {
+    fn new(limit: usize) -> Self {
+        MemoryBudget {
+            limit,
+            used: AtomicUsize::new(0),
+            needed: AtomicUsize::new(0),
+        }
+    }
+
+    fn reserve(self: &Arc<Self>, size: usize) -> Option<MemoryReservation> {
+        let limit = self.limit;
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(size).filter(|&total| total <= limit)
+            })
+            .ok()?;
+        Some(MemoryReservation {
+            budget: self.clone(),
+            size,
+        })
+    }
+
+    /// Ask the worker to evict buffered records to make room for `size`
+    /// bytes.
+    ///
+    /// Returns `true` if there was no pending request yet, so the worker
+    /// needs waking up.
+    fn request(&self, size: usize) -> bool {
+        self.needed.fetch_max(size, Ordering::Relaxed) == 0
+    }
+}
+
+struct MemoryReservation {
+    budget: Arc<MemoryBudget>,
+    size: usize,
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.size, Ordering::Relaxed);
+    }
+}
+
+/// Count of the records queued in a channel above
+/// `MAX_PREALLOCATED_CHAN_SIZE`, which is unbounded.
+///
+/// Every record sent holds a `ChanSlot` until the worker thread receives it.
+struct ChanSlots {
+    size: usize,
+    used: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl ChanSlots {
+    fn new(size: usize) -> Self {
+        ChanSlots {
+            size,
+            used: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Take a slot if one is free.
+    fn try_take(self: &Arc<Self>) -> Option<ChanSlot> {
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        if *used >= self.size {
+            return None;
+        }
+        *used += 1;
+        Some(ChanSlot(self.clone()))
+    }
+
+    /// Wait for a free slot, or until the worker thread stops running.
+    fn take(self: &Arc<Self>, running: &AtomicBool) -> Option<ChanSlot> {
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        while *used >= self.size {
+            if !running.load(Ordering::Relaxed) {
+                return None;
+            }
+            used = self
+                .freed
+                .wait_timeout(used, CHAN_SLOTS_POLL_INTERVAL)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        *used += 1;
+        Some(ChanSlot(self.clone()))
+    }
+}
+
+struct ChanSlot(Arc<ChanSlots>);
+
+impl Drop for ChanSlot {
+    fn drop(&mut self) {
+        let mut used = self.0.used.lock().unwrap_or_else(|e| e.into_inner());
+        *used -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+impl AsyncCore {
+    /// New `AsyncCore` with default parameters
+    pub fn new<D>(drain: D) -> Self
+    where
+        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+        D: std::panic::RefUnwindSafe,
+    {
+        AsyncCoreBuilder::new(drain).build()
+    }
+
+    /// Build `AsyncCore` drain with custom parameters
+    pub fn custom<
+        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    >(
+        drain: D,
+    ) -> AsyncCoreBuilder<D> {
+        AsyncCoreBuilder::new(drain)
+    }
+
+    /// Build `AsyncCore` writing to a `drain` that may fail, keeping up to
+    /// `capacity` records it failed to write for a retry
+    ///
+    /// See `AsyncCoreBuilder::retry_queue`.
+    pub fn custom_retrying<D>(
+        drain: D,
+        capacity: usize,
+    ) -> AsyncCoreBuilder<RetryOnErr<D>>
+    where
+        D: slog::Drain<Ok = ()> + Send + 'static,
+        D::Err: fmt::Debug,
+    {
+        AsyncCoreBuilder::new(RetryOnErr(drain)).retry_queue(capacity)
+    }
+
+    /// Build `AsyncCore` handing records to `drain` in batches
+    ///
+    /// The worker thread collects records until it has `max_records` of them
+    /// or the oldest one waited for `max_delay`, whichever comes first,
+    /// and writes them with a single `BatchDrain::log_batch` call. Batched
+    /// records are not kept for `context_on_error`.
+    ///
+    /// As with `custom`, `drain` must handle its errors, see `FuseBatch`.
+    pub fn custom_batch<B: BatchDrain>(
+        drain: B,
+        max_records: usize,
+        max_delay: Duration,
+    ) -> AsyncCoreBuilder<slog::Discard> {
+        let mut builder = AsyncCoreBuilder::new(slog::Discard);
+        builder.batch =
+            Some(Batcher::new(Box::new(drain), max_records, max_delay));
+        builder
+    }
+
+    /// Build `AsyncCore` handing records to `drain` in size-limited chunks
+    ///
+    /// The worker thread serializes every record with
+    /// `ChunkedDrain::serialize` and splits the bytes into `OutputChunk`s of
+    /// at most `max_output_chunk` bytes each, delivered in order.
+    pub fn custom_chunked<C: ChunkedDrain>(
+        drain: C,
+    ) -> AsyncCoreBuilder<slog::Discard> {
+        let mut builder = AsyncCoreBuilder::new(slog::Discard);
+        builder.chunked = Some(Box::new(drain));
+        builder
+    }
+
+    /// Build `AsyncCore` handing records over to the returned `RecordStream`
+    /// instead of writing them to a drain
+    ///
+    /// Streamed records are not kept for `context_on_error`.
+    #[cfg(feature = "tokio")]
+    pub fn custom_stream() -> (AsyncCoreBuilder<slog::Discard>, RecordStream) {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut builder = AsyncCoreBuilder::new(slog::Discard);
+        builder.stream = Some(tx);
+        (builder, RecordStream::new(rx))
+    }
+
+    fn get_sender(
+        &self,
+    ) -> Result<
+        &crossbeam_channel::Sender<AsyncMsg>,
+        std::sync::PoisonError<
+            sync::MutexGuard<crossbeam_channel::Sender<AsyncMsg>>,
+        >,
+    > {
+        if let Some(index) = self.bound_sender() {
+            return Ok(&self.pool_senders[index]);
+        }
+        match self.tl_sender {
+            Some(ref tl_sender) => {
+                tl_sender.get_or_try(|| Ok(self.ref_sender.clone()))
+            }
+            None => Ok(&self.ref_sender),
+        }
+    }
+
+    /// Have the worker thread write a probe record to the drain, see
+    /// `AsyncCoreBuilder::probe_on_build`.
+    fn probe(&self) -> AsyncResult<()> {
+        let r = AsyncRecord::from(
+            &record!(
+                Level::Trace,
+                PROBE_TAG,
+                &format_args!("slog-async: probe"),
+                b!()
+            ),
+            &o!().into(),
+        );
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        self.ref_sender.send(AsyncMsg::Probe(r, ack_tx))?;
+        ack_rx.recv()?.map_err(|msg| {
+            AsyncError::Fatal(Box::new(io::Error::other(format!(
+                "slog-async: drain failed its probe write: {}",
+                msg
+            ))))
+        })
+    }
+
+    /// Index of the pool sender bound to the calling thread, if any.
+    fn bound_sender(&self) -> Option<usize> {
+        if self.pool_senders.is_empty() {
+            return None;
+        }
+        BOUND_SENDERS.with(|bound| {
+            bound
+                .borrow()
+                .iter()
+                .find(|&&(pool_id, _)| pool_id == self.pool_id)
+                .map(|&(_, index)| index)
+        })
+    }
+
+    /// Write `record` to the `worker_gone_fallback` drain if `res` tells the
+    /// worker thread is gone.
+    fn or_fallback(
+        &self,
+        res: AsyncResult<()>,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> AsyncResult<()> {
+        match (res, &self.worker_gone_fallback) {
+            (Err(ref err), Some(fallback)) if err.is_worker_gone() => {
+                let start = Instant::now();
+                {
+                    let drain = match fallback.lock() {
+                        Ok(drain) => drain,
+                        Err(e) => e.into_inner(),
+                    };
+                    let _ = drain.log(record, logger_values);
+                }
+                self.fallback_count.fetch_add(1, Ordering::Relaxed);
+                self.fallback_nanos.fetch_add(
+                    duration_nanos(start.elapsed()),
+                    Ordering::Relaxed,
+                );
+                Ok(())
+            }
+            (res, _) => res,
+        }
+    }
+
+    /// Serialize a `Record` to be sent to the worker thread.
+    fn serialize(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> AsyncRecord {
+        if let Some(handle) = self.cached_logger_values(logger_values) {
+            return self.serialize_handle(record, &handle);
+        }
+        self.serialize_with(record, logger_values, || {
+            AsyncRecord::with_pid(
+                record,
+                logger_values,
+                find_pid(record, logger_values),
+                self.value_policy,
+                self.max_kv_pairs,
+                self.max_value_len,
+            )
+        })
+    }
+
+    /// Handle of `logger_values` from the `logger_values_cache`, added to
+    /// it if missing.
+    fn cached_logger_values(
+        &self,
+        logger_values: &OwnedKVList,
+    ) -> Option<LoggerValuesHandle> {
+        let cache = self.logger_values_cache.as_ref()?;
+        let id = logger_values_id(logger_values)?;
+        {
+            let mut cache = match cache.lock() {
+                Ok(cache) => cache,
+                Err(e) => e.into_inner(),
+            };
+            if let Some(handle) = cache.get(id) {
+                return Some(handle);
+            }
+        }
+        // Serialized unlocked: on a panic the record is serialized without
+        // the cache, as configured by `catch_serialization_panics`
+        let handle = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.precompute_logger_values(logger_values)
+        }))
+        .ok()?;
+        let mut cache = match cache.lock() {
+            Ok(cache) => cache,
+            Err(e) => e.into_inner(),
+        };
+        cache.insert(id, logger_values, handle.clone());
+        Some(handle)
+    }
+
+    /// Serialize a `Record` logged with the values of `handle`.
+    fn serialize_handle(
+        &self,
+        record: &Record,
+        handle: &LoggerValuesHandle,
+    ) -> AsyncRecord {
+        self.serialize_with(record, &handle.values, || {
+            AsyncRecord::with_pid(
+                record,
+                &handle.values,
+                handle.pid,
+                self.value_policy,
+                self.max_kv_pairs,
+                self.max_value_len,
+            )
+        })
+    }
+
+    /// Serialize `record` with `f`, applying the serialization options.
+    fn serialize_with<F>(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+        f: F,
+    ) -> AsyncRecord
+    where
+        F: FnOnce() -> AsyncRecord,
+    {
+        let catch_panics = self.catch_serialization_panics;
+        let f = move || {
+            if !catch_panics {
+                return f();
+            }
+            panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+                SENDING.with(|sending| sending.set(false));
+                AsyncRecord::serialization_panicked(record, logger_values)
+            })
+        };
+        let seq = self
+            .sequence
+            .as_ref()
+            .map(|sequence| sequence.fetch_add(1, Ordering::Relaxed));
+        let mut r = match self.slow_serialization {
+            Some(ref slow_serialization) => {
+                let start = Instant::now();
+                let r = f();
+                let elapsed = start.elapsed();
+                if elapsed > slow_serialization.threshold {
+                    (slow_serialization.report)(record, elapsed);
+                }
+                r
+            }
+            None => f(),
+        };
+        if let Some((key, start)) = self.elapsed_field {
+            r.push_kv(key, start.elapsed().as_millis() as u64);
+        }
+        r.seq = seq;
+        r
+    }
+
+    /// Send `AsyncRecord` to a worker thread.
+    fn send(&self, r: AsyncRecord) -> AsyncResult<()> {
+        self.send_record(r, self.blocking)
+    }
+
+    /// Send `AsyncRecord` to a worker thread, overriding the configured
+    /// `blocking` behavior.
+    fn send_record(&self, r: AsyncRecord, blocking: bool) -> AsyncResult<()> {
+        self.send_record_or(r, blocking, None)
+    }
+
+    /// Send `AsyncRecord` to a worker thread, overriding the configured
+    /// `blocking` behavior, and handing it to `on_full` if it would be
+    /// rejected because the channel is full.
+    fn send_record_or(
+        &self,
+        mut r: AsyncRecord,
+        blocking: bool,
+        on_full: Option<&OnFullFn>,
+    ) -> AsyncResult<()> {
+        if self.on_worker_thread() {
+            // Logged by the drain itself. Sending to the channel could block
+            // forever, so hand the record to the worker directly.
+            REENTRANT.with(|pending| pending.borrow_mut().push(r));
+            return Ok(());
+        }
+
+        if !self.stats.running.load(Ordering::Relaxed) {
+            return Err(AsyncError::worker_gone());
+        }
+        let sender = self.get_sender()?;
+
+        self.reserve_memory(&mut r, blocking)?;
+
+        if self.queue_time {
+            r.enqueued = Some(Instant::now());
+        }
+        let ack_rx = match self.sync_checkpoint_level {
+            Some(level) if r.level.is_at_least(level) => {
+                let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+                r.ack = Some(ack_tx);
+                Some(ack_rx)
+            }
+            _ => None,
+        };
+        // Nothing may run a manual worker before the channel fills up, so
+        // only spawned workers are waited for
+        let starting =
+            !self.manual && !self.stats.ready.load(Ordering::Acquire);
+        if blocking || ack_rx.is_some() || starting {
+            let start = self.send_started();
+            r.slot = self.take_slot();
+            let res = sender.send(AsyncMsg::Record(r));
+            self.send_finished(start);
+            res?;
+        } else {
+            if let Some(ref slots) = self.chan_slots {
+                match slots.try_take() {
+                    Some(slot) => r.slot = Some(slot),
+                    None => return self.overflow(sender, r, on_full),
+                }
+            }
+            if let Some((slots, level)) = self.reserved_capacity {
+                if !r.level.is_at_least(level)
+                    && sender.len() + slots >= self.chan_size
+                {
+                    return self.overflow(sender, r, on_full);
+                }
+            }
+            let start = self.send_started();
+            let res = sender.try_send(AsyncMsg::Record(r));
+            self.send_finished(start);
+            match res {
+                Ok(()) => {}
+                Err(crossbeam_channel::TrySendError::Full(
+                    AsyncMsg::Record(r),
+                )) => {
+                    return self.overflow(sender, r, on_full);
+                }
+                Err(crossbeam_channel::TrySendError::Full(_)) => {
+                    return Err(self.rejected());
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.sent();
+        if let Some(ack_rx) = ack_rx {
+            ack_rx.recv()?;
+        }
+
+        Ok(())
+    }
+
+    /// Wait for a slot of a channel above `MAX_PREALLOCATED_CHAN_SIZE`.
+    fn take_slot(&self) -> Option<ChanSlot> {
+        match self.chan_slots {
+            Some(ref slots) => slots.take(&self.stats.running),
+            None => None,
+        }
+    }
+
+    /// Account for a record sent to the main channel.
+    fn sent(&self) {
+        self.stats
+            .max_depth
+            .fetch_max(self.ref_sender.len(), Ordering::Relaxed);
+        self.full_transitions.sent();
+        if let Some(ref sync_escape) = self.sync_escape {
+            sync_escape.sent();
+        }
+    }
+
+    /// Start timing a send, if `measure_send_latency` is enabled.
+    fn send_started(&self) -> Option<Instant> {
+        self.send_latency.as_ref().map(|_| Instant::now())
+    }
+
+    /// Record the latency of a send timed by `send_started`.
+    fn send_finished(&self, start: Option<Instant>) {
+        if let (Some(histogram), Some(start)) = (&self.send_latency, start) {
+            histogram.record(start.elapsed());
+        }
+    }
+
+    /// Handle `r` finding the channel full, see `overflow_handler`.
+    fn overflow(
+        &self,
+        sender: &Sender<AsyncMsg>,
+        mut r: AsyncRecord,
+        on_full: Option<&OnFullFn>,
+    ) -> AsyncResult<()> {
+        if matches!(self.sync_escape, Some(ref escape) if escape.due()) {
+            let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+            r.ack = Some(ack_tx);
+            r.slot = self.take_slot();
+            sender.send(AsyncMsg::Record(r))?;
+            ack_rx.recv()?;
+            return Ok(());
+        }
+        let decision = match self.overflow_handler {
+            Some(ref handler) => handler(&r),
+            None => OverflowDecision::Drop,
+        };
+        match decision {
+            OverflowDecision::Drop => match (&self.overflow_with, on_full) {
+                (Some(f), _) => {
+                    f(r);
+                    Ok(())
+                }
+                (None, Some(on_full)) => on_full(r),
+                (None, None) => Err(self.rejected()),
+            },
+            OverflowDecision::Block => self.send_waiting(r),
+            OverflowDecision::Redirect(drain) => {
+                let _ = r.log_to_ref(&drain);
+                Ok(())
+            }
+        }
+    }
+
+    /// Send `r` to the channel, waiting for room.
+    fn send_waiting(&self, mut r: AsyncRecord) -> AsyncResult<()> {
+        let sender = self.get_sender()?;
+        let start = self.send_started();
+        r.slot = self.take_slot();
+        let res = sender.send(AsyncMsg::Record(r));
+        self.send_finished(start);
+        res?;
+        self.sent();
+        Ok(())
+    }
+
+    /// Whether the caller runs on the worker thread.
+    fn on_worker_thread(&self) -> bool {
+        self.worker_thread.get() == Some(&thread::current().id())
+    }
+
+    /// Wait until fewer than `watermark` messages are queued in the main
+    /// channel, see `OverflowStrategy::BlockAbove`.
+    fn wait_below(&self, watermark: usize) {
+        if self.on_worker_thread() {
+            return;
+        }
+        while self.ref_sender.len() >= watermark
+            && self.stats.running.load(Ordering::Relaxed)
+        {
+            thread::sleep(BLOCK_ABOVE_POLL_INTERVAL);
+        }
+    }
+
+    /// Account for a record rejected because the channel is full.
+    fn rejected(&self) -> AsyncError {
+        self.full_transitions.full();
+        self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+        AsyncError::Full
+    }
+
+    /// Send `AsyncRecord` through the control channel, ahead of the records
+    /// queued in the main channel.
+    ///
+    /// The control channel is unbounded, only the memory budget can reject
+    /// the record.
+    fn send_control(&self, mut r: AsyncRecord) -> AsyncResult<()> {
+        self.reserve_memory(&mut r, false)?;
+        self.ctrl_sender.send(AsyncMsg::Record(r))?;
+        Ok(())
+    }
+
+    /// Reserve the memory `r` takes in the memory budget, if any, asking the
+    /// worker thread to make room if it doesn't fit.
+    ///
+    /// If `blocking`, waits until the worker thread made enough room, unless
+    /// `r` is larger than the whole budget.
+    fn reserve_memory(
+        &self,
+        r: &mut AsyncRecord,
+        blocking: bool,
+    ) -> AsyncResult<()> {
+        let budget = match self.memory_budget {
+            Some(ref budget) => budget,
+            None => return Ok(()),
+        };
+        loop {
+            if let Some(reservation) = budget.reserve(r.size) {
+                r.reservation = Some(reservation);
+                return Ok(());
+            }
+            if budget.request(r.size) {
+                let _ = self.ctrl_sender.send(AsyncMsg::MemoryPressure);
+            }
+            if !blocking
+                || r.size > budget.limit
+                || !self.stats.running.load(Ordering::Relaxed)
+            {
+                return Err(self.rejected());
+            }
+            thread::sleep(MEMORY_BUDGET_POLL_INTERVAL);
+        }
+    }
+
+    /// Switch the worker thread over to writing to `drain`.
+    ///
+    /// Waits until the records sent before the call are written to the old
+    /// drain, as with `flush`, then switches: the records logged afterwards
+    /// are written to `drain`, and the old drain is dropped. Records logged
+    /// by other threads during the call go to either drain, but none is
+    /// lost. Called from the worker thread itself, eg. by the wrapped drain,
+    /// the records still queued are written to `drain`.
+    pub fn migrate_to<D>(&self, drain: D) -> AsyncResult<()>
+    where
+        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        self.flush()?;
+        self.replace_drain(drain)
+    }
+
+    /// Switch the worker thread over to writing to `drain` without waiting
+    /// for the records already queued.
+    fn replace_drain<D>(&self, drain: D) -> AsyncResult<()>
+    where
+        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        self.ctrl_sender
+            .send(AsyncMsg::ReplaceDrain(Box::new(drain)))?;
+        Ok(())
+    }
+
+    /// Write the records tagged `name` to `drain` instead, from now on.
+    ///
+    /// Meant for plugins attaching their own log destination at runtime.
+    /// Registering the same name again replaces the previous drain. Unlike
+    /// with `migrate_to`, records already queued are affected too.
+    pub fn register_sink<D>(&self, name: &str, drain: D) -> AsyncResult<()>
+    where
+        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        self.ctrl_sender
+            .send(AsyncMsg::RegisterSink(name.into(), Box::new(drain)))?;
+        Ok(())
+    }
+
+    /// Stop writing the records tagged `name` to the drain registered with
+    /// `register_sink`. The drain is dropped by the worker thread.
+    pub fn unregister_sink(&self, name: &str) -> AsyncResult<()> {
+        self.ctrl_sender
+            .send(AsyncMsg::UnregisterSink(name.into()))?;
+        Ok(())
+    }
+
+    /// Sender of `ControlMsg`s, for building custom control layers.
+    ///
+    /// Messages go through the control channel, like `register_sink`: the
+    /// worker thread handles them before the records already queued.
+    pub fn control_sender(&self) -> ControlSender {
+        ControlSender {
+            sender: self.ctrl_sender.clone(),
+        }
+    }
+
+    /// Wait until the worker thread wrote all the records sent so far.
+    ///
+    /// A barrier is sent through the main and the control channel each, and
+    /// this returns once the worker got to both of them, so records sent to
+    /// either channel before the call are all written. Pending batches are
+    /// written as well. Called from the worker thread itself, eg. by the
+    /// wrapped drain, it returns right away.
+    pub fn flush(&self) -> AsyncResult<()> {
+        if self.on_worker_thread() {
+            return Ok(());
+        }
+        wait_flushed(&send_flush(&self.ctrl_sender, self.get_sender()?)?)
+    }
+
+    /// Wait until the worker thread wrote all the records sent so far,
+    /// calling `progress` every 100ms meanwhile.
+    ///
+    /// `progress` is passed the number of messages still waiting in the
+    /// channels, eg. to show how far a long backlog is from being drained on
+    /// shutdown. Messages sent after the call are counted too. See `flush`.
+    pub fn flush_with_progress<F>(&self, progress: F) -> AsyncResult<()>
+    where
+        F: Fn(usize),
+    {
+        if self.on_worker_thread() {
+            return Ok(());
+        }
+        let sender = self.get_sender()?;
+        let ack_rx = send_flush(&self.ctrl_sender, sender)?;
+        // One acknowledgement per barrier
+        for _ in 0..2 {
+            loop {
+                match ack_rx.recv_timeout(FLUSH_PROGRESS_INTERVAL) {
+                    Ok(()) => break,
+                    Err(RecvTimeoutError::Timeout) => {
+                        progress(sender.len() + self.ctrl_sender.len())
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(AsyncError::worker_gone())
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait up to `timeout` until the worker thread wrote all the records
+    /// sent so far.
+    ///
+    /// Returns `Ok(true)` once flushed, or `Ok(false)` if `timeout` passed
+    /// first, eg. because the drain stalls on a network socket: lets a
+    /// graceful shutdown bound how long it waits before giving up. The
+    /// timeout covers queueing the barrier in a full channel too. See
+    /// `flush`.
+    pub fn flush_timeout(&self, timeout: Duration) -> AsyncResult<bool> {
+        if self.on_worker_thread() {
+            return Ok(true);
+        }
+        let deadline = Instant::now() + timeout;
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(2);
+        self.ctrl_sender.send(AsyncMsg::Flush(ack_tx.clone()))?;
+        match self
+            .get_sender()?
+            .send_deadline(AsyncMsg::Flush(ack_tx), deadline)
+        {
+            Ok(()) => {}
+            Err(SendTimeoutError::Timeout(_)) => return Ok(false),
+            Err(SendTimeoutError::Disconnected(_)) => {
+                return Err(AsyncError::worker_gone())
+            }
+        }
+        // One acknowledgement per barrier
+        for _ in 0..2 {
+            match ack_rx.recv_deadline(deadline) {
+                Ok(()) => {}
+                Err(RecvTimeoutError::Timeout) => return Ok(false),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(AsyncError::worker_gone())
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Prepare logger values that many records are logged with, so the
+    /// work done on them per record is done once instead.
+    ///
+    /// The values are serialized once, applying `value_policy` and
+    /// `max_value_len`, and searched for a PID, see `PIDLogControl`. Records
+    /// logged with `log_with_handle` share the serialized values instead of
+    /// the worker thread serializing the logger's values for each of them.
+    /// The values are serialized with a placeholder record: values computed
+    /// from the record, eg. `slog::FnValue`s, are computed only once and
+    /// can't set the PID.
+    ///
+    /// The serialized values live as long as the handle and its clones.
+    /// Handles created by the core itself are bounded by
+    /// `AsyncCoreBuilder::logger_values_cache`.
+    pub fn precompute_logger_values(
+        &self,
+        logger_values: &OwnedKVList,
+    ) -> LoggerValuesHandle {
+        LoggerValuesHandle::new(
+            logger_values,
+            self.value_policy,
+            self.max_value_len,
+        )
+    }
+
+    /// Log `record` with the logger values prepared by
+    /// `precompute_logger_values`.
+    pub fn log_with_handle(
+        &self,
+        record: &Record,
+        handle: &LoggerValuesHandle,
+    ) -> AsyncResult<()> {
+        self.send(self.serialize_handle(record, handle))
+    }
+
+    /// Wait until the worker thread wrote the records tagged `tag` sent so
+    /// far.
+    ///
+    /// Meant for shutting down a subsystem logging with its own tag. The
+    /// channel is FIFO, so all the records sent before are written too, but
+    /// unlike `flush` a pending batch is only written if it holds a record
+    /// tagged `tag`, and records sent through the control channel, such as
+    /// drop reports, aren't waited for.
+    pub fn flush_tag(&self, tag: &str) -> AsyncResult<()> {
+        if self.on_worker_thread() {
+            return Ok(());
+        }
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        self.get_sender()?
+            .send(AsyncMsg::FlushTag(tag.into(), ack_tx))?;
+        ack_rx.recv()?;
+        Ok(())
+    }
+
+    /// Wait until the worker thread wrote all the records sent so far, then
+    /// write the records kept by `AsyncCoreBuilder::keep_recent` to
+    /// `writer`, one per line, oldest first.
+    ///
+    /// Meant for a "save my logs now" action, eg. before a risky operation.
+    /// Returns how many records were written: none unless `keep_recent` was
+    /// set. Called from the worker thread itself it writes nothing, as the
+    /// worker can't answer while it waits. See `flush`.
+    pub fn checkpoint<W: io::Write>(
+        &self,
+        writer: &mut W,
+    ) -> AsyncResult<usize> {
+        if self.on_worker_thread() {
+            return Ok(0);
+        }
+        self.flush()?;
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        self.ctrl_sender.send(AsyncMsg::Checkpoint(ack_tx))?;
+        let recent = ack_rx.recv()?;
+        for line in &recent {
+            writeln!(writer, "{}", line)
+                .map_err(|e| AsyncError::Fatal(Box::new(e)))?;
+        }
+        Ok(recent.len())
+    }
+
+    /// Number of records the worker thread wrote to the drain so far.
+    ///
+    /// Meant for health checks: if it stops advancing while records are
+    /// being logged, the worker is stuck.
+    pub fn processed_count(&self) -> u64 {
+        self.stats.processed.load(Ordering::Relaxed)
+    }
+
+    /// Whether the worker thread gave up on the drain after it panicked
+    /// more than `max_restarts` times.
+    ///
+    /// See `AsyncCoreBuilder::max_restarts`.
+    pub fn is_degraded(&self) -> bool {
+        self.stats.degraded.load(Ordering::Relaxed)
+    }
+
+    /// Wait until the worker thread is ready to process records, at most
+    /// `timeout`.
+    ///
+    /// The worker is ready once the `on_thread_start` hook returned, or right
+    /// after it started without one. Returns whether it is ready.
+    pub fn wait_ready(&self, timeout: Duration) -> bool {
+        self.stats.wait_ready(timeout)
+    }
+
+    /// Log from the calling thread through the pool sender of `index`.
+    ///
+    /// Meant to be called by the threads of a pool when they start, with an
+    /// index provided by the pool, eg. the worker index of a rayon pool.
+    /// `index` is taken modulo the `pool_senders` count, so threads reusing
+    /// an index share its sender. Binding again replaces the previous index.
+    /// Does nothing unless `pool_senders` is set.
+    pub fn bind_sender(&self, index: usize) {
+        if self.pool_senders.is_empty() {
+            return;
+        }
+        let index = index % self.pool_senders.len();
+        BOUND_SENDERS.with(|bound| {
+            let mut bound = bound.borrow_mut();
+            match bound.iter_mut().find(|e| e.0 == self.pool_id) {
+                Some(entry) => entry.1 = index,
+                None => bound.push((self.pool_id, index)),
+            }
+        });
+    }
+
+    /// Number of producer threads holding a cached sender.
+    ///
+    /// Each thread logging through this drain caches its own sender on first
+    /// use. Slots of exited threads are reused by new ones, so this is the
+    /// highest number of threads that logged concurrently; a value growing
+    /// over time points to thread churn. Always 0 with
+    /// `thread_local_senders` disabled.
+    pub fn producer_thread_count(&self) -> usize {
+        match self.tl_sender {
+            Some(ref tl_sender) => tl_sender.iter().count(),
+            None => 0,
+        }
+    }
+
+    /// Most messages ever seen queued in the channel, since the drain was
+    /// built or `reset_max_depth` was last called.
+    ///
+    /// Meant for capacity sizing: a peak close to `chan_size` means bursts
+    /// come close to filling the channel. The depth is sampled by the
+    /// logging threads right after sending a record.
+    pub fn max_queue_depth(&self) -> usize {
+        self.stats.max_depth.load(Ordering::Relaxed)
+    }
+
+    /// Restart tracking `max_queue_depth` from the current depth, returning
+    /// the peak until now.
+    ///
+    /// Lets a monitor read the peak of each interval.
+    pub fn reset_max_depth(&self) -> usize {
+        self.stats
+            .max_depth
+            .swap(self.ref_sender.len(), Ordering::Relaxed)
+    }
+
+    /// Estimated memory used by the records waiting for the worker thread,
+    /// in bytes, or `None` unless `track_memory` or `memory_budget` is set.
+    ///
+    /// Counts the records queued in the channel and the ones buffered by the
+    /// worker thread until written: the record being written, pending
+    /// batches and the records kept for `context_on_error`. Each record
+    /// counts the memory allocated for its message, tag and key-values, not
+    /// the logger values it shares with its `Logger`.
+    pub fn estimated_memory_bytes(&self) -> Option<usize> {
+        self.memory_budget
+            .as_ref()
+            .map(|budget| budget.used.load(Ordering::Relaxed))
+    }
+
+    /// Distribution of the time sending records to the channel took so far,
+    /// or `None` unless `measure_send_latency` is enabled.
+    ///
+    /// Only covers handing records over to the channel, including waiting
+    /// for room when blocking, not their serialization.
+    pub fn send_latency_percentiles(&self) -> Option<SendLatencyPercentiles> {
+        self.send_latency
+            .as_ref()
+            .map(LatencyHistogram::percentiles)
+    }
+
+    /// All the runtime statistics at once.
+    ///
+    /// `dropped` is always 0, as `AsyncCore` returns `AsyncError::Full`
+    /// instead of dropping records. See `Async::stats`.
+    pub fn stats(&self) -> AsyncStatsSnapshot {
+        let depth = self.ref_sender.len();
+        let capacity = self.chan_size;
+        AsyncStatsSnapshot {
+            depth,
+            capacity,
+            processed: self.stats.processed.load(Ordering::Relaxed),
+            dropped: 0,
+            load_factor: if capacity == 0 {
+                0.0
+            } else {
+                depth as f64 / capacity as f64
+            },
+            worker_alive: self.stats.running.load(Ordering::Relaxed),
+            fallback_count: self.fallback_count.load(Ordering::Relaxed),
+            fallback_duration: Duration::from_nanos(
+                self.fallback_nanos.load(Ordering::Relaxed),
+            ),
+        }
+    }
+
+    /// Send already serialized `AsyncRecord`s to the worker thread in one
+    /// call.
+    ///
+    /// Records are sent in order, each one as with `log`: blocking or not
+    /// according to the `blocking` configuration. Without `blocking`, the
+    /// records finding the channel full are rejected, and counted as dropped
+    /// in the `self_report` records, while the following ones are still
+    /// sent. Returns the number of records accepted.
+    pub fn submit_batch<I>(&self, records: I) -> AsyncResult<usize>
+    where
+        I: IntoIterator<Item = AsyncRecord>,
+    {
+        let mut accepted = 0;
+        for r in records {
+            match self.send(r) {
+                Ok(()) => accepted += 1,
+                Err(AsyncError::Full) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(accepted)
+    }
+}
+
+impl Drain for AsyncCore {
+    type Ok = ();
+    type Err = AsyncError;
+
+    fn log(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> AsyncResult<()> {
+        let res = self.send(self.serialize(record, logger_values));
+        self.or_fallback(res, record, logger_values)
+    }
+
+    /// See `AsyncCore::flush`.
+    fn flush(&self) -> Result<(), slog::FlushError> {
+        AsyncCore::flush(self).map_err(flush_error)
+    }
+}
+
+/// Runtime statistics of an `AsyncCore` or `Async`, read all at once
+///
+/// The counters are updated concurrently by the logging threads and the
+/// worker thread, so the snapshot is only a close approximation of the state
+/// at a single point in time, but the derived fields always agree with the
+/// ones they are computed from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AsyncStatsSnapshot {
+    /// Number of messages waiting in the channel
+    pub depth: usize,
+    /// Channel size
+    pub capacity: usize,
+    /// Number of records the worker thread wrote to the drain so far
+    pub processed: u64,
+    /// Number of records dropped and not reported yet
+    pub dropped: usize,
+    /// `depth` relative to `capacity`, between 0 and 1
+    pub load_factor: f64,
+    /// Whether the worker thread is still running
+    pub worker_alive: bool,
+    /// Number of records written to the `worker_gone_fallback` drain
+    pub fallback_count: u64,
+    /// Time the logging threads spent writing to the `worker_gone_fallback`
+    /// drain
+    pub fallback_duration: Duration,
+}
+
+impl AsyncStatsSnapshot {
+    /// Render the statistics in the Prometheus text exposition format.
+    ///
+    /// Each metric name starts with `prefix`, eg. `slog_async`:
+    ///
+    /// * `<prefix>_queue_depth` - gauge, `depth`,
+    /// * `<prefix>_queue_capacity` - gauge, `capacity`,
+    /// * `<prefix>_queue_utilization` - gauge, `load_factor`,
+    /// * `<prefix>_processed_total` - counter, `processed`,
+    /// * `<prefix>_dropped_unreported` - gauge, `dropped`, reset by each
+    ///   drop report,
+    /// * `<prefix>_worker_alive` - gauge, 1 or 0, `worker_alive`,
+    /// * `<prefix>_fallback_total` - counter, `fallback_count`,
+    /// * `<prefix>_fallback_seconds_total` - counter, `fallback_duration`.
+    ///
+    /// The output ends with a newline, so it can be appended to other
+    /// metrics as is.
+    pub fn to_prometheus(&self, prefix: &str) -> String {
+        let metrics = [
+            (
+                "queue_depth",
+                "gauge",
+                "Messages waiting in the channel",
+                self.depth as f64,
+            ),
+            (
+                "queue_capacity",
+                "gauge",
+                "Channel size",
+                self.capacity as f64,
+            ),
+            (
+                "queue_utilization",
+                "gauge",
+                "Queue depth relative to the channel size",
+                self.load_factor,
+            ),
+            (
+                "processed_total",
+                "counter",
+                "Records written to the drain",
+                self.processed as f64,
+            ),
+            (
+                "dropped_unreported",
+                "gauge",
+                "Records dropped and not reported yet",
+                self.dropped as f64,
+            ),
+            (
+                "worker_alive",
+                "gauge",
+                "Whether the worker thread is running",
+                if self.worker_alive { 1.0 } else { 0.0 },
+            ),
+            (
+                "fallback_total",
+                "counter",
+                "Records written to the fallback drain",
+                self.fallback_count as f64,
+            ),
+            (
+                "fallback_seconds_total",
+                "counter",
+                "Time spent writing to the fallback drain",
+                self.fallback_duration.as_secs_f64(),
+            ),
+        ];
+        let mut out = String::new();
+        for &(name, kind, help, value) in &metrics {
+            out.push_str(&format!(
+                "# HELP {prefix}_{name} {help}\n\
+                 # TYPE {prefix}_{name} {kind}\n\
+                 {prefix}_{name} {value}\n",
+                prefix = prefix,
+                name = name,
+                help = help,
+                kind = kind,
+                value = value,
+            ));
+        }
+        out
+    }
+}
+
+/// Logger values prepared for logging many records, see
+/// `AsyncCore::precompute_logger_values`
+#[derive(Clone)]
+pub struct LoggerValuesHandle {
+    // Wraps the values serialized once, shared by the records
+    values: OwnedKVList,
+    pid: Option<usize>,
+}
+
+impl LoggerValuesHandle {
+    fn new(
+        values: &OwnedKVList,
+        policy: ValuePolicy,
+        max_value_len: Option<usize>,
+    ) -> Self {
+        let (values, pid) = Self::serialize_values(
+            &record!(Level::Info, "", &format_args!(""), b!()),
+            values,
+            ToSendSerializer::new(policy, None, max_value_len),
+        );
+        LoggerValuesHandle { values, pid }
+    }
+
+    /// Serialize `values` for the placeholder `record`, and search the
+    /// result for a PID.
+    fn serialize_values(
+        record: &Record,
+        values: &OwnedKVList,
+        mut ser: ToSendSerializer,
+    ) -> (OwnedKVList, Option<usize>) {
+        values
+            .serialize(record, &mut ser)
+            .expect("`ToSendSerializer` can't fail");
+        let (kv, _) = ser.finish();
+        // Once more to own the values in a form that can be shared
+        let mut owned = OwnedValuesSerializer(Vec::new());
+        kv.serialize(record, &mut owned)
+            .expect("`OwnedValuesSerializer` can't fail");
+        let values =
+            slog::OwnedKV(SerializedValues(owned.0.into_boxed_slice())).into();
+        let pid = find_pid(record, &values);
+        (values, pid)
+    }
+}
+
+/// Identity of logger values, see `logger_values_id`
+type LoggerValuesId = [usize; 2];
+
+/// Identity of the values shared by `logger_values` and its clones.
+///
+/// `OwnedKVList` keeps its `Arc` private, so its address is read from the
+/// list itself. It's only an identity while the `Arc` is alive: the cache
+/// keeps a clone of the list along with each entry.
+fn logger_values_id(logger_values: &OwnedKVList) -> Option<LoggerValuesId> {
+    if mem::size_of::<OwnedKVList>() != mem::size_of::<LoggerValuesId>() {
+        return None;
+    }
+    // SAFETY: `OwnedKVList` only holds an `Arc<dyn ..>`, a pair of non-null
+    // pointers without padding, so all its bytes are initialized, and the
+    // size was checked above.
+    Some(unsafe { mem::transmute_copy(logger_values) })
+}
+
+/// Handles of the logger values records were logged with, see
+/// `AsyncCoreBuilder::logger_values_cache`
+struct LoggerValuesCache {
+    capacity: usize,
+    // Incremented on each use, to find the least recently used entry
+    clock: u64,
+    entries: HashMap<LoggerValuesId, LoggerValuesCacheEntry>,
+}
+
+struct LoggerValuesCacheEntry {
+    // Keeps the id from being reused by other logger values
+    _values: OwnedKVList,
+    handle: LoggerValuesHandle,
+    last_used: u64,
+}
+
+impl LoggerValuesCache {
+    fn new(capacity: usize) -> Self {
+        LoggerValuesCache {
+            capacity,
+            clock: 0,
+            entries: HashMap::with_capacity(capacity),
+        }
+    }
+
+    fn get(&mut self, id: LoggerValuesId) -> Option<LoggerValuesHandle> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(&id)?;
+        entry.last_used = self.clock;
+        Some(entry.handle.clone())
+    }
+
+    /// Add `handle`, evicting the least recently used entry if full.
+    fn insert(
+        &mut self,
+        id: LoggerValuesId,
+        values: &OwnedKVList,
+        handle: LoggerValuesHandle,
+    ) {
+        if self.entries.len() >= self.capacity
+            && !self.entries.contains_key(&id)
+        {
+            let lru = self
+                .entries
+                .iter()
+                .min_by_key(|&(_, entry)| entry.last_used)
+                .map(|(&id, _)| id);
+            if let Some(lru) = lru {
+                self.entries.remove(&lru);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(
+            id,
+            LoggerValuesCacheEntry {
+                _values: values.clone(),
+                handle,
+                last_used: self.clock,
+            },
+        );
+    }
+}
+
+/// Logger values serialized once, see `LoggerValuesHandle`
+///
+/// Never modified, so records share them without locking.
+struct SerializedValues(Box<[(Key, OwnedValue)]>);
+
+impl KV for SerializedValues {
+    fn serialize(
+        &self,
+        _record: &Record,
+        serializer: &mut dyn Serializer,
+    ) -> slog::Result {
+        for (key, value) in self.0.iter() {
+            value.emit(Key::clone(key), serializer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Value of `SerializedValues`, emitted the way it was
+enum OwnedValue {
+    Unit,
+    None,
+    Bool(bool),
+    Char(char),
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    F32(f32),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    Usize(usize),
+    Isize(isize),
+    Str(String),
+    // Not `Sync`, so the only values behind a lock
+    #[cfg(feature = "nested-values")]
+    Serde(Mutex<Box<dyn slog::SerdeValue + Send>>),
+}
+
+impl OwnedValue {
+    fn emit(&self, key: Key, serializer: &mut dyn Serializer) -> slog::Result {
+        match *self {
+            OwnedValue::Unit => serializer.emit_unit(key),
+            OwnedValue::None => serializer.emit_none(key),
+            OwnedValue::Bool(v) => serializer.emit_bool(key, v),
+            OwnedValue::Char(v) => serializer.emit_char(key, v),
+            OwnedValue::U8(v) => serializer.emit_u8(key, v),
+            OwnedValue::I8(v) => serializer.emit_i8(key, v),
+            OwnedValue::U16(v) => serializer.emit_u16(key, v),
+            OwnedValue::I16(v) => serializer.emit_i16(key, v),
+            OwnedValue::U32(v) => serializer.emit_u32(key, v),
+            OwnedValue::I32(v) => serializer.emit_i32(key, v),
+            OwnedValue::F32(v) => serializer.emit_f32(key, v),
+            OwnedValue::U64(v) => serializer.emit_u64(key, v),
+            OwnedValue::I64(v) => serializer.emit_i64(key, v),
+            OwnedValue::F64(v) => serializer.emit_f64(key, v),
+            OwnedValue::Usize(v) => serializer.emit_usize(key, v),
+            OwnedValue::Isize(v) => serializer.emit_isize(key, v),
+            OwnedValue::Str(ref v) => serializer.emit_str(key, v),
+            #[cfg(feature = "nested-values")]
+            OwnedValue::Serde(ref v) => {
+                let v = match v.lock() {
+                    Ok(v) => v,
+                    Err(e) => e.into_inner(),
+                };
+                serializer.emit_serde(key, &**v)
+            }
+        }
+    }
+}
+
+/// Collects `OwnedValue`s, in serialization order.
+struct OwnedValuesSerializer(Vec<(Key, OwnedValue)>);
+
+impl Serializer for OwnedValuesSerializer {
+    fn emit_arguments(
+        &mut self,
+        key: Key,
+        val: &fmt::Arguments,
+    ) -> slog::Result {
+        self.0.push((key, OwnedValue::Str(fmt::format(*val))));
+        Ok(())
+    }
+    fn emit_unit(&mut self, key: Key) -> slog::Result {
+        self.0.push((key, OwnedValue::Unit));
+        Ok(())
+    }
+    fn emit_none(&mut self, key: Key) -> slog::Result {
+        self.0.push((key, OwnedValue::None));
+        Ok(())
+    }
+    fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
+        self.0.push((key, OwnedValue::Bool(val)));
+        Ok(())
+    }
+    fn emit_char(&mut self, key: Key, val: char) -> slog::Result {
+        self.0.push((key, OwnedValue::Char(val)));
+        Ok(())
+    }
+    fn emit_u8(&mut self, key: Key, val: u8) -> slog::Result {
+        self.0.push((key, OwnedValue::U8(val)));
+        Ok(())
+    }
+    fn emit_i8(&mut self, key: Key, val: i8) -> slog::Result {
+        self.0.push((key, OwnedValue::I8(val)));
+        Ok(())
+    }
+    fn emit_u16(&mut self, key: Key, val: u16) -> slog::Result {
+        self.0.push((key, OwnedValue::U16(val)));
+        Ok(())
+    }
+    fn emit_i16(&mut self, key: Key, val: i16) -> slog::Result {
+        self.0.push((key, OwnedValue::I16(val)));
+        Ok(())
+    }
+    fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
+        self.0.push((key, OwnedValue::U32(val)));
+        Ok(())
+    }
+    fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
+        self.0.push((key, OwnedValue::I32(val)));
+        Ok(())
+    }
+    fn emit_f32(&mut self, key: Key, val: f32) -> slog::Result {
+        self.0.push((key, OwnedValue::F32(val)));
+        Ok(())
+    }
+    fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
+        self.0.push((key, OwnedValue::U64(val)));
+        Ok(())
+    }
+    fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
+        self.0.push((key, OwnedValue::I64(val)));
+        Ok(())
+    }
+    fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
+        self.0.push((key, OwnedValue::F64(val)));
+        Ok(())
+    }
+    fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
+        self.0.push((key, OwnedValue::Usize(val)));
+        Ok(())
+    }
+    fn emit_isize(&mut self, key: Key, val: isize) -> slog::Result {
+        self.0.push((key, OwnedValue::Isize(val)));
+        Ok(())
+    }
+    fn emit_str(&mut self, key: Key, val: &str) -> slog::Result {
+        self.0.push((key, OwnedValue::Str(val.to_owned())));
+        Ok(())
+    }
+
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(
+        &mut self,
+        key: Key,
+        value: &dyn slog::SerdeValue,
+    ) -> slog::Result {
+        let value = Mutex::new(value.to_sendable());
+        self.0.push((key, OwnedValue::Serde(value)));
+        Ok(())
+    }
+}
+
+/// Serialized record.
+pub struct AsyncRecord {
+    // Borrowed for messages without formatting arguments
+    msg: Cow<'static, str>,
+    level: Level,
+    location: Box<slog::RecordLocation>,
+    tag: String,
+    logger_values: OwnedKVList,
+    kv: Box<dyn KV + Send>,
+    pid: Option<usize>,
+    // Estimated memory used by the record while queued
+    size: usize,
+    // Share of the memory budget held until the record is dropped
+    reservation: Option<MemoryReservation>,
+    // Slot of a channel above `MAX_PREALLOCATED_CHAN_SIZE`, held until the
+    // worker thread receives the record
+    slot: Option<ChanSlot>,
+    // When the record was sent, see `annotate_queue_time`
+    enqueued: Option<Instant>,
+    // Number of the record, see `strict_order`
+    seq: Option<u64>,
+    // Acknowledged once written, see `sync_checkpoint_level`
+    ack: Option<Sender<()>>,
+}
+
+/// Copies the record by serializing its key-values again.
+///
+/// Values keep their type, and `lazy_kv` values are computed. The copy
+/// doesn't count against a memory budget until sent.
+impl Clone for AsyncRecord {
+    fn clone(&self) -> Self {
+        // Serializing reverses the order of the key-values, so go through
+        // it twice
+        let mut ser = ToSendSerializer::new(ValuePolicy::KeepTyped, None, None);
+        self.as_record_values(|record, _| {
+            let mut reversed =
+                ToSendSerializer::new(ValuePolicy::KeepTyped, None, None);
+            record
+                .kv()
+                .serialize(record, &mut reversed)
+                .expect("`ToSendSerializer` can't fail");
+            let (reversed, _) = reversed.finish();
+            reversed
+                .serialize(record, &mut ser)
+                .expect("`ToSendSerializer` can't fail");
+        });
+        let (kv, _) = ser.finish();
+
+        AsyncRecord {
+            msg: self.msg.clone(),
+            level: self.level,
+            location: self.location.clone(),
+            tag: self.tag.clone(),
+            logger_values: self.logger_values.clone(),
+            pid: self.pid,
+            kv,
+            size: self.size,
+            reservation: None,
+            slot: None,
+            enqueued: self.enqueued,
+            // The copy may go to another channel, numbered independently
+            seq: None,
+            ack: None,
+        }
+    }
+}
+
+impl AsyncRecord {
+    /// Serializes a `Record` and an `OwnedKVList`.
+    pub fn from(record: &Record, logger_values: &OwnedKVList) -> Self {
+        Self::with_policy(record, logger_values, ValuePolicy::default())
+    }
+
+    /// Serializes a `Record` and an `OwnedKVList`, keeping the record
+    /// key-values according to `policy`.
+    pub fn with_policy(
+        record: &Record,
+        logger_values: &OwnedKVList,
+        policy: ValuePolicy,
+    ) -> Self {
+        let pid = find_pid(record, logger_values);
+        Self::with_pid(record, logger_values, pid, policy, None, None)
+    }
+
+    /// Serializes a `Record`, with the PID already found in `logger_values`,
+    /// keeping at most `max_kv_pairs` of its key-values, cut to
+    /// `max_value_len`.
+    fn with_pid(
+        record: &Record,
+        logger_values: &OwnedKVList,
+        pid: Option<usize>,
+        policy: ValuePolicy,
+        max_kv_pairs: Option<usize>,
+        max_value_len: Option<usize>,
+    ) -> Self {
+        let mut ser =
+            ToSendSerializer::new(policy, max_kv_pairs, max_value_len);
+        SENDING.with(|sending| sending.set(true));
+        let res = record.kv().serialize(record, &mut ser);
+        SENDING.with(|sending| sending.set(false));
+        res.expect("`ToSendSerializer` can't fail");
+
+        let (kv, kv_size) = ser.finish();
+        // Static messages, eg. `info!(log, "started")`, are not copied
+        let msg = match record.msg().as_str() {
+            Some(msg) => Cow::Borrowed(msg),
+            None => Cow::Owned(fmt::format(*record.msg())),
+        };
+        let tag = String::from(record.tag());
+        let msg_size = match msg {
+            Cow::Borrowed(_) => 0,
+            Cow::Owned(ref msg) => msg.len(),
+        };
+        let size = mem::size_of::<AsyncMsg>()
+            + mem::size_of::<slog::RecordLocation>()
+            + msg_size
+            + tag.len()
+            + kv_size;
+
+        AsyncRecord {
+            msg,
+            level: record.level(),
+            location: Box::new(*record.location()),
+            tag,
+            logger_values: logger_values.clone(),
+            pid,
+            kv,
+            size,
+            reservation: None,
+            slot: None,
+            enqueued: None,
+            seq: None,
+            ack: None,
+        }
+    }
+
+    /// Placeholder for `record`, whose serialization panicked, see
+    /// `AsyncCoreBuilder::catch_serialization_panics`.
+    fn serialization_panicked(
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Self {
+        let mut r = AsyncRecord::from(
+            &record!(
+                Level::Error,
+                "",
+                &format_args!("log serialization panicked"),
+                b!()
+            ),
+            logger_values,
+        );
+        r.level = record.level();
+        r.location = Box::new(*record.location());
+        r.tag = String::from(record.tag());
+        r
+    }
+
+    /// Add a key-value after the ones serialized from the record.
+    pub fn push_kv<V: slog::Value + Send + 'static>(
+        &mut self,
+        key: &'static str,
+        val: V,
+    ) {
+        self.size += mem::size_of::<(Box<dyn KV + Send>, SingleKV<V>)>();
+        take(&mut self.kv, |kv| {
+            Box::new((kv, SingleKV(static_key(key), val)))
+        });
+    }
+
+    /// Value of `key` in the record's or its logger's key-values, formatted,
+    /// leaving `lazy_kv` values out.
+    fn peek_value(&self, key: &'static str) -> Option<String> {
+        let mut ser = FindValueSerializer::new(key);
+        PEEKING.with(|peeking| peeking.set(true));
+        self.as_record_values(|record, logger_values| {
+            let _ = logger_values.serialize(record, &mut ser);
+            let _ = record.kv().serialize(record, &mut ser);
+        });
+        PEEKING.with(|peeking| peeking.set(false));
+        ser.value
+    }
+
+    /// Writes the record to a `Drain`.
+    pub fn log_to<D: Drain>(self, drain: &D) -> Result<D::Ok, D::Err> {
+        self.log_to_ref(drain)
+    }
+
+    /// Writes the record to a `Drain` without consuming it.
+    ///
+    /// Unlike `log_to`, the record is still available afterwards, eg. to be
+    /// written again to another `Drain`.
+    pub fn log_to_ref<D: Drain>(&self, drain: &D) -> Result<D::Ok, D::Err> {
+        let rs = RecordStatic {
+            location: &*self.location,
+            level: self.level,
+            tag: &self.tag,
+        };
+
+        drain.log(
+            &Record::new(
+                &rs,
+                &format_args!("{}", self.msg),
+                BorrowedKV(&self.kv),
+            ),
+            &self.logger_values,
+        )
+    }
+
+    /// Deconstruct this `AsyncRecord` into a record and `OwnedKVList`.
+    pub fn as_record_values(&self, mut f: impl FnMut(&Record, &OwnedKVList)) {
         let rs = RecordStatic {
             location: &*self.location,
             level: self.level,
             tag: &self.tag,
         };
 
-        f(
-            &Record::new(
-                &rs,
-                &format_args!("{}", self.msg),
-                BorrowedKV(&self.kv),
+        f(
+            &Record::new(
+                &rs,
+                &format_args!("{}", self.msg),
+                BorrowedKV(&self.kv),
+            ),
+            &self.logger_values,
+        )
+    }
+
+    /// Source file the record was logged from.
+    pub fn file(&self) -> &str {
+        self.location.file
+    }
+
+    /// Line the record was logged from.
+    pub fn line(&self) -> u32 {
+        self.location.line
+    }
+
+    /// Column the record was logged from.
+    pub fn column(&self) -> u32 {
+        self.location.column
+    }
+
+    /// Module the record was logged from.
+    pub fn module(&self) -> &str {
+        self.location.module
+    }
+
+    /// Function the record was logged from, when known.
+    pub fn function(&self) -> &str {
+        self.location.function
+    }
+
+    /// Formatted message of the record.
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    /// Replace the message of the record.
+    pub fn set_msg(&mut self, msg: String) {
+        self.msg = Cow::Owned(msg);
+    }
+
+    /// Level of the record.
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Replace the level of the record.
+    pub fn set_level(&mut self, level: Level) {
+        self.level = level;
+    }
+
+    /// Tag of the record.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Rename the record key-values called `from` to `to`.
+    ///
+    /// Logger key-values are left as they are.
+    pub fn rename_key(&mut self, from: &str, to: &'static str) {
+        let from = from.to_owned();
+        take(&mut self.kv, |kv| Box::new(RenamedKV { kv, from, to }));
+    }
+}
+
+/// Record key-values with a key renamed, see `AsyncRecord::rename_key`
+struct RenamedKV {
+    kv: Box<dyn KV + Send>,
+    from: String,
+    to: &'static str,
+}
+
+impl KV for RenamedKV {
+    fn serialize(
+        &self,
+        record: &Record,
+        serializer: &mut dyn Serializer,
+    ) -> slog::Result {
+        self.kv.serialize(
+            record,
+            &mut RenamingSerializer {
+                inner: serializer,
+                from: &self.from,
+                to: self.to,
+            },
+        )
+    }
+}
+
+/// Forwards key-values to another serializer, renaming a key.
+struct RenamingSerializer<'a> {
+    inner: &'a mut dyn Serializer,
+    from: &'a str,
+    to: &'static str,
+}
+
+impl<'a> RenamingSerializer<'a> {
+    fn key(&self, key: Key) -> Key {
+        if key == self.from {
+            static_key(self.to)
+        } else {
+            key
+        }
+    }
+}
+
+macro_rules! rename_emit {
+    ($($emit:ident($ty:ty)),*) => {
+        $(
+            fn $emit(&mut self, key: Key, val: $ty) -> slog::Result {
+                let key = self.key(key);
+                self.inner.$emit(key, val)
+            }
+        )*
+    };
+}
+
+impl<'a> Serializer for RenamingSerializer<'a> {
+    rename_emit!(
+        emit_bool(bool),
+        emit_char(char),
+        emit_u8(u8),
+        emit_i8(i8),
+        emit_u16(u16),
+        emit_i16(i16),
+        emit_u32(u32),
+        emit_i32(i32),
+        emit_f32(f32),
+        emit_u64(u64),
+        emit_i64(i64),
+        emit_f64(f64),
+        emit_usize(usize),
+        emit_isize(isize),
+        emit_str(&str),
+        emit_arguments(&fmt::Arguments)
+    );
+
+    fn emit_unit(&mut self, key: Key) -> slog::Result {
+        let key = self.key(key);
+        self.inner.emit_unit(key)
+    }
+
+    fn emit_none(&mut self, key: Key) -> slog::Result {
+        let key = self.key(key);
+        self.inner.emit_none(key)
+    }
+
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(
+        &mut self,
+        key: Key,
+        value: &dyn slog::SerdeValue,
+    ) -> slog::Result {
+        let key = self.key(key);
+        self.inner.emit_serde(key, value)
+    }
+}
+
+enum AsyncMsg {
+    Record(AsyncRecord),
+    // Disables a PID.
+    DisablePID(usize),
+    // Enables a PID.
+    EnablePID(usize),
+    // Replaces the enabled PIDs.
+    SetEnabledPIDs(HashSet<usize>),
+    // Routes the records of a PID to a drain
+    RoutePID(usize, BoxedDrain),
+    // Routes the records of a PID to the default drain again
+    UnroutePID(usize),
+    // Sets the emitted log levels
+    LogLevel(LevelFilter),
+    // Replaces the drain records are written to
+    ReplaceDrain(BoxedDrain),
+    // Routes the records tagged with a name to a drain
+    RegisterSink(String, BoxedDrain),
+    // Stops routing the records tagged with a name
+    UnregisterSink(String),
+    // Wakes the worker up to evict buffered records
+    MemoryPressure,
+    // Barrier acknowledged once everything sent before it is written
+    Flush(Sender<()>),
+    // Barrier acknowledged once the records with a tag sent before it are
+    // written
+    FlushTag(String, Sender<()>),
+    // Writes a record to the drain, answering with the panic message if it
+    // failed
+    Probe(AsyncRecord, Sender<Result<(), String>>),
+    // Answers with the records kept by `keep_recent`, oldest first
+    Checkpoint(Sender<Vec<String>>),
+    // Ends the task
+    Finish,
+}
+
+type BoxedDrain = Box<dyn Drain<Err = slog::Never, Ok = ()> + Send>;
+
+/// Levels emitted by the worker thread, see `PIDLogControl::level_at_least`.
+///
+/// Spelled out as severities, as `Level` orders the most severe first: a
+/// record at least as severe as `Info` compares `<=` to it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum LevelFilter {
+    AtLeast(Level),
+    AtMost(Level),
+}
+
+impl LevelFilter {
+    fn accepts(self, level: Level) -> bool {
+        match self {
+            LevelFilter::AtLeast(min) => level.is_at_least(min),
+            LevelFilter::AtMost(max) => max.is_at_least(level),
+        }
+    }
+}
+
+impl From<Level> for LevelFilter {
+    fn from(level: Level) -> Self {
+        LevelFilter::AtLeast(level)
+    }
+}
+
+thread_local! {
+    /// Records logged from a worker thread to its own `AsyncCore`
+    static REENTRANT: RefCell<Vec<AsyncRecord>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+// {{{ Worker
+/// State owned by the worker thread
+struct Worker {
+    sink: Sink,
+    enabled_pids: HashSet<usize>,
+    emit_log_level: Option<LevelFilter>,
+    error_context: Option<ErrorContext>,
+    sampler: Option<Sampler>,
+    memory_budget: Option<Arc<MemoryBudget>>,
+    stats: Arc<WorkerStats>,
+    max_restarts: Option<u32>,
+    // Number of times the drain panicked so far
+    restarts: u32,
+    retry: Option<RetryQueue>,
+    on_thread_start: Option<Box<ThreadStartFn>>,
+    transform: Option<Box<TransformFn>>,
+    // Drains registered by name, taking the records with a matching tag
+    sinks: HashMap<String, BoxedDrain>,
+    // Drains taking the records of a PID, see `PidRouter`
+    pid_routes: HashMap<usize, BoxedDrain>,
+    queue_time_key: Option<&'static str>,
+    schema_version: Option<u32>,
+    hash_chain: Option<HashChain>,
+    recent: Option<RecentRecords>,
+    // See `AsyncCoreBuilder::level_control`
+    level_control: Option<Arc<AtomicUsize>>,
+    lifecycle_events: Option<Sender<LifecycleEvent>>,
+    self_report: Option<SelfReport>,
+    // See `AsyncCoreBuilder::lifo`
+    lifo: bool,
+    // Message taken from the channel while collecting records to write
+    // newest first, handled next
+    stashed: Option<AsyncMsg>,
+    inter_record_delay: Option<Duration>,
+    // When the last record was written, see `inter_record_delay`
+    last_emit: Option<Instant>,
+    // State of the generator sampling records by `SAMPLE_RATE_KEY`
+    rng: u64,
+    reorder: Option<Reorder>,
+}
+
+impl Worker {
+    fn new(
+        sink: Sink,
+        error_context: Option<ErrorContext>,
+        sampler: Option<Sampler>,
+        memory_budget: Option<Arc<MemoryBudget>>,
+        stats: Arc<WorkerStats>,
+    ) -> Self {
+        Worker {
+            sink,
+            enabled_pids: HashSet::new(),
+            emit_log_level: None,
+            error_context,
+            sampler,
+            memory_budget,
+            stats,
+            max_restarts: None,
+            restarts: 0,
+            retry: None,
+            on_thread_start: None,
+            transform: None,
+            sinks: HashMap::new(),
+            pid_routes: HashMap::new(),
+            queue_time_key: None,
+            schema_version: None,
+            hash_chain: None,
+            recent: None,
+            level_control: None,
+            lifecycle_events: None,
+            self_report: None,
+            lifo: false,
+            stashed: None,
+            inter_record_delay: None,
+            last_emit: None,
+            // Any seed but 0 works, a random one avoids sampling the same
+            // records in every run
+            rng: RandomState::new().build_hasher().finish() | 1,
+            reorder: None,
+        }
+    }
+
+    fn run(mut self, rx: Receiver<AsyncMsg>, mut ctrl_rx: Receiver<AsyncMsg>) {
+        if let Some(on_thread_start) = self.on_thread_start.take() {
+            on_thread_start();
+        }
+        self.stats.set_ready();
+        self.lifecycle_event(LifecycleEvent::Started);
+        loop {
+            let msg = match self.stashed.take() {
+                Some(msg) => Some(msg),
+                None => recv(&rx, &mut ctrl_rx, self.deadline()),
+            };
+            let msg = match msg {
+                Some(msg) => msg,
+                None => {
+                    let now = Instant::now();
+                    if matches!(self.batch_deadline(), Some(d) if d <= now) {
+                        self.flush_batch();
+                    }
+                    self.report_stats(&rx, now);
+                    continue;
+                }
+            };
+            if self.self_report.is_some() {
+                self.report_stats(&rx, Instant::now());
+            }
+            self.relieve_memory_pressure();
+            match msg {
+                AsyncMsg::Record(r) if self.lifo => {
+                    self.process_newest_first(r, &rx);
+                }
+                AsyncMsg::Record(r) => {
+                    self.receive(r);
+                    self.process_reentrant();
+                }
+                AsyncMsg::EnablePID(pid) => {
+                    self.enabled_pids.insert(pid);
+                }
+                AsyncMsg::DisablePID(pid) => {
+                    self.enabled_pids.remove(&pid);
+                }
+                AsyncMsg::SetEnabledPIDs(pids) => {
+                    self.enabled_pids = pids;
+                }
+                AsyncMsg::RoutePID(pid, drain) => {
+                    self.pid_routes.insert(pid, drain);
+                }
+                AsyncMsg::UnroutePID(pid) => {
+                    self.pid_routes.remove(&pid);
+                }
+                AsyncMsg::LogLevel(filter) => {
+                    self.emit_log_level = Some(filter);
+                }
+                AsyncMsg::RegisterSink(name, drain) => {
+                    self.sinks.insert(name, drain);
+                }
+                AsyncMsg::UnregisterSink(name) => {
+                    self.sinks.remove(&name);
+                }
+                AsyncMsg::ReplaceDrain(drain) => {
+                    self.release_reordered();
+                    self.flush_batch();
+                    if !self.stats.degraded.load(Ordering::Relaxed) {
+                        self.sink = Sink::Drain(drain);
+                    }
+                }
+                AsyncMsg::MemoryPressure => {}
+                AsyncMsg::Flush(ack) => {
+                    self.release_reordered();
+                    self.retry_queued();
+                    self.flush_batch();
+                    let _ = ack.send(());
+                }
+                AsyncMsg::Probe(r, ack) => {
+                    let res = match self.sink {
+                        Sink::Drain(ref drain) => log_caught(&r, drain)
+                            .map_err(|payload| {
+                                panic_message(&*payload).to_string()
+                            }),
+                        _ => Ok(()),
+                    };
+                    let _ = ack.send(res);
+                }
+                AsyncMsg::Checkpoint(ack) => {
+                    let recent = match self.recent {
+                        Some(ref recent) => {
+                            recent.lines.iter().cloned().collect()
+                        }
+                        None => Vec::new(),
+                    };
+                    let _ = ack.send(recent);
+                }
+                AsyncMsg::FlushTag(tag, ack) => {
+                    self.release_reordered();
+                    if self.batch_has_tag(&tag) {
+                        self.flush_batch();
+                    }
+                    let _ = ack.send(());
+                }
+                AsyncMsg::Finish => {
+                    self.release_reordered();
+                    if let Some(ref mut sampler) = self.sampler {
+                        for summary in sampler.finish() {
+                            self.emit(summary);
+                        }
+                    }
+                    self.retry_queued();
+                    self.flush_batch();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Process the records logged from the worker thread while processing a
+    /// record, eg. by a drain logging its own errors.
+    ///
+    /// Records logged in turn while processing those are dropped to rule out
+    /// endless recursion.
+    fn process_reentrant(&mut self) {
+        let pending =
+            REENTRANT.with(|pending| mem::take(&mut *pending.borrow_mut()));
+        for r in pending {
+            self.process(r);
+        }
+        REENTRANT.with(|pending| pending.borrow_mut().clear());
+    }
+
+    /// Process `r`, or the records it lets through with `strict_order`.
+    fn receive(&mut self, r: AsyncRecord) {
+        let acked = r.ack.is_some();
+        let (ready, held) = match (self.reorder.as_mut(), r.seq) {
+            (Some(reorder), Some(seq)) => {
+                let ready = reorder.push(seq, r);
+                (ready, reorder.pending.contains_key(&seq))
+            }
+            _ => return self.process(r),
+        };
+        for r in ready {
+            self.process(r);
+        }
+        // Its sender is blocked until it is written, so it can't wait for
+        // records that may never arrive
+        if acked && held {
+            self.release_reordered();
+        }
+    }
+
+    /// Process all the records held back by `strict_order`, in order.
+    fn release_reordered(&mut self) {
+        let pending = match self.reorder {
+            Some(ref mut reorder) => reorder.release(),
+            None => return,
+        };
+        for r in pending {
+            self.process(r);
+        }
+    }
+
+    /// Process `r` and the records queued behind it newest first, see
+    /// `AsyncCoreBuilder::lifo`.
+    ///
+    /// Takes at most the messages queued when called, so a steady flow of
+    /// records can't keep the worker collecting forever, and stops at the
+    /// first message that isn't a record, handled next.
+    fn process_newest_first(
+        &mut self,
+        r: AsyncRecord,
+        rx: &Receiver<AsyncMsg>,
+    ) {
+        let mut stack = vec![r];
+        for _ in 0..rx.len() {
+            match rx.try_recv() {
+                Ok(AsyncMsg::Record(r)) => stack.push(r),
+                Ok(msg) => {
+                    self.stashed = Some(msg);
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        while let Some(r) = stack.pop() {
+            self.process(r);
+            self.process_reentrant();
+        }
+    }
+
+    /// Make room for a rejected record in the memory budget, see
+    /// `AsyncCoreBuilder::memory_budget`.
+    fn relieve_memory_pressure(&mut self) {
+        let budget = match self.memory_budget {
+            Some(ref budget) => budget.clone(),
+            None => return,
+        };
+        let needed = budget.needed.swap(0, Ordering::Relaxed);
+        if needed == 0 {
+            return;
+        }
+        let fits = || {
+            budget.used.load(Ordering::Relaxed).saturating_add(needed)
+                <= budget.limit
+        };
+        if let Some(ref mut error_context) = self.error_context {
+            while !fits() && error_context.ring.pop_front().is_some() {}
+        }
+        if let Some(ref mut retry) = self.retry {
+            while !fits() && retry.drop_oldest() {}
+        }
+        if !fits() {
+            // Written early rather than evicted
+            self.release_reordered();
+            self.flush_batch();
+        }
+    }
+
+    /// When the worker has to wake up without a message.
+    fn deadline(&self) -> Option<Instant> {
+        let report = self.self_report.as_ref().map(|report| report.next);
+        match (self.batch_deadline(), report) {
+            (Some(batch), Some(report)) => Some(batch.min(report)),
+            (batch, report) => batch.or(report),
+        }
+    }
+
+    /// Write a `self_report` record, if one is due.
+    fn report_stats(&mut self, rx: &Receiver<AsyncMsg>, now: Instant) {
+        let r = match self.self_report {
+            Some(ref mut report) if report.next <= now => {
+                report.next = now + report.interval;
+                let rejected = self.stats.rejected.load(Ordering::Relaxed);
+                let dropped = rejected - report.rejected;
+                report.rejected = rejected;
+                let depth = rx.len();
+                let capacity = report.capacity;
+                let utilization = if capacity == 0 {
+                    0.0
+                } else {
+                    depth as f64 / capacity as f64
+                };
+                let processed = self.stats.processed.load(Ordering::Relaxed);
+                AsyncRecord::from(
+                    &record!(
+                        Level::Info,
+                        "slog-async-stats",
+                        &format_args!("slog-async: stats"),
+                        b!(
+                            "depth" => depth,
+                            "capacity" => capacity,
+                            "utilization" => utilization,
+                            "dropped" => dropped,
+                            "processed" => processed
+                        )
+                    ),
+                    &o!().into(),
+                )
+            }
+            _ => return,
+        };
+        self.emit(self.with_schema_version(r));
+    }
+
+    /// Add the `schema_version` field to `r`, if set.
+    fn with_schema_version(&self, mut r: AsyncRecord) -> AsyncRecord {
+        if let Some(version) = self.schema_version {
+            r.push_kv(SCHEMA_VERSION_KEY, version);
+        }
+        r
+    }
+
+    fn batch_deadline(&self) -> Option<Instant> {
+        match self.sink {
+            Sink::Batch(ref batcher) => batcher.deadline,
+            _ => None,
+        }
+    }
+
+    fn flush_batch(&mut self) {
+        if let Sink::Batch(ref mut batcher) = self.sink {
+            match panic::catch_unwind(AssertUnwindSafe(|| batcher.flush())) {
+                Ok(flushed) => {
+                    self.stats
+                        .processed
+                        .fetch_add(flushed as u64, Ordering::Relaxed);
+                }
+                Err(payload) => self.drain_panicked(payload),
+            }
+        }
+    }
+
+    /// Whether the pending batch holds a record tagged `tag`.
+    fn batch_has_tag(&self, tag: &str) -> bool {
+        match self.sink {
+            Sink::Batch(ref batcher) => {
+                batcher.pending.iter().any(|r| r.tag == tag)
+            }
+            _ => false,
+        }
+    }
+
+    /// Carry on after the drain panicked, as long as `max_restarts` allows.
+    fn drain_panicked(&mut self, payload: Box<dyn Any + Send>) {
+        let max_restarts = match self.max_restarts {
+            Some(max_restarts) => max_restarts,
+            None => panic::resume_unwind(payload),
+        };
+        diagnostic(format_args!(
+            "drain panicked, record dropped: {}",
+            panic_message(&*payload)
+        ));
+        self.restarted(max_restarts);
+    }
+
+    /// Keep the record the drain failed to write for a retry, see
+    /// `AsyncCoreBuilder::retry_queue`.
+    fn drain_failed(&mut self, payload: Box<dyn Any + Send>) {
+        diagnostic(format_args!(
+            "drain failed, record kept for a retry: {}",
+            panic_message(&*payload)
+        ));
+        if let Some(max_restarts) = self.max_restarts {
+            self.restarted(max_restarts);
+        }
+    }
+
+    /// Count a restart, giving up on the drain after `max_restarts`.
+    fn restarted(&mut self, max_restarts: u32) {
+        self.restarts += 1;
+        if self.restarts > max_restarts {
+            self.sink = Sink::Disabled;
+            self.error_context = None;
+            if let Some(ref mut retry) = self.retry {
+                retry.records.clear();
+            }
+            self.stats.degraded.store(true, Ordering::Relaxed);
+            self.lifecycle_event(LifecycleEvent::Degraded);
+            eprintln!(
+                "slog-async: drain panicked {} times, async logging is \
+                 disabled",
+                self.restarts
+            );
+        }
+    }
+
+    fn process(&mut self, mut r: AsyncRecord) {
+        let ack = r.ack.take();
+        self.filter_and_emit(r);
+        if let Some(ack) = ack {
+            self.flush_batch();
+            let _ = ack.send(());
+        }
+    }
+
+    fn filter_and_emit(&mut self, r: AsyncRecord) {
+        if let Some(pid) = r.pid {
+            if !self.enabled_pids.contains(&pid) {
+                return;
+            }
+        }
+        let mut r = self.with_schema_version(r);
+        // This is a log we want to process, if its level is sufficiently high
+        let accepted = match self.level_control {
+            Some(ref max) => r.level.as_usize() <= max.load(Ordering::Relaxed),
+            None => matches!(
+                self.emit_log_level,
+                Some(filter) if filter.accepts(r.level)
+            ),
+        };
+        let emit = accepted && self.sample(&r);
+        let r = if emit {
+            if let (Some(key), Some(enqueued)) =
+                (self.queue_time_key, r.enqueued)
+            {
+                r.push_kv(key, enqueued.elapsed().as_micros() as u64);
+            }
+            let r = match self.transform {
+                Some(ref transform) => match transform(r) {
+                    Some(r) => r,
+                    None => return,
+                },
+                None => r,
+            };
+            // Last, as it looks through the key-values
+            if self.keep_sampled(&r) {
+                match self.emit(r) {
+                    Some(r) => r,
+                    None => return,
+                }
+            } else {
+                r
+            }
+        } else {
+            r
+        };
+        let res = match self.error_context {
+            Some(ref mut error_context) => error_context.push(r),
+            None => return,
+        };
+        if let Err(payload) = res {
+            self.drain_panicked(payload);
+        }
+    }
+
+    /// Wait until `inter_record_delay` elapsed since the last record was
+    /// written.
+    fn pace(&mut self) {
+        let delay = match self.inter_record_delay {
+            Some(delay) => delay,
+            None => return,
+        };
+        if let Some(last_emit) = self.last_emit {
+            let next = last_emit + delay;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            }
+        }
+        self.last_emit = Some(Instant::now());
+    }
+
+    /// Decide whether to keep `r` according to its `SAMPLE_RATE_KEY`.
+    fn keep_sampled(&mut self, r: &AsyncRecord) -> bool {
+        let value = r.peek_value(SAMPLE_RATE_KEY);
+        let rate = match value.and_then(|value| value.parse::<f64>().ok()) {
+            Some(rate) if !rate.is_nan() => rate,
+            _ => return true,
+        };
+        self.next_random() < rate
+    }
+
+    /// Next pseudo-random number in `[0, 1)`, from xorshift64*.
+    fn next_random(&mut self) -> f64 {
+        let mut x = self.rng;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng = x;
+        // The top 53 bits fill the mantissa of an `f64`
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64
+            / (1u64 << 53) as f64
+    }
+
+    /// Decide whether `r` passes sampling, writing summaries of the
+    /// suppressed records that are due.
+    fn sample(&mut self, r: &AsyncRecord) -> bool {
+        let (pass, summaries) = match self.sampler {
+            Some(ref mut sampler) => sampler.check(r, Instant::now()),
+            None => return true,
+        };
+        for summary in summaries {
+            self.emit(summary);
+        }
+        pass
+    }
+
+    /// Write `r` to the sink, returning it back unless a batch took it.
+    fn emit(&mut self, mut r: AsyncRecord) -> Option<AsyncRecord> {
+        self.pace();
+        if let Some(ref mut chain) = self.hash_chain {
+            let mut hash = String::new();
+            r.as_record_values(|record, logger_values| {
+                hash = chain.link(record, logger_values);
+            });
+            r.push_kv(chain.key, hash);
+        }
+        if let Some(ref mut recent) = self.recent {
+            recent.push(&r);
+        }
+        if let Some(drain) = self.sinks.get(r.tag.as_str()) {
+            let res = log_caught(&r, drain);
+            self.logged(res);
+            return Some(r);
+        }
+        if let Some(drain) = r.pid.and_then(|pid| self.pid_routes.get(&pid)) {
+            let res = log_caught(&r, drain);
+            self.logged(res);
+            return Some(r);
+        }
+        match self.sink {
+            Sink::Drain(_) if self.retry.is_some() => self.emit_retrying(r),
+            Sink::Drain(ref drain) => {
+                let res = log_caught(&r, drain);
+                self.logged(res);
+                Some(r)
+            }
+            Sink::Batch(ref mut batcher) => {
+                batcher.push(r);
+                if batcher.pending.len() >= batcher.max_records {
+                    self.flush_batch();
+                }
+                None
+            }
+            Sink::Chunked(ref mut chunker) => {
+                let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                    chunker.log(&r);
+                }));
+                self.logged(res);
+                Some(r)
+            }
+            #[cfg(feature = "tokio")]
+            Sink::Stream(ref tx) => {
+                // The memory budget only covers the records in the channel
+                let mut r = r;
+                r.reservation = None;
+                if tx.send(r).is_ok() {
+                    self.stats.processed.fetch_add(1, Ordering::Relaxed);
+                }
+                None
+            }
+            Sink::Disabled => Some(r),
+        }
+    }
+
+    /// Write `r` to the drain after the records waiting for a retry,
+    /// queueing it if any of them or `r` itself fails.
+    fn emit_retrying(&mut self, r: AsyncRecord) -> Option<AsyncRecord> {
+        self.retry_queued();
+        let waiting = self
+            .retry
+            .as_ref()
+            .is_some_and(|retry| !retry.records.is_empty());
+        if !waiting {
+            let res = match self.sink {
+                Sink::Drain(ref drain) => log_caught(&r, drain),
+                _ => return Some(r),
+            };
+            match res {
+                Ok(()) => {
+                    self.stats.processed.fetch_add(1, Ordering::Relaxed);
+                    return Some(r);
+                }
+                Err(payload) => self.drain_failed(payload),
+            }
+        }
+        match self.retry {
+            Some(ref mut retry) if matches!(self.sink, Sink::Drain(_)) => {
+                retry.push(r)
+            }
+            _ => return Some(r),
+        }
+        None
+    }
+
+    /// Write the records waiting for a retry, oldest first, until the drain
+    /// fails again.
+    fn retry_queued(&mut self) {
+        loop {
+            let next = self.retry.as_ref().and_then(|r| r.records.front());
+            let res = match (&self.sink, next) {
+                (Sink::Drain(drain), Some(r)) => log_caught(r, drain),
+                _ => return,
+            };
+            match res {
+                Ok(()) => {
+                    self.stats.processed.fetch_add(1, Ordering::Relaxed);
+                    if let Some(ref mut retry) = self.retry {
+                        retry.records.pop_front();
+                    }
+                }
+                Err(payload) => return self.drain_failed(payload),
+            }
+        }
+    }
+
+    fn lifecycle_event(&self, event: LifecycleEvent) {
+        if let Some(ref events) = self.lifecycle_events {
+            let _ = events.try_send(event);
+        }
+    }
+
+    /// Count a record written by `log_caught`.
+    fn logged(&mut self, res: thread::Result<()>) {
+        match res {
+            Ok(()) => {
+                self.stats.processed.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(payload) => self.drain_panicked(payload),
+        }
+    }
+}
+
+/// Write `r` to `drain`, catching a panic of the drain.
+fn log_caught(r: &AsyncRecord, drain: &BoxedDrain) -> thread::Result<()> {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        r.log_to_ref(drain).unwrap();
+    }))
+}
+
+/// Free the channel slot held by a record just received, see `ChanSlots`.
+fn release_slot(msg: AsyncMsg) -> AsyncMsg {
+    match msg {
+        AsyncMsg::Record(mut r) => {
+            r.slot = None;
+            AsyncMsg::Record(r)
+        }
+        msg => msg,
+    }
+}
+
+/// Receive the next message, preferring the control channel.
+///
+/// Returns `None` if `deadline` passed first.
+fn recv(
+    rx: &Receiver<AsyncMsg>,
+    ctrl_rx: &mut Receiver<AsyncMsg>,
+    deadline: Option<Instant>,
+) -> Option<AsyncMsg> {
+    loop {
+        match ctrl_rx.try_recv() {
+            Ok(msg) => return Some(msg),
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => {
+                *ctrl_rx = crossbeam_channel::never();
+            }
+        }
+        match rx.try_recv() {
+            Ok(msg) => return Some(release_slot(msg)),
+            Err(TryRecvError::Empty) => {}
+            // Every sender is gone, nothing is left to be logged.
+            Err(TryRecvError::Disconnected) => return Some(AsyncMsg::Finish),
+        }
+        let mut sel = Select::new();
+        sel.recv(ctrl_rx);
+        sel.recv(rx);
+        match deadline {
+            Some(deadline) => {
+                if sel.ready_deadline(deadline).is_err() {
+                    return None;
+                }
+            }
+            None => {
+                sel.ready();
+            }
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.stats.running.store(false, Ordering::Relaxed);
+        self.lifecycle_event(LifecycleEvent::Stopped);
+    }
+}
+
+/// Event in the life of the worker thread, see
+/// `AsyncCoreBuilder::lifecycle_events`
+///
+/// # Note
+///
+/// More variants may be added in the future, without considering it a
+/// breaking change.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum LifecycleEvent {
+    /// The worker thread is ready to process records
+    Started,
+    /// The drain panicked more than `max_restarts` times, async logging got
+    /// disabled
+    Degraded,
+    /// The worker thread ended, after writing all the records, or because
+    /// the drain panicked
+    Stopped,
+}
+
+/// Where the worker thread writes records to
+enum Sink {
+    Drain(BoxedDrain),
+    Batch(Batcher),
+    Chunked(Chunker),
+    // Records handed over to a `RecordStream`
+    #[cfg(feature = "tokio")]
+    Stream(tokio::sync::mpsc::UnboundedSender<AsyncRecord>),
+    // The drain panicked more than `max_restarts` times
+    Disabled,
+}
+
+/// Records processed by the worker thread of `AsyncCore::custom_stream` or
+/// `Async::into_stream`, for async consumers
+///
+/// The stream ends once the worker thread terminated.
+#[cfg(feature = "tokio")]
+pub type RecordStream =
+    tokio_stream::wrappers::UnboundedReceiverStream<AsyncRecord>;
+
+/// Drain receiving records in batches
+///
+/// See `AsyncCore::custom_batch`. Meant for downstreams preferring bulk
+/// submissions, eg. an HTTP API taking many records per request.
+pub trait BatchDrain: Send + 'static {
+    /// Write a batch of records, oldest first.
+    fn log_batch(&self, records: &[AsyncRecord]);
+}
+
+impl<B: BatchDrain + ?Sized> BatchDrain for Box<B> {
+    fn log_batch(&self, records: &[AsyncRecord]) {
+        (**self).log_batch(records)
+    }
+}
+
+/// Batch drain that can fail
+///
+/// Every `BatchDrain` is one that never fails. The worker thread only takes
+/// a `BatchDrain`, so a drain returning errors has to handle them, see
+/// `FuseBatch`.
+pub trait TryBatchDrain: Send + 'static {
+    /// Type returned by this drain when writing a batch failed
+    type Err;
+
+    /// Write a batch of records, oldest first.
+    fn try_log_batch(&self, records: &[AsyncRecord]) -> Result<(), Self::Err>;
+}
+
+impl<B: BatchDrain + ?Sized> TryBatchDrain for B {
+    type Err = slog::Never;
+
+    fn try_log_batch(
+        &self,
+        records: &[AsyncRecord],
+    ) -> Result<(), slog::Never> {
+        self.log_batch(records);
+        Ok(())
+    }
+}
+
+/// Writing many records with a single batch drain call
+pub trait BatchLogTo {
+    /// Write `records` to `drain` at once, oldest first.
+    fn log_batch_to<D: TryBatchDrain>(
+        records: &[AsyncRecord],
+        drain: &D,
+    ) -> Result<(), D::Err>;
+}
+
+impl BatchLogTo for AsyncRecord {
+    fn log_batch_to<D: TryBatchDrain>(
+        records: &[AsyncRecord],
+        drain: &D,
+    ) -> Result<(), D::Err> {
+        drain.try_log_batch(records)
+    }
+}
+
+/// `BatchDrain` panicking on errors of a `TryBatchDrain`, as `slog::Fuse`
+/// does
+///
+/// The worker thread handles the panic as that of any drain, see
+/// `AsyncCoreBuilder::max_restarts`.
+pub struct FuseBatch<B>(pub B);
+
+impl<B> BatchDrain for FuseBatch<B>
+where
+    B: TryBatchDrain,
+    B::Err: fmt::Debug,
+{
+    fn log_batch(&self, records: &[AsyncRecord]) {
+        if let Err(e) = self.0.try_log_batch(records) {
+            panic!("slog-async: batch drain failed: {:?}", e)
+        }
+    }
+}
+
+/// Records collected for a `BatchDrain`
+struct Batcher {
+    drain: Box<dyn BatchDrain>,
+    max_records: usize,
+    max_delay: Duration,
+    pending: Vec<AsyncRecord>,
+    // When the oldest pending record has to be written
+    deadline: Option<Instant>,
+}
+
+impl Batcher {
+    fn new(
+        drain: Box<dyn BatchDrain>,
+        max_records: usize,
+        max_delay: Duration,
+    ) -> Self {
+        Batcher {
+            drain,
+            max_records,
+            max_delay,
+            pending: Vec::with_capacity(max_records),
+            deadline: None,
+        }
+    }
+
+    fn push(&mut self, r: AsyncRecord) {
+        if self.pending.is_empty() {
+            self.deadline = Some(Instant::now() + self.max_delay);
+        }
+        self.pending.push(r);
+    }
+
+    /// Write the pending records, returning how many there were.
+    fn flush(&mut self) -> usize {
+        self.deadline = None;
+        if self.pending.is_empty() {
+            return 0;
+        }
+        let pending = mem::take(&mut self.pending);
+        self.drain.log_batch(&pending);
+        pending.len()
+    }
+}
+
+/// Drain receiving records split into size-limited chunks
+///
+/// See `AsyncCore::custom_chunked`. Meant for downstreams with a hard limit
+/// on message size, eg. UDP datagrams or a message queue, where a record with
+/// a large value would otherwise be rejected.
+pub trait ChunkedDrain: Send + 'static {
+    /// Serialize `record` into the bytes to deliver.
+    fn serialize(&self, record: &AsyncRecord) -> Vec<u8>;
+
+    /// Deliver one chunk of a record, in order of `OutputChunk::index`.
+    fn log_chunk(&self, chunk: &OutputChunk);
+}
+
+/// Part of a serialized record handed to a `ChunkedDrain`
+///
+/// Every chunk of a record shares its `record_id`, and carries its `index`
+/// and the `count` of chunks the record was split into. A record fitting
+/// into a single chunk is delivered with `index` 0 and `count` 1.
+///
+/// `encode` frames the chunk into `HEADER_LEN` bytes of header followed by
+/// the data. The header holds, big-endian, the record id as `u64`, the index
+/// as `u32` and the count as `u32`. Concatenating the data of the chunks of
+/// a record in order of their index yields the serialized record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputChunk<'a> {
+    /// Identifies the record, unique per worker thread
+    pub record_id: u64,
+    /// Position of this chunk, from 0
+    pub index: u32,
+    /// Number of chunks of the record
+    pub count: u32,
+    /// Part of the serialized record
+    pub data: &'a [u8],
+}
+
+impl<'a> OutputChunk<'a> {
+    /// Length of the header written by `encode`
+    pub const HEADER_LEN: usize = 16;
+
+    /// Frame the chunk as header and data.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::HEADER_LEN + self.data.len());
+        buf.extend_from_slice(&self.record_id.to_be_bytes());
+        buf.extend_from_slice(&self.index.to_be_bytes());
+        buf.extend_from_slice(&self.count.to_be_bytes());
+        buf.extend_from_slice(self.data);
+        buf
+    }
+
+    /// Parse a chunk framed by `encode`.
+    ///
+    /// Returns `None` if `buf` is shorter than the header.
+    pub fn decode(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < Self::HEADER_LEN {
+            return None;
+        }
+        let (header, data) = buf.split_at(Self::HEADER_LEN);
+        Some(OutputChunk {
+            record_id: u64::from_be_bytes(header[..8].try_into().unwrap()),
+            index: u32::from_be_bytes(header[8..12].try_into().unwrap()),
+            count: u32::from_be_bytes(header[12..].try_into().unwrap()),
+            data,
+        })
+    }
+}
+
+/// Splits records for a `ChunkedDrain`
+struct Chunker {
+    drain: Box<dyn ChunkedDrain>,
+    // Data bytes per chunk
+    chunk_data: usize,
+    next_id: u64,
+}
+
+impl Chunker {
+    fn new(drain: Box<dyn ChunkedDrain>, max_output_chunk: usize) -> Self {
+        Chunker {
+            drain,
+            chunk_data: max_output_chunk - OutputChunk::HEADER_LEN,
+            next_id: 0,
+        }
+    }
+
+    fn log(&mut self, r: &AsyncRecord) {
+        let bytes = self.drain.serialize(r);
+        let record_id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let count = bytes.len().div_ceil(self.chunk_data).max(1);
+        let count = u32::try_from(count).unwrap_or(u32::MAX);
+        for index in 0..count {
+            let start = index as usize * self.chunk_data;
+            let end = (start + self.chunk_data).min(bytes.len());
+            self.drain.log_chunk(&OutputChunk {
+                record_id,
+                index,
+                count,
+                data: &bytes[start.min(end)..end],
+            });
+        }
+    }
+}
+
+/// Counters updated by the worker thread
+#[derive(Default)]
+struct WorkerStats {
+    processed: AtomicU64,
+    // Records rejected because the channel or the memory budget was full
+    rejected: AtomicU64,
+    // Most messages seen queued in the main channel, see `max_queue_depth`
+    max_depth: AtomicUsize,
+    degraded: AtomicBool,
+    // Cleared once the worker ends, even by panicking
+    running: AtomicBool,
+    // Set once the `on_thread_start` hook returned
+    ready: AtomicBool,
+    ready_lock: Mutex<()>,
+    ready_cond: Condvar,
+}
+
+impl WorkerStats {
+    fn set_ready(&self) {
+        let _guard = self.ready_lock.lock().unwrap();
+        self.ready.store(true, Ordering::Release);
+        self.ready_cond.notify_all();
+    }
+
+    fn wait_ready(&self, timeout: Duration) -> bool {
+        let guard = self.ready_lock.lock().unwrap();
+        let _guard = self
+            .ready_cond
+            .wait_timeout_while(guard, timeout, |_| {
+                !self.ready.load(Ordering::Acquire)
+            })
+            .unwrap();
+        self.ready.load(Ordering::Acquire)
+    }
+}
+
+/// Buffer putting records back in order, see `AsyncCoreBuilder::strict_order`
+struct Reorder {
+    // Number of the next record to write
+    next: u64,
+    pending: BTreeMap<u64, AsyncRecord>,
+    capacity: usize,
+}
+
+impl Reorder {
+    fn new(capacity: usize) -> Self {
+        Reorder {
+            next: 0,
+            pending: BTreeMap::new(),
+            capacity,
+        }
+    }
+
+    /// Add record `seq`, returning the records now due, in order.
+    ///
+    /// Once more than `capacity` records are held back, the missing ones
+    /// are given up on.
+    fn push(&mut self, seq: u64, r: AsyncRecord) -> Vec<AsyncRecord> {
+        if seq < self.next {
+            // Arrived after being given up on
+            return vec![r];
+        }
+        self.pending.insert(seq, r);
+        if self.pending.len() > self.capacity {
+            if let Some(&first) = self.pending.keys().next() {
+                self.next = first;
+            }
+        }
+        let mut ready = vec![];
+        while let Some(r) = self.pending.remove(&self.next) {
+            ready.push(r);
+            self.next += 1;
+        }
+        ready
+    }
+
+    /// Take all the records held back, in order, giving up on the missing
+    /// ones.
+    fn release(&mut self) -> Vec<AsyncRecord> {
+        if let Some(&last) = self.pending.keys().next_back() {
+            self.next = last + 1;
+        }
+        mem::take(&mut self.pending).into_values().collect()
+    }
+}
+
+/// Periodic stats record, see `AsyncCoreBuilder::self_report`
+struct SelfReport {
+    interval: Duration,
+    next: Instant,
+    capacity: usize,
+    // Records rejected as of the previous report
+    rejected: u64,
+}
+
+impl SelfReport {
+    fn new(interval: Duration, capacity: usize) -> Self {
+        SelfReport {
+            interval,
+            next: Instant::now() + interval,
+            capacity,
+            rejected: 0,
+        }
+    }
+}
+
+/// Suppresses repeated values of a key, see `AsyncCoreBuilder::sample_by_key`
+struct Sampler {
+    key: &'static str,
+    window: Duration,
+    samples: HashMap<String, Sample>,
+    // When to look for samples with their window over next
+    next_sweep: Instant,
+}
+
+struct Sample {
+    level: Level,
+    until: Instant,
+    suppressed: u64,
+    // Message of the first suppressed record
+    msg: String,
+    first_suppressed: SystemTime,
+    last_suppressed: SystemTime,
+}
+
+impl Sampler {
+    fn new(key: &'static str, window: Duration) -> Self {
+        Sampler {
+            key,
+            window,
+            samples: HashMap::new(),
+            next_sweep: Instant::now() + window,
+        }
+    }
+
+    /// Returns whether `r` should be written and the summaries due.
+    fn check(
+        &mut self,
+        r: &AsyncRecord,
+        now: Instant,
+    ) -> (bool, Vec<AsyncRecord>) {
+        let mut summaries = vec![];
+        if now >= self.next_sweep {
+            self.next_sweep = now + self.window;
+            let key = self.key;
+            self.samples.retain(|value, sample| {
+                if now < sample.until {
+                    return true;
+                }
+                if sample.suppressed > 0 {
+                    summaries.push(summary(key, value, sample));
+                }
+                false
+            });
+        }
+
+        let value = match r.peek_value(self.key) {
+            Some(value) => value,
+            None => return (true, summaries),
+        };
+
+        let new_sample = Sample {
+            level: r.level,
+            until: now + self.window,
+            suppressed: 0,
+            msg: String::new(),
+            first_suppressed: UNIX_EPOCH,
+            last_suppressed: UNIX_EPOCH,
+        };
+        match self.samples.entry(value) {
+            Entry::Occupied(mut entry) => {
+                if now < entry.get().until {
+                    entry.get_mut().suppress(r);
+                    return (false, summaries);
+                }
+                let sample = entry.insert(new_sample);
+                if sample.suppressed > 0 {
+                    summaries.push(summary(self.key, entry.key(), &sample));
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(new_sample);
+            }
+        }
+        (true, summaries)
+    }
+
+    /// Summaries of all the suppressed records left.
+    fn finish(&mut self) -> Vec<AsyncRecord> {
+        let key = self.key;
+        self.samples
+            .drain()
+            .filter(|(_, sample)| sample.suppressed > 0)
+            .map(|(value, sample)| summary(key, &value, &sample))
+            .collect()
+    }
+}
+
+impl Sample {
+    fn suppress(&mut self, r: &AsyncRecord) {
+        let now = SystemTime::now();
+        if self.suppressed == 0 {
+            self.msg = r.msg().to_string();
+            self.first_suppressed = now;
+        }
+        self.last_suppressed = now;
+        self.suppressed += 1;
+    }
+}
+
+/// Summary of the records suppressed for `value`, see
+/// `AsyncCoreBuilder::sample_by_key` for the key-values.
+fn summary(key: &'static str, value: &str, sample: &Sample) -> AsyncRecord {
+    let mut r = AsyncRecord::from(
+        &record!(
+            Level::Info,
+            "slog-async",
+            &format_args!("slog-async: suppressed repeated records"),
+            b!(
+                "sampled_key" => key,
+                "sampled_value" => value,
+                "suppressed" => sample.suppressed,
+                "sample_msg" => &sample.msg,
+                "first_suppressed" => unix_millis(sample.first_suppressed),
+                "last_suppressed" => unix_millis(sample.last_suppressed)
+            )
+        ),
+        &o!().into(),
+    );
+    r.level = sample.level;
+    r
+}
+
+/// Milliseconds from the Unix epoch to `time`.
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Drain failing writes it returns an error for, see
+/// `AsyncCoreBuilder::retry_queue`
+///
+/// The error unwinds to the worker thread, which queues the record for a
+/// retry. Unlike a panic of `slog::Fuse`, it doesn't go through the panic
+/// hook, so isn't printed as one. Used outside of `AsyncCore`, nothing
+/// catches it.
+pub struct RetryOnErr<D>(pub D);
+
+impl<D> Drain for RetryOnErr<D>
+where
+    D: Drain<Ok = ()>,
+    D::Err: fmt::Debug,
+{
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Result<(), slog::Never> {
+        self.0.log(record, logger_values).map_err(|e| {
+            panic::resume_unwind(Box::new(format!("drain error: {:?}", e)))
+        })
+    }
+
+    fn is_enabled(&self, level: Level) -> bool {
+        self.0.is_enabled(level)
+    }
+
+    fn flush(&self) -> Result<(), slog::FlushError> {
+        self.0.flush()
+    }
+}
+
+/// Records the drain failed to write, see `AsyncCoreBuilder::retry_queue`
+struct RetryQueue {
+    capacity: usize,
+    records: VecDeque<AsyncRecord>,
+}
+
+impl RetryQueue {
+    fn new(capacity: usize) -> Self {
+        RetryQueue {
+            capacity,
+            records: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, r: AsyncRecord) {
+        if self.records.len() == self.capacity {
+            self.drop_oldest();
+        }
+        self.records.push_back(r);
+    }
+
+    /// Drop the oldest record, returning `false` if there's none.
+    fn drop_oldest(&mut self) -> bool {
+        if self.records.pop_front().is_none() {
+            return false;
+        }
+        diagnostic(format_args!("retry queue full, oldest record dropped"));
+        true
+    }
+}
+
+/// Ring buffer of recent records, written to a secondary drain once a
+/// record at or above `threshold` comes by.
+struct ErrorContext {
+    threshold: Level,
+    ring_size: usize,
+    ring: VecDeque<AsyncRecord>,
+    drain: BoxedDrain,
+}
+
+impl ErrorContext {
+    /// Keep `r`, or write the ring and `r` if it's at or above `threshold`,
+    /// catching a panic of the drain.
+    fn push(&mut self, r: AsyncRecord) -> thread::Result<()> {
+        if r.level.is_at_least(self.threshold) {
+            for context in self.ring.drain(..) {
+                log_caught(&context, &self.drain)?;
+            }
+            return log_caught(&r, &self.drain);
+        }
+        if self.ring_size > 0 {
+            if self.ring.len() == self.ring_size {
+                self.ring.pop_front();
+            }
+            self.ring.push_back(r);
+        }
+        Ok(())
+    }
+}
+// }}}
+
+/// Interval at which callers blocked by `OverflowStrategy::BlockAbove` check
+/// the channel.
+const BLOCK_ABOVE_POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+/// Interval between calls of the `AsyncCore::flush_with_progress` callback.
+const FLUSH_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Send a flush barrier through the control and the main channel, returning
+/// the receiver of the worker's acknowledgements.
+fn send_flush(
+    ctrl_sender: &Sender<AsyncMsg>,
+    sender: &Sender<AsyncMsg>,
+) -> AsyncResult<Receiver<()>> {
+    let (ack_tx, ack_rx) = crossbeam_channel::bounded(2);
+    ctrl_sender.send(AsyncMsg::Flush(ack_tx.clone()))?;
+    sender.send(AsyncMsg::Flush(ack_tx))?;
+    Ok(ack_rx)
+}
+
+/// Wait until the worker got to both barriers sent by `send_flush`.
+fn wait_flushed(ack_rx: &Receiver<()>) -> AsyncResult<()> {
+    ack_rx.recv()?;
+    ack_rx.recv()?;
+    Ok(())
+}
+
+impl Drop for AsyncCore {
+    fn drop(&mut self) {
+        let _err: Result<(), Box<dyn std::error::Error>> = {
+            || {
+                let join = self.join.lock()?.take();
+                if join.is_some() || self.manual {
+                    let _ = self.get_sender()?.send(AsyncMsg::Finish);
+                }
+                if let Some(join) = join {
+                    if join.thread().id() != thread::current().id() {
+                        // A custom Drain::log implementation could dynamically
+                        // swap out the logger which eventually invokes
+                        // AsyncCore::drop in the worker thread.
+                        // If we drop the AsyncCore inside the logger thread,
+                        // this join() either panic or dead-lock.
+                        // TODO: Figure out whether skipping join() instead of
+                        // panicking is desirable.
+                        join.join().map_err(join_error)?;
+                    }
+                }
+                Ok(())
+            }
+        }();
+    }
+}
+// }}}
+
+/// Behavior used when the channel is full.
+///
+/// For custom behavior, eg. writing records to another drain instead, see
+/// `AsyncBuilder::overflow_with` and `AsyncBuilder::overflow_handler`.
+///
+/// # Note
+///
+/// More variants may be added in the future, without considering it a breaking change.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum OverflowStrategy {
+    /// The message gets dropped and a message with number of dropped is produced once there's
+    /// space.
+    ///
+    /// This is the default.
+    ///
+    /// Note that the message with number of dropped messages takes one slot in the channel as
+    /// well.
+    DropAndReport,
+    /// The message gets dropped silently.
+    Drop,
+    /// The caller is blocked until there's enough space.
+    Block,
+    /// The caller is blocked while the channel holds at least this many
+    /// messages, applying backpressure before the channel is full.
+    ///
+    /// Blocked callers poll the channel. Records that still find the channel
+    /// full, with a watermark above the channel size, are dropped and
+    /// reported like with `DropAndReport`. Watermarks are capped at
+    /// `usize::MAX >> 3`.
+    BlockAbove(usize),
+    /// While the channel is full, one out of every `keep_every` records
+    /// finding it full is kept and the others are dropped silently.
+    ///
+    /// A middle ground between `Drop` and `DropAndReport` under sustained
+    /// overload, keeping a representative trickle of records. Kept records
+    /// wait for room in the channel, as with `Block`, so they hold the
+    /// logging thread until the worker catches up. Best-effort: the count is
+    /// shared by all logging threads. `keep_every` must be at least 1, and is
+    /// capped at `usize::MAX >> 3`, as are watermarks of `BlockAbove`.
+    DropAndSample {
+        /// Keep one record out of this many
+        keep_every: u32,
+    },
+    #[doc(hidden)]
+    DoNotMatchAgainstThisAndReadTheDocs,
+}
+
+impl OverflowStrategy {
+    /// Why the strategy can't be used, if it can't.
+    fn invalid(self) -> Option<&'static str> {
+        if self == OverflowStrategy::DoNotMatchAgainstThisAndReadTheDocs {
+            Some(
+                "DoNotMatchAgainstThisAndReadTheDocs is not an overflow \
+                 strategy",
+            )
+        } else if self == (OverflowStrategy::DropAndSample { keep_every: 0 }) {
+            Some("DropAndSample needs a keep_every of at least 1")
+        } else {
+            None
+        }
+    }
+}
+
+/// What to do with a record finding the channel full, see
+/// `AsyncCoreBuilder::overflow_handler`
+pub enum OverflowDecision {
+    /// Reject the record, as without a handler.
+    Drop,
+    /// Wait for room in the channel.
+    Block,
+    /// Write the record to this drain instead, on the logging thread.
+    Redirect(Arc<dyn Drain<Ok = (), Err = slog::Never> + Send + Sync>),
+}
+
+/// `OverflowStrategy` that can be atomically replaced while `Async` is in use.
+struct SharedOverflowStrategy(AtomicUsize);
+
+impl SharedOverflowStrategy {
+    fn new(overflow_strategy: OverflowStrategy) -> Self {
+        let s = SharedOverflowStrategy(AtomicUsize::new(0));
+        // Checked by `AsyncBuilder::overflow_strategy` already
+        let _ = s.store(overflow_strategy);
+        s
+    }
+
+    // The variant is kept in the low three bits, below the watermark of
+    // `BlockAbove` or the rate of `DropAndSample`
+    fn load(&self) -> OverflowStrategy {
+        let v = self.0.load(Ordering::Relaxed);
+        match v & 7 {
+            1 => OverflowStrategy::Drop,
+            2 => OverflowStrategy::Block,
+            3 => OverflowStrategy::BlockAbove(v >> 3),
+            4 => OverflowStrategy::DropAndSample {
+                keep_every: u32::try_from(v >> 3).unwrap_or(u32::MAX),
+            },
+            _ => OverflowStrategy::DropAndReport,
+        }
+    }
+
+    /// Replace the strategy, unless `OverflowStrategy::invalid`, keeping the
+    /// current one.
+    fn store(&self, overflow_strategy: OverflowStrategy) -> AsyncResult<()> {
+        if let Some(reason) = overflow_strategy.invalid() {
+            return Err(AsyncError::Fatal(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                reason,
+            ))));
+        }
+        let v = match overflow_strategy {
+            OverflowStrategy::DropAndReport => 0,
+            OverflowStrategy::Drop => 1,
+            OverflowStrategy::Block => 2,
+            OverflowStrategy::BlockAbove(watermark) => {
+                watermark.min(usize::MAX >> 3) << 3 | 3
+            }
+            OverflowStrategy::DropAndSample { keep_every } => {
+                let keep_every =
+                    usize::try_from(keep_every).unwrap_or(usize::MAX);
+                keep_every.min(usize::MAX >> 3) << 3 | 4
+            }
+            // Rejected above
+            OverflowStrategy::DoNotMatchAgainstThisAndReadTheDocs => 0,
+        };
+        self.0.store(v, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// `Async` builder
+pub struct AsyncBuilder<D>
+where
+    D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+{
+    core: AsyncCoreBuilder<D>,
+    options: AsyncOptions,
+}
+
+type OnOverflowFn = dyn Fn(usize) + Send + Sync;
+
+/// Overflow strategies overriding the default one, indexed by
+/// `Level::as_usize() - 1`
+type LevelOverflowStrategies = [Option<OverflowStrategy>; 6];
+
+/// Options of `Async` itself, rather than of its `AsyncCore`
+struct AsyncOptions {
+    overflow_strategy: OverflowStrategy,
+    level_overflow_strategies: LevelOverflowStrategies,
+    drop_report_interval: Option<Duration>,
+    dropped_report_level: Level,
+    dropped_report_tag: String,
+    on_overflow: Option<AssertUnwindSafe<Box<OnOverflowFn>>>,
+}
+
+impl Default for AsyncOptions {
+    fn default() -> Self {
+        AsyncOptions {
+            overflow_strategy: OverflowStrategy::DropAndReport,
+            level_overflow_strategies: [None; 6],
+            drop_report_interval: None,
+            dropped_report_level: Level::Error,
+            dropped_report_tag: DROPPED_REPORT_TAG.to_string(),
+            on_overflow: None,
+        }
+    }
+}
+
+impl<D> AsyncBuilder<D>
+where
+    D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+{
+    fn new(drain: D) -> AsyncBuilder<D> {
+        AsyncBuilder {
+            core: AsyncCoreBuilder::new(drain),
+            options: AsyncOptions::default(),
+        }
+    }
+
+    /// Set channel size used to send logging records to worker thread. When
+    /// buffer is full `AsyncCore` will start returning `AsyncError::Full`.
+    pub fn chan_size(self, s: usize) -> Self {
+        AsyncBuilder {
+            core: self.core.chan_size(s),
+            ..self
+        }
+    }
+
+    /// Keep the last `slots` slots of the channel for records at or above
+    /// `level`.
+    ///
+    /// See `AsyncCoreBuilder::reserved_capacity`.
+    pub fn reserved_capacity(self, slots: usize, level: Level) -> Self {
+        AsyncBuilder {
+            core: self.core.reserved_capacity(slots, level),
+            ..self
+        }
+    }
+
+    /// Sets what will happen if the channel is full.
+    pub fn overflow_strategy(
+        mut self,
+        overflow_strategy: OverflowStrategy,
+    ) -> Self {
+        if let Some(reason) = overflow_strategy.invalid() {
+            panic!("Invalid variant: {}", reason)
+        }
+        self.options.overflow_strategy = overflow_strategy;
+        self
+    }
+
+    /// Sets what will happen to records at `level` if the channel is full,
+    /// overriding `overflow_strategy` for them.
+    ///
+    /// Eg. `OverflowStrategy::Block` for `Level::Error` and
+    /// `Level::Critical` makes sure errors are never dropped, while less
+    /// important records still are. Each level is configured on its own.
+    /// `Async::set_overflow_strategy` only replaces the default strategy,
+    /// not the ones set here.
+    pub fn overflow_strategy_for_level(
+        mut self,
+        level: Level,
+        overflow_strategy: OverflowStrategy,
+    ) -> Self {
+        if let Some(reason) = overflow_strategy.invalid() {
+            panic!("Invalid variant: {}", reason)
+        }
+        self.options.level_overflow_strategies[level.as_usize() - 1] =
+            Some(overflow_strategy);
+        self
+    }
+
+    /// Report dropped records at most once per `interval`.
+    ///
+    /// With `OverflowStrategy::DropAndReport` the number of records dropped
+    /// in between is accumulated into the next report. The final count is
+    /// always reported when `Async` is dropped.
+    pub fn drop_report_interval(mut self, interval: Duration) -> Self {
+        self.options.drop_report_interval = Some(interval);
+        self
+    }
+
+    /// Log reports of dropped records at `level`, `Level::Error` by default.
+    ///
+    /// Eg. so that losing debug records under load doesn't trigger alerts
+    /// meant for errors.
+    pub fn dropped_report_level(mut self, level: Level) -> Self {
+        self.options.dropped_report_level = level;
+        self
+    }
+
+    /// Tag reports of dropped records with `tag`, `DROPPED_REPORT_TAG` by
+    /// default.
+    pub fn dropped_report_tag(mut self, tag: String) -> Self {
+        self.options.dropped_report_tag = tag;
+        self
+    }
+
+    /// Call `f` with the number of records dropped instead of logging a
+    /// report of them.
+    ///
+    /// With `OverflowStrategy::DropAndReport` and
+    /// `OverflowStrategy::BlockAbove` the report is otherwise a record sent
+    /// to the drain that's already falling behind; `f` can eg. bump a
+    /// metric or write to stderr instead. It's called on a logging thread,
+    /// whenever a report would be sent, with the number of records dropped
+    /// since the previous call, and respects `drop_report_interval` and
+    /// `PIDLogControl::set_drop_reporting`.
+    pub fn on_overflow<F>(mut self, f: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.options.on_overflow = Some(AssertUnwindSafe(Box::new(f)));
+        self
+    }
+
+    /// Keep the last `ring_size` records and write them to `context_drain`
+    /// whenever a record at or above `threshold` is processed.
+    ///
+    /// See `AsyncCoreBuilder::context_on_error`.
+    pub fn context_on_error<C>(
+        self,
+        threshold: Level,
+        ring_size: usize,
+        context_drain: C,
+    ) -> Self
+    where
+        C: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        AsyncBuilder {
+            core: self.core.context_on_error(
+                threshold,
+                ring_size,
+                context_drain,
+            ),
+            ..self
+        }
+    }
+
+    /// Call `f` whenever the channel becomes full.
+    ///
+    /// See `AsyncCoreBuilder::on_full`.
+    pub fn on_full<F>(self, f: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        AsyncBuilder {
+            core: self.core.on_full(f),
+            ..self
+        }
+    }
+
+    /// Let `handler` decide what happens to each record finding the channel
+    /// full.
+    ///
+    /// See `AsyncCoreBuilder::overflow_handler`.
+    pub fn overflow_handler<F>(self, handler: F) -> Self
+    where
+        F: Fn(&AsyncRecord) -> OverflowDecision + Send + Sync + 'static,
+    {
+        AsyncBuilder {
+            core: self.core.overflow_handler(handler),
+            ..self
+        }
+    }
+
+    /// Hand each record finding the channel full to `f` instead of dropping
+    /// it.
+    ///
+    /// See `AsyncCoreBuilder::overflow_with`.
+    pub fn overflow_with<F>(self, f: F) -> Self
+    where
+        F: Fn(AsyncRecord) + Send + Sync + 'static,
+    {
+        AsyncBuilder {
+            core: self.core.overflow_with(f),
+            ..self
+        }
+    }
+
+    /// Wait for room in the channel instead of dropping records, once it
+    /// stayed full for `duration`.
+    ///
+    /// See `AsyncCoreBuilder::sync_escape_after`.
+    pub fn sync_escape_after(self, duration: Duration) -> Self {
+        AsyncBuilder {
+            core: self.core.sync_escape_after(duration),
+            ..self
+        }
+    }
+
+    /// Measure the time sending each record to the channel takes.
+    ///
+    /// See `AsyncCoreBuilder::measure_send_latency`.
+    pub fn measure_send_latency(self, enabled: bool) -> Self {
+        AsyncBuilder {
+            core: self.core.measure_send_latency(enabled),
+            ..self
+        }
+    }
+
+    /// Call `f` whenever a record is sent again after the channel was full.
+    ///
+    /// See `AsyncCoreBuilder::on_recovered`.
+    pub fn on_recovered<F>(self, f: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        AsyncBuilder {
+            core: self.core.on_recovered(f),
+            ..self
+        }
+    }
+
+    /// Call `report` for records taking longer than `threshold` to serialize.
+    ///
+    /// See `AsyncCoreBuilder::slow_serialization`.
+    pub fn slow_serialization<F>(self, threshold: Duration, report: F) -> Self
+    where
+        F: Fn(&Record, Duration) + Send + Sync + 'static,
+    {
+        AsyncBuilder {
+            core: self.core.slow_serialization(threshold, report),
+            ..self
+        }
+    }
+
+    /// Catch panics while serializing records on the logging thread.
+    ///
+    /// See `AsyncCoreBuilder::catch_serialization_panics`.
+    pub fn catch_serialization_panics(self, enabled: bool) -> Self {
+        AsyncBuilder {
+            core: self.core.catch_serialization_panics(enabled),
+            ..self
+        }
+    }
+
+    /// Add the milliseconds elapsed since the drain was built to every
+    /// record, under `key`.
+    ///
+    /// See `AsyncCoreBuilder::elapsed_field`.
+    pub fn elapsed_field(self, key: &'static str) -> Self {
+        AsyncBuilder {
+            core: self.core.elapsed_field(key),
+            ..self
+        }
+    }
+
+    /// Add the time each record spent queued, in microseconds, under `key`.
+    ///
+    /// See `AsyncCoreBuilder::annotate_queue_time`.
+    pub fn annotate_queue_time(self, key: &'static str) -> Self {
+        AsyncBuilder {
+            core: self.core.annotate_queue_time(key),
+            ..self
+        }
+    }
+
+    /// Add `version` under `SCHEMA_VERSION_KEY` to every record.
+    ///
+    /// See `AsyncCoreBuilder::schema_version`.
+    pub fn schema_version(self, version: u32) -> Self {
+        AsyncBuilder {
+            core: self.core.schema_version(version),
+            ..self
+        }
+    }
+
+    /// Limit the chunks handed to a `ChunkedDrain` to `bytes`, header
+    /// included.
+    ///
+    /// See `AsyncCoreBuilder::max_output_chunk`.
+    pub fn max_output_chunk(self, bytes: usize) -> Self {
+        AsyncBuilder {
+            core: self.core.max_output_chunk(bytes),
+            ..self
+        }
+    }
+
+    /// Emit the records up to the level stored in `max_level`.
+    ///
+    /// See `AsyncCoreBuilder::level_control`.
+    pub fn level_control(self, max_level: Arc<AtomicUsize>) -> Self {
+        AsyncBuilder {
+            core: self.core.level_control(max_level),
+            ..self
+        }
+    }
+
+    /// Seed the generator deciding which records to keep by
+    /// `SAMPLE_RATE_KEY`.
+    ///
+    /// See `AsyncCoreBuilder::sampling_seed`.
+    pub fn sampling_seed(self, seed: u64) -> Self {
+        AsyncBuilder {
+            core: self.core.sampling_seed(seed),
+            ..self
+        }
+    }
+
+    /// Add a hash chaining each written record to the previous one under
+    /// `key`.
+    ///
+    /// See `AsyncCoreBuilder::hash_chain`.
+    pub fn hash_chain(self, key: &'static str) -> Self {
+        AsyncBuilder {
+            core: self.core.hash_chain(key),
+            ..self
+        }
+    }
+
+    /// Keep the last `count` records written for `checkpoint`.
+    ///
+    /// See `AsyncCoreBuilder::keep_recent`.
+    pub fn keep_recent(self, count: usize) -> Self {
+        AsyncBuilder {
+            core: self.core.keep_recent(count),
+            ..self
+        }
+    }
+
+    /// Wait at least `delay` between writing two records.
+    ///
+    /// See `AsyncCoreBuilder::inter_record_delay`.
+    pub fn inter_record_delay(self, delay: Duration) -> Self {
+        AsyncBuilder {
+            core: self.core.inter_record_delay(delay),
+            ..self
+        }
+    }
+
+    /// Wait for records at or above `level` to be written before returning
+    /// from logging them.
+    ///
+    /// See `AsyncCoreBuilder::sync_checkpoint_level`.
+    pub fn sync_checkpoint_level(self, level: Level) -> Self {
+        AsyncBuilder {
+            core: self.core.sync_checkpoint_level(level),
+            ..self
+        }
+    }
+
+    /// Write records in the order they were logged in, across all threads.
+    ///
+    /// See `AsyncCoreBuilder::strict_order`.
+    pub fn strict_order(self, buffer_size: usize) -> Self {
+        AsyncBuilder {
+            core: self.core.strict_order(buffer_size),
+            ..self
+        }
+    }
+
+    /// Experimental: write the queued records newest first, for forensic
+    /// dumps only.
+    ///
+    /// See `AsyncCoreBuilder::lifo`.
+    pub fn lifo(self, lifo: bool) -> Self {
+        AsyncBuilder {
+            core: self.core.lifo(lifo),
+            ..self
+        }
+    }
+
+    /// Send `LifecycleEvent`s of the worker thread to `events`.
+    ///
+    /// See `AsyncCoreBuilder::lifecycle_events`.
+    pub fn lifecycle_events(self, events: Sender<LifecycleEvent>) -> Self {
+        AsyncBuilder {
+            core: self.core.lifecycle_events(events),
+            ..self
+        }
+    }
+
+    /// Set how record key-values are kept when serialized for the worker
+    /// thread.
+    ///
+    /// See `AsyncCoreBuilder::value_policy`.
+    pub fn value_policy(self, policy: ValuePolicy) -> Self {
+        AsyncBuilder {
+            core: self.core.value_policy(policy),
+            ..self
+        }
+    }
+
+    /// Keep at most `max` key-values of each record.
+    ///
+    /// See `AsyncCoreBuilder::max_kv_pairs`.
+    pub fn max_kv_pairs(self, max: usize) -> Self {
+        AsyncBuilder {
+            core: self.core.max_kv_pairs(max),
+            ..self
+        }
+    }
+
+    /// Cut string and formatted record values to at most `max` bytes.
+    ///
+    /// See `AsyncCoreBuilder::max_value_len`.
+    pub fn max_value_len(self, max: usize) -> Self {
+        AsyncBuilder {
+            core: self.core.max_value_len(max),
+            ..self
+        }
+    }
+
+    /// Reuse the serialized logger values of up to `capacity` loggers.
+    ///
+    /// See `AsyncCoreBuilder::logger_values_cache`.
+    pub fn logger_values_cache(self, capacity: usize) -> Self {
+        AsyncBuilder {
+            core: self.core.logger_values_cache(capacity),
+            ..self
+        }
+    }
+
+    /// Write records logged after the worker thread terminated to `drain`.
+    ///
+    /// See `AsyncCoreBuilder::worker_gone_fallback`.
+    pub fn worker_gone_fallback<F>(self, drain: F) -> Self
+    where
+        F: Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        AsyncBuilder {
+            core: self.core.worker_gone_fallback(drain),
+            ..self
+        }
+    }
+
+    /// Keep the worker thread going when the drain panics, up to `max` times.
+    ///
+    /// See `AsyncCoreBuilder::max_restarts`.
+    pub fn max_restarts(self, max: u32) -> Self {
+        AsyncBuilder {
+            core: self.core.max_restarts(max),
+            ..self
+        }
+    }
+
+    /// Keep up to `capacity` records the drain failed to write, and write
+    /// them again once it recovers.
+    ///
+    /// See `AsyncCoreBuilder::retry_queue`.
+    pub fn retry_queue(self, capacity: usize) -> Self {
+        AsyncBuilder {
+            core: self.core.retry_queue(capacity),
+            ..self
+        }
+    }
+
+    /// Write only the first record for each value of `key` within `window`.
+    ///
+    /// See `AsyncCoreBuilder::sample_by_key`.
+    pub fn sample_by_key(self, key: &'static str, window: Duration) -> Self {
+        AsyncBuilder {
+            core: self.core.sample_by_key(key, window),
+            ..self
+        }
+    }
+
+    /// Write a record with the state of the channel every `interval`.
+    ///
+    /// See `AsyncCoreBuilder::self_report`.
+    pub fn self_report(self, interval: Duration) -> Self {
+        AsyncBuilder {
+            core: self.core.self_report(interval),
+            ..self
+        }
+    }
+
+    /// Limit the memory used by records waiting for the worker thread to
+    /// `bytes`.
+    ///
+    /// Records that don't fit are handled like on channel overflow: with
+    /// `OverflowStrategy::Block` logging waits until they fit.
+    ///
+    /// See `AsyncCoreBuilder::memory_budget`.
+    pub fn memory_budget(self, bytes: usize) -> Self {
+        AsyncBuilder {
+            core: self.core.memory_budget(bytes),
+            ..self
+        }
+    }
+
+    /// Keep track of the memory used by records waiting for the worker
+    /// thread.
+    ///
+    /// See `AsyncCoreBuilder::track_memory`.
+    pub fn track_memory(self, enabled: bool) -> Self {
+        AsyncBuilder {
+            core: self.core.track_memory(enabled),
+            ..self
+        }
+    }
+
+    /// Configure a name to be used for the background thread.
+    ///
+    /// See `AsyncCoreBuilder::thread_name`.
+    ///
+    /// # Panics
+    ///
+    /// If an empty name, or a name with '\0' is passed.
+    pub fn thread_name(self, name: String) -> Self {
+        AsyncBuilder {
+            core: self.core.thread_name(name),
+            ..self
+        }
+    }
+
+    /// Cache a sender per logging thread, enabled by default.
+    ///
+    /// See `AsyncCoreBuilder::thread_local_senders`.
+    pub fn thread_local_senders(self, enabled: bool) -> Self {
+        AsyncBuilder {
+            core: self.core.thread_local_senders(enabled),
+            ..self
+        }
+    }
+
+    /// Write a probe record when building with `try_build`, failing the
+    /// build if the drain panics on it.
+    ///
+    /// See `AsyncCoreBuilder::probe_on_build`.
+    pub fn probe_on_build(self, enabled: bool) -> Self {
+        AsyncBuilder {
+            core: self.core.probe_on_build(enabled),
+            ..self
+        }
+    }
+
+    /// Clone `count` senders up front, for thread pools to share.
+    ///
+    /// See `AsyncCoreBuilder::pool_senders`.
+    pub fn pool_senders(self, count: usize) -> Self {
+        AsyncBuilder {
+            core: self.core.pool_senders(count),
+            ..self
+        }
+    }
+
+    /// Use `spawn` to start the worker thread.
+    ///
+    /// See `AsyncCoreBuilder::spawn_with`.
+    pub fn spawn_with<F>(self, spawn: F) -> Self
+    where
+        F: FnOnce(Box<dyn FnOnce() + Send>) -> thread::JoinHandle<()> + 'static,
+    {
+        AsyncBuilder {
+            core: self.core.spawn_with(spawn),
+            ..self
+        }
+    }
+
+    /// Call `f` on the worker thread before it processes any record.
+    ///
+    /// See `AsyncCoreBuilder::on_thread_start`.
+    pub fn on_thread_start<F>(self, f: F) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        AsyncBuilder {
+            core: self.core.on_thread_start(f),
+            ..self
+        }
+    }
+
+    /// Pass each record through `f` on the worker thread before writing it.
+    ///
+    /// See `AsyncCoreBuilder::transform`.
+    pub fn transform<F>(self, f: F) -> Self
+    where
+        F: Fn(AsyncRecord) -> Option<AsyncRecord> + Send + 'static,
+    {
+        AsyncBuilder {
+            core: self.core.transform(f),
+            ..self
+        }
+    }
+
+    /// Write the records of some PIDs to their own drains.
+    ///
+    /// See `PidRouter`.
+    pub fn pid_router(self, router: PidRouter) -> Self {
+        AsyncBuilder {
+            core: self.core.pid_router(router),
+            ..self
+        }
+    }
+
+    /// Configure from environment variables
+    ///
+    /// Lets deployments tune the logger without code changes. Recognized
+    /// variables, each overriding the corresponding option when set:
+    ///
+    /// * `SLOG_ASYNC_CHAN_SIZE` - channel size, a non-negative integer,
+    /// * `SLOG_ASYNC_OVERFLOW` - overflow strategy, one of `drop`,
+    ///   `drop_and_report` or `block` (case insensitive),
+    /// * `SLOG_ASYNC_THREAD_NAME` - name of the worker thread.
+    ///
+    /// Invalid values are ignored with a warning printed to stderr, keeping
+    /// the option as it was.
+    pub fn from_env(self) -> Self {
+        self.env_overrides(|key| env::var(key))
+    }
+
+    fn env_overrides<F>(mut self, lookup: F) -> Self
+    where
+        F: Fn(&str) -> Result<String, env::VarError>,
+    {
+        if let Some(s) = env_value(&lookup, ENV_CHAN_SIZE) {
+            match s.trim().parse() {
+                Ok(chan_size) => self = self.chan_size(chan_size),
+                Err(e) => env_warning(ENV_CHAN_SIZE, &s, &e.to_string()),
+            }
+        }
+        if let Some(s) = env_value(&lookup, ENV_OVERFLOW) {
+            match parse_overflow_strategy(&s) {
+                Some(strategy) => self = self.overflow_strategy(strategy),
+                None => env_warning(
+                    ENV_OVERFLOW,
+                    &s,
+                    "expected `drop`, `drop_and_report` or `block`",
+                ),
+            }
+        }
+        if let Some(s) = env_value(&lookup, ENV_THREAD_NAME) {
+            if s.is_empty() {
+                env_warning(ENV_THREAD_NAME, &s, "empty");
+            } else if s.contains('\0') {
+                env_warning(ENV_THREAD_NAME, &s, "contains '\\0'");
+            } else {
+                self = self.thread_name(s);
+            }
+        }
+        self
+    }
+
+    /// Complete building `Async`
+    pub fn build(self) -> Async {
+        Async::from_core(self.core.build_no_guard(), self.options)
+    }
+
+    /// Complete building `Async`
+    pub fn build_no_guard(self) -> Async {
+        Async::from_core(self.core.build_no_guard(), self.options)
+    }
+
+    /// Complete building `Async`, failing if the drain panics on the probe
+    /// record of `probe_on_build`
+    ///
+    /// See `AsyncCoreBuilder::try_build`.
+    pub fn try_build(self) -> AsyncResult<Async> {
+        Ok(Async::from_core(self.core.try_build()?, self.options))
+    }
+
+    /// Complete building `Async` with PID channel
+    pub fn build_with_channel(self) -> (Async, PIDLogControl) {
+        let async_struct =
+            Async::from_core(self.core.build_no_guard(), self.options);
+        let log_control = PIDLogControl::new(
+            async_struct.core.ref_sender.clone(),
+            async_struct.overflow_strategy.clone(),
+            async_struct.drop_reporting.clone(),
+        );
+        (async_struct, log_control)
+    }
+
+    /// Complete building `Async` with an `AsyncWorker`, to run the worker
+    /// loop on a thread of the caller's own
+    ///
+    /// See `AsyncCoreBuilder::build_manual`.
+    pub fn build_manual(self) -> (Async, AsyncWorker) {
+        let (core, worker) = self.core.build_manual();
+        (Async::from_core(core, self.options), worker)
+    }
+
+    /// Complete building `Async` with `AsyncGuard`
+    ///
+    /// See `AsyncGuard` for more information.
+    pub fn build_with_guard(self) -> (Async, AsyncGuard) {
+        let (core, guard) = self.core.build_with_guard();
+        (Async::from_core(core, self.options), guard)
+    }
+}
+
+const ENV_CHAN_SIZE: &str = "SLOG_ASYNC_CHAN_SIZE";
+const ENV_OVERFLOW: &str = "SLOG_ASYNC_OVERFLOW";
+const ENV_THREAD_NAME: &str = "SLOG_ASYNC_THREAD_NAME";
+
+fn env_value<F>(lookup: &F, key: &str) -> Option<String>
+where
+    F: Fn(&str) -> Result<String, env::VarError>,
+{
+    match lookup(key) {
+        Ok(s) => Some(s),
+        Err(env::VarError::NotPresent) => None,
+        Err(e @ env::VarError::NotUnicode(_)) => {
+            env_warning(key, "", &e.to_string());
+            None
+        }
+    }
+}
+
+fn env_warning(key: &str, value: &str, reason: &str) {
+    eprintln!(
+        "slog-async: ignoring invalid {}={:?}: {}",
+        key, value, reason
+    );
+}
+
+fn parse_overflow_strategy(s: &str) -> Option<OverflowStrategy> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "drop" => Some(OverflowStrategy::Drop),
+        "drop_and_report" => Some(OverflowStrategy::DropAndReport),
+        "block" => Some(OverflowStrategy::Block),
+        _ => None,
+    }
+}
+
+/// Statistics of the records dropped by `Async`, see `Async::drop_stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DropStats {
+    /// Number of records dropped since `Async` was built
+    pub total: u64,
+    /// When the last record was dropped
+    pub last_drop: Option<Instant>,
+    /// Largest number of records dropped in a row, without any record
+    /// getting through in between
+    pub max_burst: usize,
+}
+
+/// Async drain
+///
+/// `Async` will send all the logging records to a wrapped drain running in
+/// another thread.
+///
+/// `Async` never returns `AsyncError::Full`.
+///
+/// `Record`s are passed to the worker thread through a channel with a bounded
+/// size (see `AsyncBuilder::chan_size`). On channel overflow `Async` will
+/// start dropping `Record`s and log a message informing about it. The message
+/// is sent through a separate control channel the worker thread reads first,
+/// so it doesn't get dropped itself. The exact details of handling
+/// overflow is implementation defined, might change and should not be relied
+/// on, other than message won't be dropped as long as channel does not
+/// overflow.
+///
+/// Any messages reported by `Async` will contain `slog-async` logging `Record`
+/// tag to allow easy custom handling (see `AsyncBuilder::dropped_report_tag`).
+///
+/// Note: On drop `Async` waits for it's worker-thread to finish (after handling
+/// all previous `Record`s sent to it). If you can't tolerate the delay, make
+/// sure you drop it eg. in another thread.
+pub struct Async {
+    core: AsyncCore,
+    dropped: AtomicUsize,
+    overflow_strategy: Arc<SharedOverflowStrategy>,
+    // See `AsyncBuilder::overflow_strategy_for_level`
+    level_overflow_strategies: LevelOverflowStrategies,
+    drop_report_interval: Option<Duration>,
+    dropped_report_level: Level,
+    dropped_report_tag: String,
+    // See `AsyncBuilder::on_overflow`
+    on_overflow: Option<AssertUnwindSafe<Box<OnOverflowFn>>>,
+    // See `PIDLogControl::set_drop_reporting`
+    drop_reporting: Arc<AtomicBool>,
+    created: Instant,
+    // Nanoseconds since `created` the last drop report was sent at
+    last_drop_report: AtomicU64,
+    drop_stats: Mutex<DropStats>,
+    // Records dropped since the last one sent
+    drop_burst: AtomicUsize,
+    // Records that found the channel full, see
+    // `OverflowStrategy::DropAndSample`
+    overflow_samples: AtomicUsize,
+}
+
+const NO_DROP_REPORT: u64 = u64::MAX;
+
+fn duration_nanos(d: Duration) -> u64 {
+    d.as_secs()
+        .saturating_mul(1_000_000_000)
+        .saturating_add(u64::from(d.subsec_nanos()))
+}
+
+impl Async {
+    /// New `AsyncCore` with default parameters
+    pub fn default<
+        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    >(
+        drain: D,
+    ) -> Self {
+        AsyncBuilder::new(drain).build()
+    }
+
+    /// Build `Async` drain with defaults suited for production, and its
+    /// `AsyncGuard`
+    ///
+    /// A one-call setup for applications not needing to tune the drain. The
+    /// current defaults are:
+    ///
+    /// * a channel of `PRODUCTION_CHAN_SIZE` records,
+    /// * `OverflowStrategy::DropAndReport`, so logging never blocks the
+    ///   application,
+    /// * a worker thread named `slog-async`,
+    /// * every level emitted: filter in front of the drain, eg. with
+    ///   `slog::LevelFilter`.
+    ///
+    /// They may change in future versions. Keep the guard alive until the
+    /// end of `main`, so records are flushed on exit; see `AsyncGuard`.
+    ///
+    /// ```
+    /// #[macro_use]
+    /// extern crate slog;
+    /// extern crate slog_async;
+    ///
+    /// use slog::Drain;
+    ///
+    /// fn main() {
+    ///     let (drain, _guard) = slog_async::Async::production(slog::Discard);
+    ///     let log = slog::Logger::root(drain.fuse(), o!());
+    ///     info!(log, "started");
+    /// }
+    /// ```
+    pub fn production<D>(drain: D) -> (Self, AsyncGuard)
+    where
+        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        let (drain, guard) = AsyncBuilder::new(drain)
+            .chan_size(PRODUCTION_CHAN_SIZE)
+            .overflow_strategy(OverflowStrategy::DropAndReport)
+            .thread_name("slog-async".into())
+            .build_with_guard();
+        let _ = drain
+            .core
+            .ctrl_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()));
+        (drain, guard)
+    }
+
+    /// Deliver every record to both `first` and `second`, each with its own
+    /// channel and overflow strategy.
+    ///
+    /// See `AsyncFork`.
+    pub fn fork(first: Async, second: Async) -> AsyncFork {
+        AsyncFork::new(first, second)
+    }
+
+    /// Build `Async` drain with custom parameters
+    ///
+    /// The wrapped drain must handle all results (`Drain<Ok=(),Error=Never>`)
+    /// since there's no way to return it back. See `slog::DrainExt::fuse()` and
+    /// `slog::DrainExt::ignore_res()` for typical error handling strategies.
+    pub fn new<D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static>(
+        drain: D,
+    ) -> AsyncBuilder<D> {
+        AsyncBuilder::new(drain)
+    }
+
+    /// Build `Async` drain writing to a `drain` that may fail, keeping up
+    /// to `capacity` records it failed to write for a retry
+    ///
+    /// See `AsyncCore::custom_retrying`.
+    pub fn new_retrying<D>(
+        drain: D,
+        capacity: usize,
+    ) -> AsyncBuilder<RetryOnErr<D>>
+    where
+        D: slog::Drain<Ok = ()> + Send + 'static,
+        D::Err: fmt::Debug,
+    {
+        AsyncBuilder::new(RetryOnErr(drain)).retry_queue(capacity)
+    }
+
+    /// Build `Async` drain handing records to `drain` in batches
+    ///
+    /// See `AsyncCore::custom_batch`.
+    pub fn new_batch<B: BatchDrain>(
+        drain: B,
+        max_records: usize,
+        max_delay: Duration,
+    ) -> AsyncBuilder<slog::Discard> {
+        AsyncBuilder {
+            core: AsyncCore::custom_batch(drain, max_records, max_delay),
+            options: AsyncOptions::default(),
+        }
+    }
+
+    /// Build `Async` drain handing records to `drain` in size-limited chunks
+    ///
+    /// See `AsyncCore::custom_chunked`.
+    pub fn new_chunked<C: ChunkedDrain>(
+        drain: C,
+    ) -> AsyncBuilder<slog::Discard> {
+        AsyncBuilder {
+            core: AsyncCore::custom_chunked(drain),
+            options: AsyncOptions::default(),
+        }
+    }
+
+    /// Build `Async` drain yielding its records as a stream
+    ///
+    /// The worker thread hands the records over to the returned
+    /// `RecordStream` instead of writing them to a drain, eg. for an async
+    /// task forwarding them to a remote service. Use the returned
+    /// `PIDLogControl` to set the level emitted to the stream.
+    ///
+    /// ```edition2018,no_run
+    /// #[macro_use]
+    /// extern crate slog;
+    /// extern crate slog_async;
+    /// extern crate tokio_stream;
+    ///
+    /// use slog::Drain;
+    /// use tokio_stream::StreamExt;
+    ///
+    /// async fn forward(mut records: slog_async::RecordStream) {
+    ///     while let Some(record) = records.next().await {
+    ///         record.as_record_values(|record, _| {
+    ///             println!("{} {}", record.level(), record.msg())
+    ///         });
+    ///     }
+    /// }
+    ///
+    /// fn main() {
+    ///     let (drain, control, records) = slog_async::Async::into_stream();
+    ///     control.log_level(slog::Level::Info).unwrap();
+    ///     let log = slog::Logger::root(drain.fuse(), o!());
+    ///     info!(log, "started");
+    ///     // Spawned on the application's runtime
+    ///     let _forward = forward(records);
+    /// }
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub fn into_stream() -> (Self, PIDLogControl, RecordStream) {
+        let (core, records) = AsyncCore::custom_stream();
+        let (drain, control) = AsyncBuilder {
+            core,
+            options: AsyncOptions::default(),
+        }
+        .build_with_channel();
+        (drain, control, records)
+    }
+
+    /// Build a drain logging everything asynchronously to `file_drain`, and
+    /// records at or above `level` synchronously to `stderr_drain` as well.
+    ///
+    /// Use the returned `PIDLogControl` to set the level emitted to
+    /// `file_drain`. See `ErrorMirror`.
+    pub fn file_with_error_mirror<D, M>(
+        file_drain: D,
+        stderr_drain: M,
+        level: Level,
+    ) -> (ErrorMirror<M>, PIDLogControl)
+    where
+        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+        M: slog::Drain<Err = slog::Never, Ok = ()>,
+    {
+        let (async_drain, control) =
+            AsyncBuilder::new(file_drain).build_with_channel();
+        (ErrorMirror::new(async_drain, stderr_drain, level), control)
+    }
+
+    /// Build `Async` drain sending records to the local syslog daemon
+    ///
+    /// Records are sent to `/dev/log` with the given facility. See
+    /// `syslog::SyslogDrain` for details on the format; errors writing to the
+    /// socket are ignored.
+    #[cfg(all(unix, feature = "syslog"))]
+    pub fn to_syslog(
+        facility: syslog::Facility,
+    ) -> io::Result<AsyncBuilder<slog::IgnoreResult<syslog::SyslogDrain>>> {
+        let drain = syslog::SyslogDrain::unix("/dev/log", facility)?;
+        Ok(AsyncBuilder::new(drain.ignore_res()))
+    }
+
+    /// Build `Async` drain sending records to a Graylog GELF UDP input
+    ///
+    /// See `gelf::GelfDrain` for details on the format and chunking; errors
+    /// sending to `addr` are ignored.
+    #[cfg(feature = "gelf")]
+    pub fn to_gelf_udp<A: std::net::ToSocketAddrs>(
+        addr: A,
+    ) -> io::Result<AsyncBuilder<slog::IgnoreResult<gelf::GelfDrain>>> {
+        let drain = gelf::GelfDrain::udp(addr)?;
+        Ok(AsyncBuilder::new(drain.ignore_res()))
+    }
+
+    /// Build `Async` drain writing records to a Parquet file
+    ///
+    /// Each row group has a column for each of `keys`, and up to
+    /// `arrow::DEFAULT_MAX_ROWS` records, waiting at most
+    /// `arrow::DEFAULT_MAX_DELAY`; use `Async::new_batch` with an
+    /// `arrow::ArrowBatchDrain` for other limits. See `arrow` for the schema.
+    /// Errors writing to `writer` panic the worker thread, see `FuseBatch`.
+    #[cfg(feature = "arrow")]
+    pub fn to_arrow<W, K, I>(
+        writer: W,
+        keys: I,
+    ) -> Result<AsyncBuilder<slog::Discard>, parquet::errors::ParquetError>
+    where
+        W: io::Write + Send + 'static,
+        K: Into<String>,
+        I: IntoIterator<Item = K>,
+    {
+        Ok(Async::new_batch(
+            FuseBatch(arrow::ArrowBatchDrain::new(writer, keys)?),
+            arrow::DEFAULT_MAX_ROWS,
+            arrow::DEFAULT_MAX_DELAY,
+        ))
+    }
+
+    /// Build `Async` drain emitting records as OpenTelemetry log records
+    /// with `logger`
+    ///
+    /// Batches hold up to `otel::DEFAULT_MAX_RECORDS` records, waiting at
+    /// most `otel::DEFAULT_MAX_DELAY`; use `Async::new_batch` with an
+    /// `otel::OtelBatchDrain` for other limits. See `otel` for the mapping.
+    #[cfg(feature = "otel")]
+    pub fn to_otel<L>(logger: L) -> AsyncBuilder<slog::Discard>
+    where
+        L: opentelemetry::logs::Logger + Send + 'static,
+    {
+        Async::new_batch(
+            otel::OtelBatchDrain::new(logger),
+            otel::DEFAULT_MAX_RECORDS,
+            otel::DEFAULT_MAX_DELAY,
+        )
+    }
+
+    /// Build `Async` drain writing records in logfmt to `writer`
+    ///
+    /// See `logfmt::LogfmtDrain` for details on the format; errors writing to
+    /// `writer` are ignored.
+    #[cfg(feature = "logfmt")]
+    pub fn to_logfmt<W>(
+        writer: W,
+    ) -> AsyncBuilder<slog::IgnoreResult<logfmt::LogfmtDrain<W>>>
+    where
+        W: io::Write + Send + 'static,
+    {
+        AsyncBuilder::new(logfmt::LogfmtDrain::new(writer).ignore_res())
+    }
+
+    fn from_core(core: AsyncCore, options: AsyncOptions) -> Self {
+        Async {
+            core,
+            dropped: AtomicUsize::new(0),
+            overflow_strategy: Arc::new(SharedOverflowStrategy::new(
+                options.overflow_strategy,
+            )),
+            level_overflow_strategies: options.level_overflow_strategies,
+            drop_report_interval: options.drop_report_interval,
+            dropped_report_level: options.dropped_report_level,
+            dropped_report_tag: options.dropped_report_tag,
+            on_overflow: options.on_overflow,
+            drop_reporting: Arc::new(AtomicBool::new(true)),
+            created: Instant::now(),
+            last_drop_report: AtomicU64::new(NO_DROP_REPORT),
+            drop_stats: Mutex::new(DropStats::default()),
+            drop_burst: AtomicUsize::new(0),
+            overflow_samples: AtomicUsize::new(0),
+        }
+    }
+
+    /// Make sure the current thread's records are delivered while the
+    /// returned guard is alive.
+    ///
+    /// Meant for critical sections, eg. a financial transaction: logging
+    /// from this thread blocks until there's room in the channel, as with
+    /// `OverflowStrategy::Block`, whatever the configured strategy, so its
+    /// records aren't dropped while other threads' are. Applies to every
+    /// `Async` drain. Scopes can be nested.
+    pub fn priority_scope() -> PriorityScope {
+        PRIORITY_SCOPES.with(|scopes| scopes.set(scopes.get() + 1));
+        PriorityScope {
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Flush all the live drains when the process exits, even through
+    /// `std::process::exit`.
+    ///
+    /// Registers an `atexit` hook calling `registry::flush_all`, once however
+    /// many times it's called. The hook doesn't run when the process is
+    /// killed by a signal or aborts, eg. on a panic with `panic = "abort"`.
+    #[cfg(feature = "registry")]
+    pub fn install_exit_guard() {
+        registry::install_exit_guard()
+    }
+
+    /// Switch the worker thread over to writing to `drain`, once the records
+    /// logged so far, and the report of the ones dropped, are written to the
+    /// old drain.
+    ///
+    /// See `AsyncCore::migrate_to`.
+    pub fn migrate_to<D>(&self, drain: D) -> AsyncResult<()>
+    where
+        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        self.flush()?;
+        self.core.replace_drain(drain)
+    }
+
+    /// Write the records tagged `name` to `drain` instead, from now on.
+    ///
+    /// See `AsyncCore::register_sink`.
+    pub fn register_sink<D>(&self, name: &str, drain: D) -> AsyncResult<()>
+    where
+        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
+    {
+        self.core.register_sink(name, drain)
+    }
+
+    /// Stop writing the records tagged `name` to a registered drain.
+    ///
+    /// See `AsyncCore::unregister_sink`.
+    pub fn unregister_sink(&self, name: &str) -> AsyncResult<()> {
+        self.core.unregister_sink(name)
+    }
+
+    /// Sender of `ControlMsg`s, for building custom control layers.
+    ///
+    /// See `AsyncCore::control_sender`.
+    pub fn control_sender(&self) -> ControlSender {
+        self.core.control_sender()
+    }
+
+    /// Wait until the worker thread wrote all the records logged so far,
+    /// including a report of the records dropped until now.
+    ///
+    /// See `AsyncCore::flush`.
+    pub fn flush(&self) -> AsyncResult<()> {
+        self.report_dropped(&o!().into())?;
+        self.core.flush()
+    }
+
+    /// Wait until the worker thread wrote all the records logged so far,
+    /// calling `progress` every 100ms meanwhile.
+    ///
+    /// See `AsyncCore::flush_with_progress`.
+    pub fn flush_with_progress<F>(&self, progress: F) -> AsyncResult<()>
+    where
+        F: Fn(usize),
+    {
+        self.report_dropped(&o!().into())?;
+        self.core.flush_with_progress(progress)
+    }
+
+    /// Wait up to `timeout` until the worker thread wrote all the records
+    /// logged so far, including a report of the records dropped until now.
+    ///
+    /// See `AsyncCore::flush_timeout`.
+    pub fn flush_timeout(&self, timeout: Duration) -> AsyncResult<bool> {
+        self.report_dropped(&o!().into())?;
+        self.core.flush_timeout(timeout)
+    }
+
+    /// Wait until the worker thread wrote all the records logged so far,
+    /// including a report of the records dropped until now, then write the
+    /// records kept by `AsyncBuilder::keep_recent` to `writer`.
+    ///
+    /// See `AsyncCore::checkpoint`.
+    pub fn checkpoint<W: io::Write>(
+        &self,
+        writer: &mut W,
+    ) -> AsyncResult<usize> {
+        self.report_dropped(&o!().into())?;
+        self.core.checkpoint(writer)
+    }
+
+    /// Prepare logger values that many records are logged with.
+    ///
+    /// See `AsyncCore::precompute_logger_values`.
+    pub fn precompute_logger_values(
+        &self,
+        logger_values: &OwnedKVList,
+    ) -> LoggerValuesHandle {
+        self.core.precompute_logger_values(logger_values)
+    }
+
+    /// Log `record` with the logger values prepared by
+    /// `precompute_logger_values`.
+    ///
+    /// See `AsyncCore::log_with_handle`.
+    pub fn log_with_handle(
+        &self,
+        record: &Record,
+        handle: &LoggerValuesHandle,
+    ) -> AsyncResult<()> {
+        self.push_dropped(&handle.values)?;
+        self.send_serialized(self.core.serialize_handle(record, handle))
+    }
+
+    /// Wait until the worker thread wrote the records tagged `tag` logged
+    /// so far.
+    ///
+    /// See `AsyncCore::flush_tag`.
+    pub fn flush_tag(&self, tag: &str) -> AsyncResult<()> {
+        self.core.flush_tag(tag)
+    }
+
+    /// Number of records the worker thread wrote to the drain so far.
+    ///
+    /// See `AsyncCore::processed_count`.
+    pub fn processed_count(&self) -> u64 {
+        self.core.processed_count()
+    }
+
+    /// Whether async logging got disabled because the drain kept panicking.
+    ///
+    /// See `AsyncCore::is_degraded`.
+    pub fn is_degraded(&self) -> bool {
+        self.core.is_degraded()
+    }
+
+    /// Wait until the worker thread is ready to process records, at most
+    /// `timeout`.
+    ///
+    /// See `AsyncCore::wait_ready`.
+    pub fn wait_ready(&self, timeout: Duration) -> bool {
+        self.core.wait_ready(timeout)
+    }
+
+    /// Log from the calling thread through the pool sender of `index`.
+    ///
+    /// See `AsyncCore::bind_sender`.
+    pub fn bind_sender(&self, index: usize) {
+        self.core.bind_sender(index)
+    }
+
+    /// Number of producer threads holding a cached sender.
+    ///
+    /// See `AsyncCore::producer_thread_count`.
+    pub fn producer_thread_count(&self) -> usize {
+        self.core.producer_thread_count()
+    }
+
+    /// Most messages ever seen queued in the channel.
+    ///
+    /// See `AsyncCore::max_queue_depth`.
+    pub fn max_queue_depth(&self) -> usize {
+        self.core.max_queue_depth()
+    }
+
+    /// Restart tracking `max_queue_depth`, returning the peak until now.
+    ///
+    /// See `AsyncCore::reset_max_depth`.
+    pub fn reset_max_depth(&self) -> usize {
+        self.core.reset_max_depth()
+    }
+
+    /// Estimated memory used by the records waiting for the worker thread.
+    ///
+    /// See `AsyncCore::estimated_memory_bytes`.
+    pub fn estimated_memory_bytes(&self) -> Option<usize> {
+        self.core.estimated_memory_bytes()
+    }
+
+    /// Distribution of the time sending records to the channel took so far.
+    ///
+    /// See `AsyncCore::send_latency_percentiles`.
+    pub fn send_latency_percentiles(&self) -> Option<SendLatencyPercentiles> {
+        self.core.send_latency_percentiles()
+    }
+
+    /// All the runtime statistics at once, including the number of records
+    /// dropped since the last drop report.
+    ///
+    /// See `AsyncCore::stats`.
+    pub fn stats(&self) -> AsyncStatsSnapshot {
+        AsyncStatsSnapshot {
+            dropped: self.dropped.load(Ordering::Relaxed),
+            ..self.core.stats()
+        }
+    }
+
+    /// Number of records dropped since the last drop report, or
+    /// `take_dropped_count` call.
+    ///
+    /// Cheap enough to poll, eg. to export as a gauge. Reading doesn't reset
+    /// the count. Drops are counted by the logging threads, so records being
+    /// dropped concurrently may show up only in the next read. Only
+    /// `OverflowStrategy::DropAndReport` and `OverflowStrategy::BlockAbove`
+    /// count drops, see `drop_stats` for all of them.
+    pub fn dropped_count(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Read and reset the number of records dropped since the last drop
+    /// report or call, in one step.
+    ///
+    /// Meant for metrics exporters computing drop rates per scrape interval:
+    /// drops are never counted twice, nor missed between reading and
+    /// resetting. The records taken aren't part of the next drop report
+    /// anymore. Only `OverflowStrategy::DropAndReport` and
+    /// `OverflowStrategy::BlockAbove` count drops, see `drop_stats` for all
+    /// of them.
+    pub fn take_dropped_count(&self) -> usize {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+
+    /// Replaces the overflow strategy.
+    ///
+    /// Takes effect from the next logged record onwards, eg. to block during
+    /// a critical window and go back to dropping afterwards. Returns
+    /// `AsyncError::Fatal` with an `InvalidInput` error if passed the hidden
+    /// `DoNotMatchAgainstThisAndReadTheDocs` variant or a `DropAndSample`
+    /// with a `keep_every` of 0, keeping the current strategy. Also available
+    /// on the `PIDLogControl` of `AsyncBuilder::build_with_channel`.
+    pub fn set_overflow_strategy(
+        &self,
+        overflow_strategy: OverflowStrategy,
+    ) -> AsyncResult<()> {
+        self.overflow_strategy.store(overflow_strategy)
+    }
+
+    /// Statistics of the records dropped so far.
+    ///
+    /// Unlike the report logged by `OverflowStrategy::DropAndReport`, these
+    /// are never reset, and cover records dropped silently with
+    /// `OverflowStrategy::Drop` too. Meant for metrics exporters.
+    pub fn drop_stats(&self) -> DropStats {
+        match self.drop_stats.lock() {
+            Ok(stats) => *stats,
+            Err(e) => *e.into_inner(),
+        }
+    }
+
+    /// Send already serialized `AsyncRecord`s to the worker thread in one
+    /// call.
+    ///
+    /// Records are sent in order, each one as with `log`: according to the
+    /// overflow strategy for its level, dropped records being counted and
+    /// reported as usual. Returns the number of records sent, not dropped.
+    pub fn submit_batch<I>(&self, records: I) -> AsyncResult<usize>
+    where
+        I: IntoIterator<Item = AsyncRecord>,
+    {
+        let mut accepted = 0;
+        for r in records {
+            self.push_dropped(&r.logger_values)?;
+            if self.send_or_drop(r)? {
+                accepted += 1;
+            }
+        }
+        Ok(accepted)
+    }
+
+    /// Send a serialized record according to the overflow strategy.
+    fn send_serialized(&self, r: AsyncRecord) -> AsyncResult<()> {
+        self.send_or_drop(r).map(|_| ())
+    }
+
+    /// Send a serialized record according to the overflow strategy, telling
+    /// whether it was sent rather than dropped.
+    fn send_or_drop(&self, r: AsyncRecord) -> AsyncResult<bool> {
+        let overflow_strategy = self.level_overflow_strategies
+            [r.level.as_usize() - 1]
+            .unwrap_or_else(|| self.overflow_strategy.load());
+        let blocking = match overflow_strategy {
+            OverflowStrategy::Block => true,
+            OverflowStrategy::BlockAbove(watermark) => {
+                self.core.wait_below(watermark);
+                false
+            }
+            _ => false,
+        };
+        let blocking = blocking || PRIORITY_SCOPES.with(Cell::get) > 0;
+        let res = match overflow_strategy {
+            OverflowStrategy::DropAndSample { keep_every } => {
+                let keep = |r: AsyncRecord| {
+                    let n =
+                        self.overflow_samples.fetch_add(1, Ordering::Relaxed);
+                    if n % keep_every as usize == 0 {
+                        self.core.send_waiting(r)
+                    } else {
+                        Err(self.core.rejected())
+                    }
+                };
+                self.core.send_record_or(r, blocking, Some(&keep))
+            }
+            _ => self.core.send_record(r, blocking),
+        };
+        match res {
+            Ok(()) => {
+                if self.drop_burst.load(Ordering::Relaxed) != 0 {
+                    self.drop_burst.store(0, Ordering::Relaxed);
+                }
+            }
+            Err(AsyncError::Full)
+                if matches!(
+                    overflow_strategy,
+                    OverflowStrategy::DropAndReport
+                        | OverflowStrategy::BlockAbove(_)
+                ) =>
+            {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                self.record_drop();
+            }
+            Err(AsyncError::Full) => self.record_drop(),
+            Err(e) => return Err(e),
+        }
+
+        Ok(res.is_ok())
+    }
+
+    /// Account for a record dropped because the channel is full.
+    fn record_drop(&self) {
+        let burst = self.drop_burst.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut stats = match self.drop_stats.lock() {
+            Ok(stats) => stats,
+            Err(e) => e.into_inner(),
+        };
+        stats.total += 1;
+        stats.last_drop = Some(Instant::now());
+        stats.max_burst = stats.max_burst.max(burst);
+    }
+
+    /// Report dropped records, unless a previous report is still waiting for
+    /// the worker thread or the last one was sent less than
+    /// `drop_report_interval` ago.
+    fn push_dropped(&self, logger_values: &OwnedKVList) -> AsyncResult<()> {
+        if (self.on_overflow.is_none() && !self.core.ctrl_sender.is_empty())
+            || self.dropped.load(Ordering::Relaxed) == 0
+            || !self.drop_report_due()
+        {
+            return Ok(());
+        }
+        self.report_dropped(logger_values)
+    }
+
+    /// Claim the next drop report if `drop_report_interval` elapsed since
+    /// the last one.
+    fn drop_report_due(&self) -> bool {
+        let interval = match self.drop_report_interval {
+            Some(interval) => duration_nanos(interval),
+            None => return true,
+        };
+        let now = duration_nanos(self.created.elapsed());
+        let last = self.last_drop_report.load(Ordering::Relaxed);
+        if last != NO_DROP_REPORT && now.saturating_sub(last) < interval {
+            return false;
+        }
+        self.last_drop_report
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn report_dropped(&self, logger_values: &OwnedKVList) -> AsyncResult<()> {
+        if !self.drop_reporting.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped == 0 {
+            return Ok(());
+        }
+        if let Some(ref on_overflow) = self.on_overflow {
+            on_overflow(dropped);
+        } else {
+            let mut r = AsyncRecord::from(
+                &record!(
+                    slog::Level::Error,
+                    DROPPED_REPORT_TAG,
+                    &format_args!(
+                        "slog-async: logger dropped messages \
+                         due to channel \
+                         overflow"
+                    ),
+                    b!("count" => dropped)
+                ),
+                logger_values,
+            );
+            // `record!` only takes constants
+            r.level = self.dropped_report_level;
+            r.tag.clone_from(&self.dropped_report_tag);
+            match self.core.send_control(r) {
+                Ok(()) => {}
+                // Over the memory budget, report these with the next ones
+                Err(AsyncError::Full) => {
+                    self.dropped.fetch_add(dropped, Ordering::Relaxed);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drain for Async {
+    type Ok = ();
+    type Err = AsyncError;
+
+    // TODO: Review `Ordering::Relaxed`
+    fn log(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> AsyncResult<()> {
+        let res = self.push_dropped(logger_values).and_then(|()| {
+            self.send_serialized(self.core.serialize(record, logger_values))
+        });
+        self.core.or_fallback(res, record, logger_values)
+    }
+
+    /// See `Async::flush`.
+    fn flush(&self) -> Result<(), slog::FlushError> {
+        Async::flush(self).map_err(flush_error)
+    }
+}
+
+impl Drop for Async {
+    fn drop(&mut self) {
+        let _ = self.report_dropped(&o!().into());
+    }
+}
+
+thread_local! {
+    /// Number of live `PriorityScope`s of the thread
+    static PRIORITY_SCOPES: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Guard giving the current thread's records priority, see
+/// `Async::priority_scope`
+pub struct PriorityScope {
+    // Tied to the thread it was created on
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for PriorityScope {
+    fn drop(&mut self) {
+        PRIORITY_SCOPES.with(|scopes| scopes.set(scopes.get() - 1));
+    }
+}
+
+// }}}
+
+// {{{ ErrorMirror
+/// Drain logging everything through `Async` and mirroring important records
+/// synchronously to another drain
+///
+/// Typically everything goes asynchronously to a file, while errors are
+/// also printed to stderr right away, so operators see them immediately and
+/// they aren't lost if the process dies before the worker thread writes them.
+/// See `Async::file_with_error_mirror`.
+pub struct ErrorMirror<M> {
+    async_drain: Async,
+    mirror: M,
+    level: Level,
+}
+
+impl<M> ErrorMirror<M>
+where
+    M: slog::Drain<Err = slog::Never, Ok = ()>,
+{
+    /// Log records to `async_drain`, and records at or above `level` to
+    /// `mirror` as well, in the calling thread.
+    pub fn new(async_drain: Async, mirror: M, level: Level) -> Self {
+        ErrorMirror {
+            async_drain,
+            mirror,
+            level,
+        }
+    }
+}
+
+impl<M> Drain for ErrorMirror<M>
+where
+    M: slog::Drain<Err = slog::Never, Ok = ()>,
+{
+    type Ok = ();
+    type Err = AsyncError;
+
+    fn log(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> AsyncResult<()> {
+        if record.level().is_at_least(self.level) {
+            self.mirror.log(record, logger_values).unwrap();
+        }
+        self.async_drain.log(record, logger_values)
+    }
+}
+// }}}
+
+// {{{ AsyncFork
+/// Drain delivering every record to two `Async` drains
+///
+/// Each side has its own channel, worker thread and overflow strategy, so
+/// a slow side doesn't hold up the other one, eg. a local file that never
+/// drops next to a network sink dropping heavily under load. Records are
+/// serialized once, with the settings of the first side, and cloned for the
+/// second one. See `Async::fork`.
+pub struct AsyncFork {
+    first: Async,
+    second: Async,
+}
+
+impl AsyncFork {
+    /// Log records to both `first` and `second`.
+    pub fn new(first: Async, second: Async) -> Self {
+        AsyncFork { first, second }
+    }
+
+    /// The first side, eg. to flush it or read its stats.
+    pub fn first(&self) -> &Async {
+        &self.first
+    }
+
+    /// The second side, eg. to flush it or read its stats.
+    pub fn second(&self) -> &Async {
+        &self.second
+    }
+}
+
+impl Drain for AsyncFork {
+    type Ok = ();
+    type Err = AsyncError;
+
+    /// Logs to both sides, returning the error of the first side if any,
+    /// else the one of the second side.
+    fn log(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> AsyncResult<()> {
+        let r = self.first.core.serialize(record, logger_values);
+        let second = self
+            .second
+            .push_dropped(logger_values)
+            .and_then(|()| self.second.send_serialized(r.clone()));
+        let first = self
+            .first
+            .push_dropped(logger_values)
+            .and_then(|()| self.first.send_serialized(r));
+        let first = self.first.core.or_fallback(first, record, logger_values);
+        let second =
+            self.second.core.or_fallback(second, record, logger_values);
+        first.and(second)
+    }
+}
+// }}}
+
+// {{{ SharedDrain
+/// Drain shared by several `Async` drains, see `shared_drain`.
+pub struct SharedDrain<D> {
+    drain: Arc<Mutex<D>>,
+}
+
+/// Share `drain` between several `Async` drains.
+///
+/// The worker thread of each `Async` owns its drain, so a sink that isn't
+/// thread-safe, eg. a single file, can't be handed to two of them directly.
+/// Wrap it in an `Arc<Mutex<_>>` instead and give each one a `SharedDrain`:
+/// their worker threads then take turns writing to it. Keep a clone of the
+/// `Arc` to reach the sink afterwards.
+///
+/// ```
+/// #[macro_use]
+/// extern crate slog;
+/// extern crate slog_async;
+///
+/// use slog::Drain;
+/// use std::sync::{Arc, Mutex};
+///
+/// fn main() {
+///     let sink = Arc::new(Mutex::new(slog::Discard));
+///     let requests = slog_async::Async::default(
+///         slog_async::shared_drain(sink.clone()),
+///     );
+///     let audit = slog_async::Async::default(slog_async::shared_drain(sink));
+///     let _requests = slog::Logger::root(requests.fuse(), o!());
+///     let _audit = slog::Logger::root(audit.fuse(), o!());
+/// }
+/// ```
+pub fn shared_drain<D>(drain: Arc<Mutex<D>>) -> SharedDrain<D>
+where
+    D: slog::Drain<Err = slog::Never, Ok = ()> + Send,
+{
+    SharedDrain { drain }
+}
+
+impl<D> Drain for SharedDrain<D>
+where
+    D: slog::Drain<Err = slog::Never, Ok = ()> + Send,
+{
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Result<(), slog::Never> {
+        // A drain that panicked is left as is, the next writer carries on
+        let drain = match self.drain.lock() {
+            Ok(drain) => drain,
+            Err(e) => e.into_inner(),
+        };
+        drain.log(record, logger_values)
+    }
+}
+// }}}
+
+// {{{ ShardedDrain
+/// Drain distributing records across several wrapped drains
+///
+/// Meant to be wrapped by `Async`, to spread the downstream IO of a single
+/// worker thread across eg. multiple files.
+pub struct ShardedDrain<D> {
+    drains: Vec<D>,
+    shard: AssertUnwindSafe<Box<ShardFn>>,
+}
+
+type ShardFn = dyn Fn(&Record, &OwnedKVList) -> usize + Send + Sync;
+
+impl<D: Drain> ShardedDrain<D> {
+    /// Shard records with a custom function.
+    ///
+    /// The returned index is taken modulo the number of `drains`.
+    ///
+    /// # Panics
+    ///
+    /// If `drains` is empty.
+    pub fn new<F>(drains: Vec<D>, shard: F) -> Self
+    where
+        F: Fn(&Record, &OwnedKVList) -> usize + Send + Sync + 'static,
+    {
+        assert!(!drains.is_empty(), "No drains to shard across");
+        ShardedDrain {
+            drains,
+            shard: AssertUnwindSafe(Box::new(shard)),
+        }
+    }
+
+    /// Send each record to the next drain in turn.
+    ///
+    /// # Panics
+    ///
+    /// If `drains` is empty.
+    pub fn round_robin(drains: Vec<D>) -> Self {
+        let next = AtomicUsize::new(0);
+        ShardedDrain::new(drains, move |_, _| {
+            next.fetch_add(1, Ordering::Relaxed)
+        })
+    }
+
+    /// Send all records with the same value of `key` to the same drain.
+    ///
+    /// Both the record's and the logger's key-values are searched. Records
+    /// without `key` all go to the same drain.
+    ///
+    /// # Panics
+    ///
+    /// If `drains` is empty.
+    pub fn by_key(drains: Vec<D>, key: &'static str) -> Self {
+        ShardedDrain::new(drains, move |record, logger_values| {
+            let mut ser = FindValueSerializer::new(key);
+            let _ = logger_values.serialize(record, &mut ser);
+            let _ = record.kv().serialize(record, &mut ser);
+            let mut hasher = DefaultHasher::new();
+            ser.value.hash(&mut hasher);
+            hasher.finish() as usize
+        })
+    }
+}
+
+impl<D: Drain> Drain for ShardedDrain<D> {
+    type Ok = D::Ok;
+    type Err = D::Err;
+
+    fn log(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Result<D::Ok, D::Err> {
+        let i = (self.shard)(record, logger_values) % self.drains.len();
+        self.drains[i].log(record, logger_values)
+    }
+}
+// }}}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn integration_test() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let async_drain = AsyncBuilder::new(mock_drain).build();
+        let slog =
+            slog::Logger::root(async_drain.fuse(), o!("field1" => "value1"));
+
+        info!(slog, "Message 1"; "field2" => "value2");
+        warn!(slog, "Message 2"; "field3" => "value3");
+        assert_eq!(
+            mock_drain_rx.recv().unwrap(),
+            r#"INFO Message 1: [("field1", "value1"), ("field2", "value2")]"#
+        );
+        assert_eq!(
+            mock_drain_rx.recv().unwrap(),
+            r#"WARN Message 2: [("field1", "value1"), ("field3", "value3")]"#
+        );
+    }
+
+    #[test]
+    fn overflow_strategy_can_be_replaced_at_runtime() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(gated_drain)
+            .chan_size(1)
+            .overflow_strategy(OverflowStrategy::Block)
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+
+        // Park the worker inside the drain, then fill up the channel.
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+
+        async_drain
+            .set_overflow_strategy(OverflowStrategy::Drop)
+            .unwrap();
+        log_msg(&async_drain, "dropped silently").unwrap();
+        assert_eq!(async_drain.dropped.load(Ordering::Relaxed), 0);
+
+        control
+            .set_overflow_strategy(OverflowStrategy::DropAndReport)
+            .unwrap();
+        log_msg(&async_drain, "dropped and reported").unwrap();
+        assert_eq!(async_drain.dropped.load(Ordering::Relaxed), 1);
+
+        for res in [
+            control.set_overflow_strategy(
+                OverflowStrategy::DoNotMatchAgainstThisAndReadTheDocs,
+            ),
+            async_drain.set_overflow_strategy(
+                OverflowStrategy::DoNotMatchAgainstThisAndReadTheDocs,
+            ),
+        ] {
+            match res {
+                Err(AsyncError::Fatal(err)) => assert!(err
+                    .to_string()
+                    .contains("not an overflow strategy")),
+                res => panic!("unexpected result {:?}", res),
+            }
+        }
+        // The current strategy is kept: the previous drop gets reported and
+        // this one counted for the next report
+        log_msg(&async_drain, "dropped and reported again").unwrap();
+        assert_eq!(async_drain.dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(async_drain.drop_stats().total, 3);
+        drop(release);
+    }
+
+    #[test]
+    fn overflow_strategy_for_level_blocks_errors() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) =
+            AsyncBuilder::new(TeeDrain(gated_drain, mock_drain))
+                .chan_size(1)
+                .overflow_strategy(OverflowStrategy::Drop)
+                .overflow_strategy_for_level(
+                    Level::Error,
+                    OverflowStrategy::Block,
+                )
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let async_drain = Arc::new(async_drain);
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+        log_msg(&async_drain, "dropped").unwrap();
+
+        let (sent_tx, sent_rx) = mpsc::channel();
+        let blocked = {
+            let async_drain = async_drain.clone();
+            thread::spawn(move || {
+                let mut r = async_record("error");
+                r.level = Level::Error;
+                async_drain.send_serialized(r).unwrap();
+                sent_tx.send(()).unwrap();
+            })
+        };
+        assert!(sent_rx.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(release);
+        sent_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        blocked.join().unwrap();
+        drop((async_drain, control));
+
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec!["INFO parked: []", "INFO queued: []", "ERRO error: []"]
+        );
+    }
+
+    #[test]
+    fn drop_and_sample_keeps_one_in_n() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) =
+            AsyncBuilder::new(TeeDrain(mock_drain, gated_drain))
+                .chan_size(1)
+                .overflow_strategy(OverflowStrategy::DropAndSample {
+                    keep_every: 3,
+                })
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+        let async_drain = Arc::new(async_drain);
+        let producer = {
+            let async_drain = async_drain.clone();
+            thread::spawn(move || {
+                for i in 0..7 {
+                    log_msg(&*async_drain, &format!("overflow {}", i)).unwrap();
+                }
+            })
+        };
+        // Kept records wait for room in the channel: let one record through
+        // each time the next kept one is waiting
+        for dropped in &[0, 2, 4] {
+            while async_drain.drop_stats().total < *dropped {
+                thread::yield_now();
+            }
+            release.send(()).unwrap();
+            entered.recv().unwrap();
+        }
+        producer.join().unwrap();
+        assert_eq!(async_drain.drop_stats().total, 4);
+        assert_eq!(async_drain.dropped_count(), 0);
+        // Nothing bypassed the channel
+        assert_eq!(async_drain.core.ctrl_sender.len(), 0);
+        let strategy = OverflowStrategy::DropAndSample { keep_every: 3 };
+        assert_eq!(SharedOverflowStrategy::new(strategy).load(), strategy);
+
+        match control.set_overflow_strategy(OverflowStrategy::DropAndSample {
+            keep_every: 0,
+        }) {
+            Err(AsyncError::Fatal(err)) => {
+                assert!(err.to_string().contains("keep_every"))
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        drop(release);
+        drop((async_drain, control));
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec![
+                "INFO parked: []",
+                "INFO queued: []",
+                "INFO overflow 0: []",
+                "INFO overflow 3: []",
+                "INFO overflow 6: []",
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "keep_every")]
+    fn drop_and_sample_rejects_keep_every_of_zero() {
+        let _ = AsyncBuilder::new(slog::Discard).overflow_strategy(
+            OverflowStrategy::DropAndSample { keep_every: 0 },
+        );
+    }
+
+    #[test]
+    fn drop_reporting_can_be_turned_off_at_runtime() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) =
+            AsyncBuilder::new(TeeDrain(gated_drain, mock_drain))
+                .chan_size(1)
+                .overflow_strategy(OverflowStrategy::DropAndReport)
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+        control.set_drop_reporting(false);
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+        log_msg(&async_drain, "dropped").unwrap();
+        drop(release);
+        async_drain.flush().unwrap();
+        log_msg(&async_drain, "after").unwrap();
+        async_drain.flush().unwrap();
+
+        // Counted, but not reported
+        assert_eq!(async_drain.stats().dropped, 1);
+        assert_eq!(async_drain.drop_stats().total, 1);
+        assert_eq!(
+            mock_drain_rx.try_iter().collect::<Vec<_>>(),
+            vec!["INFO parked: []", "INFO queued: []", "INFO after: []"]
+        );
+
+        control.set_drop_reporting(true);
+        async_drain.flush().unwrap();
+        assert_eq!(async_drain.stats().dropped, 0);
+        let report = mock_drain_rx.try_recv().unwrap();
+        assert!(report.contains("(\"count\", \"1\")"), "{}", report);
+    }
+
+    #[test]
+    fn dropped_report_level_and_tag_are_configurable() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (tx, rx) = mpsc::channel();
+        let (async_drain, control) = AsyncBuilder::new(gated_drain)
+            .chan_size(1)
+            .dropped_report_level(Level::Warning)
+            .dropped_report_tag("overload".to_string())
+            .transform(move |r| {
+                tx.send((r.level(), r.tag().to_string())).unwrap();
+                Some(r)
+            })
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+        log_msg(&async_drain, "dropped").unwrap();
+        drop(release);
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+        drop((async_drain, control));
+
+        assert_eq!(
+            rx.iter().collect::<Vec<_>>(),
+            vec![
+                (Level::Info, String::new()),
+                (Level::Info, String::new()),
+                (Level::Warning, "overload".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn submit_batch_rejects_records_finding_the_channel_full() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let core = AsyncCore::custom(gated_drain).chan_size(2).build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        assert_eq!(core.submit_batch(vec![async_record("parked")]).unwrap(), 1);
+        entered.recv().unwrap();
+
+        let batch = (0..4).map(|i| async_record(&format!("batched {}", i)));
+        assert_eq!(core.submit_batch(batch).unwrap(), 2);
+        assert_eq!(core.stats.rejected.load(Ordering::Relaxed), 2);
+        drop(release);
+    }
+
+    #[test]
+    fn async_submit_batch_applies_the_overflow_strategy() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) =
+            AsyncBuilder::new(TeeDrain(mock_drain, gated_drain))
+                .chan_size(1)
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        let batch = (0..3).map(|i| async_record(&format!("batched {}", i)));
+        assert_eq!(async_drain.submit_batch(batch).unwrap(), 1);
+        // The first drop got reported before the last record of the batch
+        assert_eq!(async_drain.dropped_count(), 1);
+        assert_eq!(async_drain.drop_stats().total, 2);
+
+        // Blocking waits for room instead of dropping the rest of the batch
+        async_drain
+            .set_overflow_strategy(OverflowStrategy::Block)
+            .unwrap();
+        let async_drain = Arc::new(async_drain);
+        let producer = {
+            let async_drain = async_drain.clone();
+            thread::spawn(move || {
+                let batch =
+                    (3..6).map(|i| async_record(&format!("batched {}", i)));
+                async_drain.submit_batch(batch).unwrap()
+            })
+        };
+        drop(release);
+        assert_eq!(producer.join().unwrap(), 3);
+        assert_eq!(async_drain.drop_stats().total, 2);
+        drop((async_drain, control));
+
+        let written: Vec<_> = mock_drain_rx
+            .iter()
+            .filter(|line| line.starts_with("INFO"))
+            .collect();
+        assert_eq!(
+            written,
+            vec![
+                "INFO parked: []",
+                "INFO batched 0: []",
+                "INFO batched 3: []",
+                "INFO batched 4: []",
+                "INFO batched 5: []",
+            ]
+        );
+    }
+
+    #[test]
+    fn context_on_error_writes_recent_records() {
+        let (context_drain, context_rx) = MockDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(slog::Discard)
+            .context_on_error(Level::Error, 2, context_drain)
+            .build_with_channel();
+        control.log_level(Level::Info).unwrap();
+        let logger = slog::Logger::root(async_drain.fuse(), o!());
+
+        debug!(logger, "evicted");
+        debug!(logger, "context 1");
+        info!(logger, "context 2");
+        error!(logger, "failure");
+        info!(logger, "after");
+        drop(logger);
+
+        let written: Vec<_> = context_rx.iter().collect();
+        assert_eq!(
+            written,
+            vec![
+                "DEBG context 1: []",
+                "INFO context 2: []",
+                "ERRO failure: []"
+            ]
+        );
+    }
+
+    #[test]
+    fn context_on_error_drain_panics_are_caught() {
+        struct PanickyDrain;
+
+        impl slog::Drain for PanickyDrain {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                _: &Record,
+                _: &OwnedKVList,
+            ) -> Result<Self::Ok, Self::Err> {
+                panic!("context drain failure");
+            }
+        }
+
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCore::custom(mock_drain)
+            .context_on_error(Level::Error, 2, PanickyDrain)
+            .max_restarts(5)
+            .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        core.log(
+            &record!(Level::Error, "", &format_args!("failure"), b!()),
+            &o!().into(),
+        )
+        .unwrap();
+        log_msg(&core, "after").unwrap();
+        core.flush().unwrap();
+        assert_eq!(
+            mock_drain_rx.try_iter().collect::<Vec<_>>(),
+            vec!["ERRO failure: []", "INFO after: []"]
+        );
+    }
+
+    #[test]
+    fn degraded_after_context_drain_max_restarts() {
+        /// Counts the records it panics on
+        struct PanickyDrain(Arc<AtomicUsize>);
+
+        impl slog::Drain for PanickyDrain {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                _: &Record,
+                _: &OwnedKVList,
+            ) -> Result<Self::Ok, Self::Err> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                panic!("context drain failure");
+            }
+        }
+
+        let panics = Arc::new(AtomicUsize::new(0));
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCore::custom(mock_drain)
+            .context_on_error(Level::Error, 0, PanickyDrain(panics.clone()))
+            .max_restarts(1)
+            .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        let error = || {
+            core.log(
+                &record!(Level::Error, "", &format_args!("failure"), b!()),
+                &o!().into(),
+            )
+            .unwrap()
+        };
+
+        error();
+        core.flush().unwrap();
+        assert!(!core.is_degraded());
+        error();
+        core.flush().unwrap();
+        assert!(core.is_degraded());
+
+        // Neither drain is written to anymore
+        error();
+        log_msg(&core, "discarded").unwrap();
+        core.flush().unwrap();
+        assert_eq!(panics.load(Ordering::SeqCst), 2);
+        assert_eq!(mock_drain_rx.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn slow_serialization_is_reported() {
+        struct Slow;
+        impl fmt::Display for Slow {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                thread::sleep(Duration::from_millis(20));
+                write!(f, "slow")
+            }
+        }
+
+        let reported = Arc::new(Mutex::new(vec![]));
+        let reported_clone = reported.clone();
+        let core = AsyncCore::custom(slog::Discard)
+            .slow_serialization(
+                Duration::from_millis(10),
+                move |record, elapsed| {
+                    let msg = record.msg().to_string();
+                    reported_clone.lock().unwrap().push((msg, elapsed));
+                },
+            )
+            .build();
+
+        core.log(
+            &record!(Level::Info, "", &format_args!("fast"), b!()),
+            &o!().into(),
+        )
+        .unwrap();
+        core.log(
+            &record!(Level::Info, "", &format_args!("slow"), b!("v" => %Slow)),
+            &o!().into(),
+        )
+        .unwrap();
+
+        let reported = reported.lock().unwrap();
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].0, "slow");
+        assert!(reported[0].1 >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn sharded_drain_round_robin() {
+        let counters: Vec<_> =
+            (0..3).map(|_| Arc::new(AtomicUsize::new(0))).collect();
+        let drains = counters.iter().cloned().map(CountingDrain).collect();
+        let sharded = ShardedDrain::round_robin(drains);
+
+        for _ in 0..9 {
+            log_msg(&sharded, "msg").unwrap();
+        }
+        for counter in &counters {
+            assert_eq!(counter.load(Ordering::Relaxed), 3);
+        }
+    }
+
+    #[test]
+    fn sharded_drain_by_key() {
+        let (drain_a, rx_a) = MockDrain::new();
+        let (drain_b, rx_b) = MockDrain::new();
+        let sharded = ShardedDrain::by_key(vec![drain_a, drain_b], "user");
+        let logger = slog::Logger::root(sharded.fuse(), o!());
+
+        for user in &["alice", "bob", "alice", "carol", "bob", "alice"] {
+            info!(logger, "login"; "user" => *user);
+        }
+        drop(logger);
+
+        let users = |rx: mpsc::Receiver<String>| {
+            let mut users: Vec<_> = rx
+                .iter()
+                .map(|entry| entry.split('"').nth(3).unwrap().to_owned())
+                .collect();
+            users.sort();
+            users.dedup();
+            users
+        };
+        let (users_a, users_b) = (users(rx_a), users(rx_b));
+        assert_eq!(users_a.len() + users_b.len(), 3);
+        assert!(users_a.iter().all(|user| !users_b.contains(user)));
+    }
+
+    /// Test-helper drain counting the records it gets
+    struct CountingDrain(Arc<AtomicUsize>);
+
+    impl slog::Drain for CountingDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(
+            &self,
+            _record: &Record,
+            _logger_kv: &OwnedKVList,
+        ) -> Result<Self::Ok, Self::Err> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn full_transitions_are_edge_triggered() {
+        let fulls = Arc::new(AtomicUsize::new(0));
+        let recoveries = Arc::new(AtomicUsize::new(0));
+        let (fulls_clone, recoveries_clone) =
+            (fulls.clone(), recoveries.clone());
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let core = AsyncCore::custom(gated_drain)
+            .chan_size(1)
+            .on_full(move || {
+                fulls_clone.fetch_add(1, Ordering::Relaxed);
+            })
+            .on_recovered(move || {
+                recoveries_clone.fetch_add(1, Ordering::Relaxed);
+            })
+            .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        while !core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+
+        log_msg(&core, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&core, "queued").unwrap();
+        assert_eq!(fulls.load(Ordering::Relaxed), 0);
+
+        assert!(log_msg(&core, "full").is_err());
+        assert!(log_msg(&core, "still full").is_err());
+        assert_eq!(fulls.load(Ordering::Relaxed), 1);
+        assert_eq!(recoveries.load(Ordering::Relaxed), 0);
+
+        drop(release);
+        while log_msg(&core, "recovered").is_err() {
+            thread::yield_now();
+        }
+        while !core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+        log_msg(&core, "still recovered").unwrap();
+        assert_eq!(fulls.load(Ordering::Relaxed), 1);
+        assert_eq!(recoveries.load(Ordering::Relaxed), 1);
+    }
+
+    /// Test-helper drain panicking on every record
+    struct PanickingDrain;
+
+    impl slog::Drain for PanickingDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(
+            &self,
+            record: &Record,
+            _logger_kv: &OwnedKVList,
+        ) -> Result<Self::Ok, Self::Err> {
+            panic!("cannot log {}", record.msg());
+        }
+    }
+
+    #[test]
+    fn join_error_includes_panic_message() {
+        let (run, tx, _ctrl_tx, _stats, _worker_thread) =
+            AsyncCore::custom(PanickingDrain).worker();
+        let join = thread::spawn(run);
+        tx.send(AsyncMsg::LogLevel(Level::Trace.into())).unwrap();
+        tx.send(AsyncMsg::Record(async_record("boom"))).unwrap();
+
+        let err = join.join().map_err(join_error).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Logging thread worker join error: cannot log boom"
+        );
+
+        let err = join_error(Box::new("static message"));
+        assert_eq!(
+            err.to_string(),
+            "Logging thread worker join error: static message"
+        );
+    }
+
+    #[test]
+    fn estimated_memory_tracks_queued_records() {
+        let untracked = AsyncCore::custom(slog::Discard).build();
+        assert_eq!(untracked.estimated_memory_bytes(), None);
+
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let core = AsyncCore::custom(gated_drain).track_memory(true).build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        assert_eq!(core.estimated_memory_bytes(), Some(0));
+
+        let parked = async_record("parked");
+        let mut total = parked.size;
+        core.send(parked).unwrap();
+        entered.recv().unwrap();
+        // Held until written
+        assert_eq!(core.estimated_memory_bytes(), Some(total));
+        for msg in &["queued", "a much longer queued message"] {
+            let r = async_record(msg);
+            total += r.size;
+            core.send(r).unwrap();
+            assert_eq!(core.estimated_memory_bytes(), Some(total));
+        }
+
+        drop(release);
+        core.flush().unwrap();
+        assert_eq!(core.estimated_memory_bytes(), Some(0));
+    }
+
+    #[test]
+    fn memory_budget_bounds_queued_records() {
+        let size = async_record("record").size;
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let core = AsyncCore::custom(gated_drain)
+            .chan_size(100)
+            .memory_budget(3 * size)
+            .build();
+        let budget = core.memory_budget.clone().unwrap();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        log_msg(&core, "record").unwrap();
+        entered.recv().unwrap();
+        let accepted =
+            (0..20).filter(|_| log_msg(&core, "record").is_ok()).count();
+        assert_eq!(accepted, 2);
+        assert_eq!(budget.used.load(Ordering::Relaxed), 3 * size);
+
+        drop(release);
+        drop(core);
+        assert_eq!(budget.used.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn memory_budget_blocks_with_block_strategy() {
+        let size = async_record("record").size;
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) =
+            AsyncBuilder::new(TeeDrain(mock_drain, gated_drain))
+                .chan_size(100)
+                .memory_budget(2 * size)
+                .overflow_strategy(OverflowStrategy::Block)
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+        let async_drain = Arc::new(async_drain);
+        let (logged_tx, logged_rx) = mpsc::channel();
+        let producer = {
+            let async_drain = async_drain.clone();
+            thread::spawn(move || {
+                for i in 0..3 {
+                    log_msg(&*async_drain, &format!("waiting {}", i)).unwrap();
+                    logged_tx.send(i).unwrap();
+                }
+            })
+        };
+        // Over the budget: the producer waits instead of dropping
+        assert!(logged_rx.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(release);
+        producer.join().unwrap();
+        assert_eq!(logged_rx.iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(async_drain.drop_stats().total, 0);
+        drop((async_drain, control));
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec![
+                "INFO parked: []",
+                "INFO queued: []",
+                "INFO waiting 0: []",
+                "INFO waiting 1: []",
+                "INFO waiting 2: []",
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_budget_covers_control_channel() {
+        let size = async_record("record").size;
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let core = AsyncCore::custom(gated_drain)
+            .memory_budget(2 * size)
+            .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        log_msg(&core, "record").unwrap();
+        entered.recv().unwrap();
+        core.send_control(async_record("record")).unwrap();
+        assert!(matches!(
+            core.send_control(async_record("record")),
+            Err(AsyncError::Full)
+        ));
+        drop(release);
+    }
+
+    #[test]
+    fn memory_budget_evicts_retry_queue() {
+        struct FailingDrain {
+            failing: Arc<AtomicBool>,
+            drain: MockDrain,
+        }
+
+        impl slog::Drain for FailingDrain {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                record: &Record,
+                logger_kv: &OwnedKVList,
+            ) -> Result<Self::Ok, Self::Err> {
+                if self.failing.load(Ordering::SeqCst) {
+                    panic!("drain failure");
+                }
+                self.drain.log(record, logger_kv)
+            }
+        }
+
+        let size = async_record("msg 1").size;
+        let failing = Arc::new(AtomicBool::new(true));
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let drain = FailingDrain {
+            failing: failing.clone(),
+            drain: mock_drain,
+        };
+        let core = AsyncCore::custom(drain)
+            .retry_queue(10)
+            .memory_budget(2 * size)
+            .build();
+        let budget = core.memory_budget.clone().unwrap();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        log_msg(&core, "msg 1").unwrap();
+        log_msg(&core, "msg 2").unwrap();
+        // Rejected until "msg 1" is evicted from the retry queue
+        while log_msg(&core, "msg 3").is_err() {
+            thread::yield_now();
+        }
+        assert!(budget.used.load(Ordering::Relaxed) <= 2 * size);
+
+        failing.store(false, Ordering::SeqCst);
+        core.flush().unwrap();
+        assert_eq!(
+            mock_drain_rx.try_iter().collect::<Vec<_>>(),
+            vec!["INFO msg 2: []", "INFO msg 3: []"]
+        );
+        assert_eq!(budget.used.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn memory_budget_evicts_error_context() {
+        let size = async_record("msg 1").size;
+        let (context_drain, context_rx) = MockDrain::new();
+        let core = AsyncCore::custom(slog::Discard)
+            .memory_budget(2 * size)
+            .context_on_error(Level::Error, 10, context_drain)
+            .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        log_msg(&core, "msg 1").unwrap();
+        log_msg(&core, "msg 2").unwrap();
+        while log_msg(&core, "msg 3").is_err() {
+            thread::yield_now();
+        }
+        while core
+            .log(
+                &record!(Level::Error, "", &format_args!("error"), b!()),
+                &o!().into(),
+            )
+            .is_err()
+        {
+            thread::yield_now();
+        }
+        drop(core);
+
+        let written: Vec<_> = context_rx.iter().collect();
+        assert_eq!(written, vec!["INFO msg 3: []", "ERRO error: []"]);
+    }
+
+    #[test]
+    fn drop_report_bypasses_full_channel() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) =
+            AsyncBuilder::new(TeeDrain(mock_drain, gated_drain))
+                .chan_size(1)
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+        // The main channel stays full, the first drop gets reported on the
+        // next call and the following ones once the report got through.
+        log_msg(&async_drain, "dropped").unwrap();
+        log_msg(&async_drain, "dropped").unwrap();
+        log_msg(&async_drain, "dropped").unwrap();
+
+        drop(release);
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+        drop(async_drain);
+        let written: Vec<_> = mock_drain_rx.iter().collect();
+        assert_eq!(
+            written,
+            vec![
+                "INFO parked: []",
+                "ERRO slog-async: logger dropped messages due to channel \
+                 overflow: [(\"count\", \"1\")]",
+                "INFO queued: []",
+                "ERRO slog-async: logger dropped messages due to channel \
+                 overflow: [(\"count\", \"2\")]",
+            ]
+        );
+    }
+
+    #[test]
+    fn on_overflow_replaces_drop_report() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let reported_by_callback = reported.clone();
+        let (async_drain, control) =
+            AsyncBuilder::new(TeeDrain(mock_drain, gated_drain))
+                .chan_size(1)
+                .on_overflow(move |dropped| {
+                    reported_by_callback.lock().unwrap().push(dropped)
+                })
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+        // Each drop is reported on the next call, there's no report record
+        // to wait for.
+        log_msg(&async_drain, "dropped").unwrap();
+        log_msg(&async_drain, "dropped").unwrap();
+        log_msg(&async_drain, "dropped").unwrap();
+        assert_eq!(*reported.lock().unwrap(), vec![1, 1]);
+
+        drop(release);
+        drop(async_drain);
+        assert_eq!(*reported.lock().unwrap(), vec![1, 1, 1]);
+        let written: Vec<_> = mock_drain_rx.iter().collect();
+        assert_eq!(written, vec!["INFO parked: []", "INFO queued: []"]);
+    }
+
+    #[test]
+    fn from_env_parses_variables() {
+        let vars = |key: &str| match key {
+            "SLOG_ASYNC_CHAN_SIZE" => Ok(" 512".to_string()),
+            "SLOG_ASYNC_OVERFLOW" => Ok("Block".to_string()),
+            "SLOG_ASYNC_THREAD_NAME" => Ok("logger".to_string()),
+            _ => Err(env::VarError::NotPresent),
+        };
+        let builder = AsyncBuilder::new(slog::Discard).env_overrides(vars);
+        assert_eq!(builder.core.chan_size, 512);
+        assert_eq!(builder.options.overflow_strategy, OverflowStrategy::Block);
+        assert_eq!(builder.core.thread_name, Some("logger".to_string()));
+
+        let invalid = |key: &str| match key {
+            "SLOG_ASYNC_CHAN_SIZE" => Ok("-1".to_string()),
+            "SLOG_ASYNC_OVERFLOW" => Ok("sometimes".to_string()),
+            _ => Err(env::VarError::NotPresent),
+        };
+        let builder = AsyncBuilder::new(slog::Discard)
+            .chan_size(16)
+            .env_overrides(invalid);
+        assert_eq!(builder.core.chan_size, 16);
+        assert_eq!(
+            builder.options.overflow_strategy,
+            OverflowStrategy::DropAndReport
+        );
+        assert_eq!(builder.core.thread_name, None);
+
+        assert_eq!(
+            parse_overflow_strategy("drop_and_report"),
+            Some(OverflowStrategy::DropAndReport)
+        );
+        assert_eq!(
+            parse_overflow_strategy("DROP"),
+            Some(OverflowStrategy::Drop)
+        );
+        assert_eq!(parse_overflow_strategy(""), None);
+    }
+
+    #[test]
+    fn migrate_to_does_not_lose_records() {
+        let (old_drain, old_rx) = MockDrain::new();
+        let (new_drain, new_rx) = MockDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(old_drain)
+            .chan_size(8)
+            .overflow_strategy(OverflowStrategy::Block)
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let async_drain = Arc::new(async_drain);
+
+        let (halfway_tx, halfway_rx) = mpsc::channel();
+        let (migrated_tx, migrated_rx) = mpsc::channel::<()>();
+        let producer = {
+            let async_drain = async_drain.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    if i == 500 {
+                        halfway_tx.send(()).unwrap();
+                    }
+                    log_msg(&*async_drain, &i.to_string()).unwrap();
+                }
+                let _ = migrated_rx.recv();
+                for i in 1000..1100 {
+                    log_msg(&*async_drain, &i.to_string()).unwrap();
+                }
+            })
+        };
+        halfway_rx.recv().unwrap();
+        async_drain.migrate_to(new_drain).unwrap();
+        drop(migrated_tx);
+        producer.join().unwrap();
+        drop(async_drain);
+
+        let old: Vec<_> = old_rx.iter().collect();
+        let new: Vec<_> = new_rx.iter().collect();
+        assert!(new.len() >= 100);
+        let written: Vec<_> = old.into_iter().chain(new).collect();
+        let expected: Vec<_> =
+            (0..1100).map(|i| format!("INFO {}: []", i)).collect();
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn migrate_to_writes_queued_records_to_the_old_drain() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (old_drain, old_rx) = MockDrain::new();
+        let (new_drain, new_rx) = MockDrain::new();
+        let (async_drain, control) =
+            AsyncBuilder::new(TeeDrain(old_drain, gated_drain))
+                .chan_size(8)
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        for i in 0..3 {
+            log_msg(&async_drain, &format!("queued {}", i)).unwrap();
+        }
+        // Let the old drain go on once the migration is waiting for it
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(release);
+        });
+        async_drain.migrate_to(new_drain).unwrap();
+        log_msg(&async_drain, "migrated").unwrap();
+        releaser.join().unwrap();
+        drop((async_drain, control));
+
+        assert_eq!(
+            old_rx.iter().collect::<Vec<_>>(),
+            vec![
+                "INFO parked: []",
+                "INFO queued 0: []",
+                "INFO queued 1: []",
+                "INFO queued 2: []",
+            ]
+        );
+        assert_eq!(
+            new_rx.iter().collect::<Vec<_>>(),
+            vec!["INFO migrated: []"]
+        );
+    }
+
+    #[test]
+    fn drain_can_log_to_its_own_logger() {
+        struct EchoDrain {
+            logger: Arc<Mutex<Option<slog::Logger>>>,
+            inner: MockDrain,
+        }
+
+        impl slog::Drain for EchoDrain {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                record: &Record,
+                logger_kv: &OwnedKVList,
+            ) -> Result<Self::Ok, Self::Err> {
+                let msg = record.msg().to_string();
+                if !msg.starts_with("echo") {
+                    if let Some(ref logger) = *self.logger.lock().unwrap() {
+                        info!(logger, "echo {}", msg);
+                    }
+                }
+                self.inner.log(record, logger_kv)
+            }
+        }
+
+        let shared_logger = Arc::new(Mutex::new(None));
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let drain = EchoDrain {
+            logger: shared_logger.clone(),
+            inner: mock_drain,
+        };
+        // Blocking on the channel from the worker thread would dead-lock
+        let (async_drain, control) = AsyncBuilder::new(drain)
+            .chan_size(1)
+            .overflow_strategy(OverflowStrategy::Block)
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let logger = slog::Logger::root(async_drain.fuse(), o!());
+        *shared_logger.lock().unwrap() = Some(logger.clone());
+
+        info!(logger, "one");
+        info!(logger, "two");
+        info!(logger, "three");
+        let written: Vec<_> = mock_drain_rx.iter().take(6).collect();
+        shared_logger.lock().unwrap().take();
+        drop(logger);
+
+        assert_eq!(
+            written,
+            vec![
+                "INFO one: []",
+                "INFO echo one: []",
+                "INFO two: []",
+                "INFO echo two: []",
+                "INFO three: []",
+                "INFO echo three: []",
+            ]
+        );
+    }
+
+    #[test]
+    fn drop_reports_are_rate_limited() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        // The logged records don't fit the memory budget, so every one gets
+        // dropped while the smaller reports still get through
+        let msgs: Vec<_> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|msg| msg.repeat(1000))
+            .collect();
+        let (async_drain, control) = AsyncBuilder::new(mock_drain)
+            .memory_budget(async_record(&msgs[0]).size - 1)
+            .drop_report_interval(Duration::from_secs(3600))
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+
+        for msg in &msgs {
+            while !async_drain.core.ctrl_sender.is_empty() {
+                thread::yield_now();
+            }
+            log_msg(&async_drain, msg).unwrap();
+        }
+        drop(async_drain);
+
+        let written: Vec<_> = mock_drain_rx.iter().collect();
+        assert_eq!(
+            written,
+            vec![
+                "ERRO slog-async: logger dropped messages due to channel \
+                 overflow: [(\"count\", \"1\")]",
+                "ERRO slog-async: logger dropped messages due to channel \
+                 overflow: [(\"count\", \"3\")]",
+            ]
+        );
+    }
+
+    #[test]
+    fn processed_count_advances() {
+        let core = AsyncCore::new(slog::Discard);
+        assert_eq!(core.processed_count(), 0);
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Info.into()))
+            .unwrap();
+
+        for _ in 0..3 {
+            log_msg(&core, "counted").unwrap();
+        }
+        core.log(
+            &record!(Level::Debug, "", &format_args!("filtered"), b!()),
+            &o!().into(),
+        )
+        .unwrap();
+        log_msg(&core, "counted").unwrap();
+        while core.processed_count() < 4 {
+            thread::yield_now();
+        }
+        assert_eq!(core.processed_count(), 4);
+    }
+
+    #[test]
+    fn lazy_kv_is_computed_only_for_written_records() {
+        let computed = Arc::new(Mutex::new(vec![]));
+        let lazy = |name: &'static str| {
+            let computed = computed.clone();
+            lazy_kv("lazy", move || {
+                computed.lock().unwrap().push(name);
+                name.to_uppercase()
+            })
+        };
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) =
+            AsyncBuilder::new(mock_drain).build_with_channel();
+        control.log_level(Level::Info).unwrap();
+        let logger = slog::Logger::root(async_drain.fuse(), o!());
+
+        debug!(logger, "filtered"; lazy("filtered"));
+        info!(logger, "written"; lazy("written"));
+        drop(logger);
+
+        let written: Vec<_> = mock_drain_rx.iter().collect();
+        assert_eq!(written, vec!["INFO written: [(\"lazy\", \"WRITTEN\")]"]);
+        assert_eq!(*computed.lock().unwrap(), vec!["written"]);
+    }
+
+    #[test]
+    fn lazy_kv_is_not_computed_for_sampled_out_records() {
+        let computed = Arc::new(Mutex::new(vec![]));
+        let lazy = |name: &'static str| {
+            let computed = computed.clone();
+            lazy_kv("lazy", move || {
+                computed.lock().unwrap().push(name);
+                name.to_uppercase()
+            })
+        };
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(mock_drain)
+            .sample_by_key("user", Duration::from_secs(3600))
+            .build_with_channel();
+        control.log_level(Level::Info).unwrap();
+        let logger = slog::Logger::root(async_drain.fuse(), o!());
+
+        info!(logger, "rate"; SAMPLE_RATE_KEY => 0.0, lazy("rate"));
+        info!(logger, "first"; "user" => 1, lazy("first"));
+        info!(logger, "repeat"; "user" => 1, lazy("repeat"));
+        info!(logger, "kept"; SAMPLE_RATE_KEY => 1.0, lazy("kept"));
+        drop(logger);
+
+        let written: Vec<_> = mock_drain_rx
+            .iter()
+            .filter(|r| !r.contains("suppressed"))
+            .collect();
+        assert_eq!(
+            written,
+            vec![
+                "INFO first: [(\"lazy\", \"FIRST\"), (\"user\", \"1\")]",
+                "INFO kept: [(\"lazy\", \"KEPT\"), \
+                 (\"sample_rate\", \"1\")]",
+            ]
+        );
+        assert_eq!(*computed.lock().unwrap(), vec!["first", "kept"]);
+    }
+
+    #[test]
+    fn lazy_kv_panics_unwind_into_the_worker() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(mock_drain)
+            .max_restarts(1)
+            .build_with_channel();
+        control.log_level(Level::Info).unwrap();
+        let logger = slog::Logger::root(async_drain.fuse(), o!());
+
+        info!(logger, "panics"; lazy_kv("lazy", || panic!("lazy failure")));
+        info!(logger, "after");
+        drop(logger);
+
+        let written: Vec<_> = mock_drain_rx.iter().collect();
+        assert_eq!(written, vec!["INFO after: []"]);
+    }
+
+    /// Test-helper batch drain sending the messages of every batch
+    struct MockBatchDrain(mpsc::Sender<Vec<String>>);
+
+    impl BatchDrain for MockBatchDrain {
+        fn log_batch(&self, records: &[AsyncRecord]) {
+            let msgs = records.iter().map(|r| r.msg.to_string()).collect();
+            self.0.send(msgs).unwrap();
+        }
+    }
+
+    /// Test-helper batch drain counting batch sizes, failing on batches
+    /// above a size
+    struct CountingBatchDrain {
+        sizes: Arc<Mutex<Vec<usize>>>,
+        max: usize,
+    }
+
+    impl TryBatchDrain for CountingBatchDrain {
+        type Err = usize;
+
+        fn try_log_batch(&self, records: &[AsyncRecord]) -> Result<(), usize> {
+            if records.len() > self.max {
+                return Err(records.len());
+            }
+            self.sizes.lock().unwrap().push(records.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn batches_are_logged_with_one_call() {
+        let sizes = Arc::new(Mutex::new(vec![]));
+        let drain = CountingBatchDrain {
+            sizes: sizes.clone(),
+            max: 3,
+        };
+        let records: Vec<_> =
+            (0..4).map(|i| async_record(&i.to_string())).collect();
+        assert_eq!(AsyncRecord::log_batch_to(&records[..3], &drain), Ok(()));
+        assert_eq!(AsyncRecord::log_batch_to(&records, &drain), Err(4));
+        // A `BatchDrain` never fails
+        let (tx, rx) = mpsc::channel();
+        assert!(
+            AsyncRecord::log_batch_to(&records, &MockBatchDrain(tx)).is_ok()
+        );
+        assert_eq!(rx.recv().unwrap().len(), 4);
+
+        // Through the worker thread, a failing batch panics it
+        let core = AsyncCore::custom_batch(
+            FuseBatch(drain),
+            4,
+            Duration::from_secs(3600),
+        )
+        .max_restarts(1)
+        .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        for i in 0..6 {
+            log_msg(&core, &i.to_string()).unwrap();
+        }
+        // The first batch panicked, the rest gets flushed when finishing
+        drop(core);
+        assert_eq!(*sizes.lock().unwrap(), vec![3, 2]);
+    }
+
+    #[test]
+    fn batches_are_flushed_by_count() {
+        let (tx, rx) = mpsc::channel();
+        let core = AsyncCore::custom_batch(
+            MockBatchDrain(tx),
+            3,
+            Duration::from_secs(3600),
+        )
+        .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        for i in 0..7 {
+            log_msg(&core, &i.to_string()).unwrap();
+        }
+        assert_eq!(rx.recv().unwrap(), vec!["0", "1", "2"]);
+        assert_eq!(rx.recv().unwrap(), vec!["3", "4", "5"]);
+        // The rest gets flushed when finishing
+        drop(core);
+        assert_eq!(rx.recv().unwrap(), vec!["6"]);
+    }
+
+    #[test]
+    fn batches_are_flushed_by_time() {
+        let (tx, rx) = mpsc::channel();
+        let core = AsyncCore::custom_batch(
+            MockBatchDrain(tx),
+            100,
+            Duration::from_millis(100),
+        )
+        .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        log_msg(&core, "a").unwrap();
+        log_msg(&core, "b").unwrap();
+        let batch = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(batch, vec!["a", "b"]);
+        log_msg(&core, "c").unwrap();
+        let batch = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(batch, vec!["c"]);
+    }
+
+    #[test]
+    fn flush_waits_for_both_channels() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCore::custom(mock_drain).chan_size(4).build();
+        core.ctrl_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        for i in 0..100 {
+            if i % 10 == 0 {
+                core.send_control(async_record("control")).unwrap();
+            }
+            core.send_record(async_record(&i.to_string()), true)
+                .unwrap();
+        }
+        core.flush().unwrap();
+        assert_eq!(mock_drain_rx.try_iter().count(), 110);
+    }
+
+    #[test]
+    fn flush_writes_pending_batch() {
+        let (tx, rx) = mpsc::channel();
+        let core = AsyncCore::custom_batch(
+            MockBatchDrain(tx),
+            100,
+            Duration::from_secs(3600),
+        )
+        .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        log_msg(&core, "a").unwrap();
+        core.flush().unwrap();
+        assert_eq!(rx.try_recv().unwrap(), vec!["a"]);
+    }
+
+    #[test]
+    fn sample_by_key_suppresses_repeats() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCore::custom(mock_drain)
+            .sample_by_key("user", Duration::from_millis(200))
+            .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        let log_user = |user: u32| {
+            core.log(
+                &record!(
+                    Level::Error,
+                    "",
+                    &format_args!("failed"),
+                    b!("user" => user)
+                ),
+                &o!().into(),
+            )
+            .unwrap();
+        };
+
+        let start = unix_millis(SystemTime::now());
+        log_user(1);
+        log_user(2);
+        log_user(1);
+        log_user(1);
+        log_msg(&core, "no user").unwrap();
+        core.flush().unwrap();
+        let end = unix_millis(SystemTime::now());
+        thread::sleep(Duration::from_millis(250));
+        log_user(1);
+        drop(core);
+
+        let mut written: Vec<_> = mock_drain_rx.iter().collect();
+        let summary = written.remove(3);
+        assert_eq!(
+            written,
+            vec![
+                "ERRO failed: [(\"user\", \"1\")]",
+                "ERRO failed: [(\"user\", \"2\")]",
+                "INFO no user: []",
+                "ERRO failed: [(\"user\", \"1\")]",
+            ]
+        );
+        assert!(summary.starts_with("ERRO slog-async: suppressed repeated"));
+        for kv in &[
+            "(\"sampled_key\", \"user\")",
+            "(\"sampled_value\", \"1\")",
+            "(\"suppressed\", \"2\")",
+            "(\"sample_msg\", \"failed\")",
+        ] {
+            assert!(summary.contains(kv), "{} missing in {}", kv, summary);
+        }
+        let timestamp = |key: &str| -> u64 {
+            let prefix = format!("(\"{}\", \"", key);
+            let start = summary.find(&prefix).unwrap() + prefix.len();
+            let len = summary[start..].find('"').unwrap();
+            summary[start..start + len].parse().unwrap()
+        };
+        let first = timestamp("first_suppressed");
+        let last = timestamp("last_suppressed");
+        assert!(start <= first && first <= last && last <= end);
+    }
+
+    #[test]
+    fn error_mirror_writes_errors_synchronously() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (file_drain, file_rx) = MockDrain::new();
+        let (stderr_drain, stderr_rx) = MockDrain::new();
+        let (drain, control) = Async::file_with_error_mirror(
+            TeeDrain(file_drain, gated_drain),
+            stderr_drain,
+            Level::Error,
+        );
+        control.log_level(Level::Trace).unwrap();
+        let logger = slog::Logger::root(drain.fuse(), o!());
+
+        info!(logger, "parked");
+        entered.recv().unwrap();
+        error!(logger, "failure");
+        // Already written to stderr while the worker thread is stuck
+        assert_eq!(stderr_rx.try_recv().unwrap(), "ERRO failure: []");
+        assert_eq!(file_rx.try_recv().unwrap(), "INFO parked: []");
+        assert!(file_rx.try_recv().is_err());
+
+        drop(release);
+        drop(logger);
+        assert_eq!(
+            file_rx.iter().collect::<Vec<_>>(),
+            vec!["ERRO failure: []"]
+        );
+        assert!(stderr_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn degraded_after_max_restarts() {
+        struct PanickyDrain(MockDrain);
+
+        impl slog::Drain for PanickyDrain {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                record: &Record,
+                logger_kv: &OwnedKVList,
+            ) -> Result<Self::Ok, Self::Err> {
+                if record.msg().to_string() == "panic" {
+                    panic!("drain failure");
+                }
+                self.0.log(record, logger_kv)
+            }
+        }
+
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCore::custom(PanickyDrain(mock_drain))
+            .max_restarts(2)
+            .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        for _ in 0..2 {
+            log_msg(&core, "panic").unwrap();
+            log_msg(&core, "survived").unwrap();
+        }
+        core.flush().unwrap();
+        assert!(!core.is_degraded());
+        assert_eq!(mock_drain_rx.try_iter().count(), 2);
+
+        log_msg(&core, "panic").unwrap();
+        log_msg(&core, "discarded").unwrap();
+        core.flush().unwrap();
+        assert!(core.is_degraded());
+        assert_eq!(core.processed_count(), 2);
+        drop(core);
+        assert_eq!(mock_drain_rx.iter().count(), 0);
+    }
+
+    #[test]
+    fn retry_queue_writes_failed_records_once_recovered() {
+        /// Fails the first writes, then recovers
+        struct FlakyDrain {
+            failures: AtomicUsize,
+            drain: MockDrain,
+        }
+
+        impl slog::Drain for FlakyDrain {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                record: &Record,
+                logger_kv: &OwnedKVList,
+            ) -> Result<Self::Ok, Self::Err> {
+                if self.failures.load(Ordering::SeqCst) > 0 {
+                    self.failures.fetch_sub(1, Ordering::SeqCst);
+                    panic!("drain failure");
+                }
+                self.drain.log(record, logger_kv)
+            }
+        }
+
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let drain = FlakyDrain {
+            failures: AtomicUsize::new(3),
+            drain: mock_drain,
+        };
+        let core = AsyncCore::custom(drain).retry_queue(2).build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        // "one" fails and gets queued, the retries of "one" fail before
+        // "two" and "three", which are queued behind it, pushing it out.
+        for msg in &["one", "two", "three"] {
+            log_msg(&core, msg).unwrap();
+        }
+        // Recovered: the flush retries the queue
+        core.flush().unwrap();
+        assert_eq!(
+            mock_drain_rx.try_iter().collect::<Vec<_>>(),
+            vec!["INFO two: []", "INFO three: []"]
+        );
+
+        log_msg(&core, "four").unwrap();
+        core.flush().unwrap();
+        assert_eq!(
+            mock_drain_rx.try_iter().collect::<Vec<_>>(),
+            vec!["INFO four: []"]
+        );
+        assert_eq!(core.processed_count(), 3);
+    }
+
+    #[test]
+    fn retry_queue_retries_drain_errors() {
+        /// Returns an error for the first write, then recovers
+        struct FlakyDrain {
+            failed: AtomicBool,
+            drain: MockDrain,
+        }
+
+        impl slog::Drain for FlakyDrain {
+            type Ok = ();
+            type Err = io::Error;
+
+            fn log(
+                &self,
+                record: &Record,
+                logger_kv: &OwnedKVList,
+            ) -> io::Result<()> {
+                if !self.failed.swap(true, Ordering::SeqCst) {
+                    return Err(io::Error::other("connection reset"));
+                }
+                self.drain.log(record, logger_kv).map_err(|e| match e {})
+            }
+        }
+
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let drain = FlakyDrain {
+            failed: AtomicBool::new(false),
+            drain: mock_drain,
+        };
+        let core = AsyncCore::custom_retrying(drain, 2).build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        // "one" fails and gets queued, then is retried before "two"
+        log_msg(&core, "one").unwrap();
+        log_msg(&core, "two").unwrap();
+        core.flush().unwrap();
+        assert_eq!(
+            mock_drain_rx.try_iter().collect::<Vec<_>>(),
+            vec!["INFO one: []", "INFO two: []"]
+        );
+        assert_eq!(core.processed_count(), 2);
+    }
+
+    #[test]
+    fn location_accessors() {
+        let r = async_record("msg");
+        assert_eq!(r.file(), file!());
+        assert_eq!(r.module(), module_path!());
+        assert_eq!(r.function(), "");
+        r.as_record_values(|record, _| {
+            assert_eq!(r.line(), record.line());
+            assert_eq!(r.column(), record.column());
+        });
+    }
+
+    #[test]
+    fn spawn_with_custom_spawner() {
+        let spawned = Arc::new(AtomicBool::new(false));
+        let spawned_clone = spawned.clone();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCore::custom(mock_drain)
+            .spawn_with(move |run| {
+                spawned_clone.store(true, Ordering::SeqCst);
+                thread::Builder::new()
+                    .name("custom-worker".into())
+                    .spawn(run)
+                    .unwrap()
+            })
+            .build();
+        assert!(spawned.load(Ordering::SeqCst));
+
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        log_msg(&core, "msg").unwrap();
+        drop(core);
+        assert_eq!(mock_drain_rx.iter().count(), 1);
+    }
+
+    #[test]
+    fn max_queue_depth_tracks_the_peak() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(gated_drain)
+            .chan_size(8)
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+        assert_eq!(async_drain.max_queue_depth(), 0);
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        for _ in 0..5 {
+            log_msg(&async_drain, "burst").unwrap();
+        }
+        assert_eq!(async_drain.max_queue_depth(), 5);
+
+        drop(release);
+        async_drain.flush().unwrap();
+        log_msg(&async_drain, "after the burst").unwrap();
+        assert_eq!(async_drain.max_queue_depth(), 5);
+        async_drain.flush().unwrap();
+        assert_eq!(async_drain.reset_max_depth(), 5);
+        assert_eq!(async_drain.max_queue_depth(), 0);
+    }
+
+    #[test]
+    fn stats_snapshot_is_consistent() {
+        let (gated_drain, entered_rx, release_tx) = GatedDrain::new();
+        let async_drain = Async::new(gated_drain)
+            .chan_size(4)
+            .overflow_strategy(OverflowStrategy::DropAndReport)
+            .build();
+        async_drain
+            .core
+            .ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        log_msg(&async_drain, "parks the worker").unwrap();
+        entered_rx.recv().unwrap();
+
+        for _ in 0..5 {
+            log_msg(&async_drain, "queued or dropped").unwrap();
+        }
+        let stats = async_drain.stats();
+        assert_eq!(
+            stats,
+            AsyncStatsSnapshot {
+                depth: 4,
+                capacity: 4,
+                processed: 0,
+                dropped: 1,
+                load_factor: 1.0,
+                worker_alive: true,
+                fallback_count: 0,
+                fallback_duration: Duration::from_secs(0),
+            }
+        );
+        assert_eq!(stats.processed, async_drain.processed_count());
+
+        drop(release_tx);
+        async_drain.flush().unwrap();
+        let stats = async_drain.stats();
+        assert_eq!(stats.depth, 0);
+        assert_eq!(stats.load_factor, 0.0);
+        assert_eq!(stats.processed, 6);
+        assert_eq!(stats.dropped, 0);
+    }
+
+    #[test]
+    fn records_wait_for_slow_thread_start() {
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let async_drain = Async::new(mock_drain)
+            .chan_size(1)
+            .on_thread_start(move || {
+                let _ = release_rx.recv();
+            })
+            .build();
+        async_drain
+            .core
+            .ctrl_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        assert!(!async_drain.wait_ready(Duration::from_millis(10)));
+
+        let release = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(release_tx);
+        });
+        // More than the channel holds: blocks until the hook returned
+        for _ in 0..3 {
+            log_msg(&async_drain, "early").unwrap();
+        }
+        release.join().unwrap();
+        assert!(async_drain.wait_ready(Duration::from_secs(1)));
+        drop(async_drain);
+        assert_eq!(mock_drain_rx.iter().count(), 3);
+    }
+
+    #[test]
+    fn tagged_records_go_to_registered_sinks() {
+        let (main_drain, main_rx) = MockDrain::new();
+        let (plugin_drain, plugin_rx) = MockDrain::new();
+        let (async_drain, control) =
+            Async::new(main_drain).build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let log_tagged = |msg: &str| {
+            async_drain
+                .log(
+                    &record!(
+                        Level::Info,
+                        "plugin",
+                        &format_args!("{}", msg),
+                        b!()
+                    ),
+                    &o!().into(),
+                )
+                .unwrap();
+        };
+
+        log_tagged("before");
+        async_drain.flush().unwrap();
+        async_drain.register_sink("plugin", plugin_drain).unwrap();
+        log_tagged("registered");
+        log_msg(&async_drain, "untagged").unwrap();
+        async_drain.flush().unwrap();
+        async_drain.unregister_sink("plugin").unwrap();
+        log_tagged("unregistered");
+        drop(async_drain);
+
+        assert_eq!(
+            main_rx.iter().collect::<Vec<_>>(),
+            vec![
+                "INFO before: []",
+                "INFO untagged: []",
+                "INFO unregistered: []"
+            ]
+        );
+        assert_eq!(
+            plugin_rx.iter().collect::<Vec<_>>(),
+            vec!["INFO registered: []"]
+        );
+    }
+
+    #[test]
+    fn huge_chan_size_is_not_preallocated() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCore::custom(mock_drain).chan_size(usize::MAX).build();
+        assert_eq!(core.stats().capacity, usize::MAX);
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        log_msg(&core, "msg").unwrap();
+        drop(core);
+        assert_eq!(mock_drain_rx.iter().count(), 1);
+    }
+
+    #[test]
+    fn huge_chan_size_still_blocks() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let core = Arc::new(
+            AsyncCore::custom(gated_drain)
+                .chan_size(MAX_PREALLOCATED_CHAN_SIZE + 1)
+                .blocking(true)
+                .build(),
+        );
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        // Park the worker inside the drain, then fill up the channel.
+        log_msg(&*core, "parked").unwrap();
+        entered.recv().unwrap();
+        for _ in 0..=MAX_PREALLOCATED_CHAN_SIZE {
+            log_msg(&*core, "queued").unwrap();
+        }
+
+        let (sent_tx, sent_rx) = mpsc::channel();
+        let blocked = {
+            let core = core.clone();
+            thread::spawn(move || {
+                log_msg(&*core, "blocked").unwrap();
+                sent_tx.send(()).unwrap();
+            })
+        };
+        assert!(sent_rx.recv_timeout(Duration::from_millis(50)).is_err());
+        assert_eq!(core.ref_sender.len(), MAX_PREALLOCATED_CHAN_SIZE + 1);
+        drop(release);
+        sent_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        blocked.join().unwrap();
+    }
+
+    #[test]
+    fn elapsed_field_increases() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) = Async::new(mock_drain)
+            .elapsed_field("elapsed_ms")
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        for _ in 0..3 {
+            thread::sleep(Duration::from_millis(2));
+            log_msg(&async_drain, "msg").unwrap();
+        }
+        drop(async_drain);
+
+        let elapsed: Vec<u64> = mock_drain_rx
+            .iter()
+            .map(|entry| {
+                let value = entry
+                    .split("(\"elapsed_ms\", \"")
+                    .nth(1)
+                    .expect("elapsed field");
+                value[..value.find('"').unwrap()].parse().unwrap()
+            })
+            .collect();
+        assert_eq!(elapsed.len(), 3);
+        assert!(elapsed[0] >= 2);
+        assert!(elapsed.windows(2).all(|w| w[0] < w[1]), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn drop_stats_track_bursts() {
+        let (gated_drain, entered_rx, release_tx) = GatedDrain::new();
+        let async_drain = Async::new(gated_drain)
+            .chan_size(2)
+            .overflow_strategy(OverflowStrategy::Drop)
+            .build();
+        async_drain
+            .core
+            .ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        assert_eq!(async_drain.drop_stats(), DropStats::default());
+
+        log_msg(&async_drain, "parks the worker").unwrap();
+        entered_rx.recv().unwrap();
+        let before = Instant::now();
+        for _ in 0..5 {
+            log_msg(&async_drain, "queued or dropped").unwrap();
+        }
+        let stats = async_drain.drop_stats();
+        assert_eq!(stats.total, 3);
+        assert_eq!(stats.max_burst, 3);
+        assert!(stats.last_drop.unwrap() >= before);
+
+        // Let one queued record through, then drop a shorter burst
+        release_tx.send(()).unwrap();
+        entered_rx.recv().unwrap();
+        log_msg(&async_drain, "sent").unwrap();
+        log_msg(&async_drain, "dropped").unwrap();
+        let stats = async_drain.drop_stats();
+        assert_eq!(stats.total, 4);
+        assert_eq!(stats.max_burst, 3);
+        drop(release_tx);
+    }
+
+    #[test]
+    fn value_policies() {
+        /// Records the type each value was emitted as
+        #[derive(Default)]
+        struct TypeSerializer(Vec<String>);
+
+        impl slog::Serializer for TypeSerializer {
+            fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
+                self.0.push(format!("{}=u64:{}", key, val));
+                Ok(())
+            }
+            fn emit_str(&mut self, key: Key, val: &str) -> slog::Result {
+                self.0.push(format!("{}=str:{}", key, val));
+                Ok(())
+            }
+            fn emit_arguments(
+                &mut self,
+                key: Key,
+                val: &fmt::Arguments,
+            ) -> slog::Result {
+                self.0.push(format!("{}=args:{}", key, val));
+                Ok(())
+            }
+        }
+
+        let values = |policy| {
+            let r = AsyncRecord::with_policy(
+                &record!(
+                    Level::Info,
+                    "",
+                    &format_args!("msg"),
+                    b!("n" => 5u64, "s" => "text", "secret" => 42u64)
+                ),
+                &o!().into(),
+                policy,
+            );
+            let mut ser = TypeSerializer::default();
+            r.as_record_values(|record, _| {
+                record.kv().serialize(record, &mut ser).unwrap();
+            });
+            ser.0.sort();
+            ser.0
+        };
+
+        assert_eq!(
+            values(ValuePolicy::KeepTyped),
+            vec!["n=u64:5", "s=str:text", "secret=u64:42"]
+        );
+        assert_eq!(
+            values(ValuePolicy::StringifyAll),
+            vec!["n=str:5", "s=str:text", "secret=str:42"]
+        );
+        assert_eq!(
+            values(ValuePolicy::Redact(&["secret"])),
+            vec!["n=u64:5", "s=str:text", "secret=str:[redacted]"]
+        );
+    }
+
+    #[test]
+    fn schema_version_is_added() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) = Async::new(mock_drain)
+            .schema_version(3)
+            .self_report(Duration::from_millis(10))
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        log_msg(&async_drain, "versioned").unwrap();
+
+        let mut entries = Vec::new();
+        while !entries.iter().any(|e: &String| e.contains("stats")) {
+            entries.push(
+                mock_drain_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            );
+        }
+        drop(async_drain);
+        assert_eq!(entries[0], "INFO versioned: [(\"schema_version\", \"3\")]");
+        let report = entries.last().unwrap();
+        assert!(report.contains("(\"schema_version\", \"3\")"), "{}", report);
+    }
+
+    #[test]
+    fn queue_time_is_annotated() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let async_drain = Async::new(mock_drain)
+            .annotate_queue_time("queue_us")
+            // Records queue up until the worker starts processing
+            .on_thread_start(|| thread::sleep(Duration::from_millis(50)))
+            .build();
+        async_drain
+            .core
+            .ctrl_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        log_msg(&async_drain, "backlogged").unwrap();
+        async_drain.flush().unwrap();
+        log_msg(&async_drain, "prompt").unwrap();
+        drop(async_drain);
+
+        let queue_times: Vec<u64> = mock_drain_rx
+            .iter()
+            .map(|entry| {
+                let value = entry
+                    .split("(\"queue_us\", \"")
+                    .nth(1)
+                    .expect("queue time field");
+                value[..value.find('"').unwrap()].parse().unwrap()
+            })
+            .collect();
+        assert_eq!(queue_times.len(), 2);
+        assert!(queue_times[0] >= 20_000, "{:?}", queue_times);
+        assert!(queue_times[1] < queue_times[0], "{:?}", queue_times);
+    }
+
+    #[test]
+    fn diagnostics_are_rate_limited() {
+        let gate = DiagnosticGate::new(Duration::from_secs(1));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(gate.check(at(0)), Some(0));
+        assert_eq!(gate.check(at(10)), None);
+        assert_eq!(gate.check(at(999)), None);
+        assert_eq!(gate.check(at(1000)), Some(2));
+        assert_eq!(gate.check(at(1500)), None);
+        assert_eq!(gate.check(at(5000)), Some(1));
+        assert_eq!(gate.check(at(5001)), None);
+    }
+
+    #[test]
+    fn priority_scope_records_are_not_dropped() {
+        let (gated_drain, entered_rx, release_tx) = GatedDrain::new();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let async_drain = Arc::new(
+            Async::new(TeeDrain(gated_drain, mock_drain))
+                .chan_size(1)
+                .overflow_strategy(OverflowStrategy::Drop)
+                .build(),
+        );
+        async_drain
+            .core
+            .ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        log_msg(&*async_drain, "parks the worker").unwrap();
+        entered_rx.recv().unwrap();
+        log_msg(&*async_drain, "queued").unwrap();
+        log_msg(&*async_drain, "dropped").unwrap();
+
+        let critical = {
+            let async_drain = async_drain.clone();
+            thread::spawn(move || {
+                let _scope = Async::priority_scope();
+                for i in 0..2 {
+                    log_msg(&*async_drain, &format!("critical {}", i)).unwrap();
+                }
+            })
+        };
+        drop(release_tx);
+        critical.join().unwrap();
+        Async::flush(&async_drain).unwrap();
+
+        assert_eq!(
+            mock_drain_rx.try_iter().collect::<Vec<_>>(),
+            vec![
+                "INFO parks the worker: []",
+                "INFO queued: []",
+                "INFO critical 0: []",
+                "INFO critical 1: []"
+            ]
+        );
+        assert_eq!(async_drain.drop_stats().total, 1);
+    }
+
+    #[test]
+    fn lifecycle_events_are_delivered() {
+        let (events_tx, events_rx) = crossbeam_channel::unbounded();
+        let async_drain = Async::new(slog::Discard)
+            .lifecycle_events(events_tx)
+            .build();
+        assert_eq!(
+            events_rx.recv_timeout(Duration::from_secs(1)),
+            Ok(LifecycleEvent::Started)
+        );
+        log_msg(&async_drain, "msg").unwrap();
+        drop(async_drain);
+        assert_eq!(
+            events_rx.iter().collect::<Vec<_>>(),
+            vec![LifecycleEvent::Stopped]
+        );
+    }
+
+    #[test]
+    fn static_messages_are_not_copied() {
+        let r = AsyncRecord::from(
+            &record!(Level::Info, "", &format_args!("static"), b!()),
+            &o!().into(),
+        );
+        assert!(matches!(r.msg, Cow::Borrowed("static")));
+        let n = 1;
+        let formatted = AsyncRecord::from(
+            &record!(Level::Info, "", &format_args!("formatted {}", n), b!()),
+            &o!().into(),
+        );
+        assert!(matches!(formatted.msg, Cow::Owned(_)));
+        assert!(formatted.size > r.size);
+
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        r.log_to(&mock_drain).unwrap();
+        formatted.log_to(&mock_drain).unwrap();
+        assert_eq!(
+            mock_drain_rx.try_iter().collect::<Vec<_>>(),
+            vec!["INFO static: []", "INFO formatted 1: []"]
+        );
+    }
+
+    #[test]
+    fn flush_tag_writes_batches_holding_the_tag() {
+        let (tx, rx) = mpsc::channel();
+        let core = AsyncCore::custom_batch(
+            MockBatchDrain(tx),
+            100,
+            Duration::from_secs(3600),
+        )
+        .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        macro_rules! log_tagged {
+            ($tag:expr, $msg:expr) => {
+                core.log(
+                    &record!(Level::Info, $tag, &format_args!($msg), b!()),
+                    &o!().into(),
+                )
+                .unwrap()
+            };
+        }
+
+        log_tagged!("a", "a1");
+        log_tagged!("b", "b1");
+        log_tagged!("a", "a2");
+        core.flush_tag("a").unwrap();
+        assert_eq!(rx.try_recv().unwrap(), vec!["a1", "b1", "a2"]);
+
+        log_tagged!("b", "b2");
+        core.flush_tag("a").unwrap();
+        assert!(rx.try_recv().is_err());
+        core.flush_tag("b").unwrap();
+        assert_eq!(rx.try_recv().unwrap(), vec!["b2"]);
+    }
+
+    #[test]
+    fn log_with_precomputed_logger_values() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) =
+            Async::new(mock_drain).build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let enabled =
+            async_drain.precompute_logger_values(&o!("pid" => 1).into());
+        let disabled = async_drain
+            .precompute_logger_values(&o!("pid" => 2, "id" => 3).into());
+        assert_eq!((enabled.pid, disabled.pid), (Some(1), Some(2)));
+        control.enable(1).unwrap();
+
+        for handle in &[enabled, disabled] {
+            async_drain
+                .log_with_handle(
+                    &record!(Level::Info, "", &format_args!("msg"), b!()),
+                    handle,
+                )
+                .unwrap();
+        }
+        drop(async_drain);
+
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec!["INFO msg: [(\"pid\", \"1\")]"]
+        );
+    }
+
+    #[test]
+    fn precomputed_logger_values_are_serialized_once() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) =
+            Async::new(mock_drain).build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let handle = async_drain.precompute_logger_values(
+            &o!(
+                "host" => "db1",
+                "computed" => slog::FnValue(move |_| {
+                    counted.fetch_add(1, Ordering::Relaxed)
+                }),
+            )
+            .into(),
+        );
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        for i in 0..3 {
+            async_drain
+                .log_with_handle(
+                    &record!(
+                        Level::Info,
+                        "",
+                        &format_args!("msg"),
+                        b!("i" => i)
+                    ),
+                    &handle,
+                )
+                .unwrap();
+        }
+        drop(async_drain);
+
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        let entries: Vec<_> = mock_drain_rx.iter().collect();
+        assert_eq!(entries.len(), 3);
+        for (i, entry) in entries.iter().enumerate() {
+            assert_eq!(
+                *entry,
+                format!(
+                    "INFO msg: [(\"computed\", \"0\"), (\"host\", \"db1\"), \
+                     (\"i\", \"{}\")]",
+                    i
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn logger_values_cache_reuses_values_of_logger_clones() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) = Async::new(mock_drain)
+            .logger_values_cache(4)
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let logger = slog::Logger::root(
+            async_drain.fuse(),
+            o!(
+                "host" => "db1",
+                "computed" => slog::FnValue(move |_| {
+                    counted.fetch_add(1, Ordering::Relaxed)
+                }),
+            ),
+        );
+
+        info!(logger, "first");
+        info!(logger.clone(), "second");
+        info!(logger, "third");
+        drop(logger);
+
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        let entries: Vec<_> = mock_drain_rx.iter().collect();
+        assert_eq!(
+            entries,
+            ["first", "second", "third"]
+                .iter()
+                .map(|msg| format!(
+                    "INFO {}: [(\"computed\", \"0\"), (\"host\", \"db1\")]",
+                    msg
+                ))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn logger_values_cache_evicts_least_recently_used() {
+        /// Logger values counting their serializations into `calls`
+        fn counted_values(calls: &Arc<AtomicUsize>) -> OwnedKVList {
+            let counted = calls.clone();
+            o!("n" => slog::FnValue(move |_| {
+                counted.fetch_add(1, Ordering::Relaxed)
+            }))
+            .into()
+        }
+
+        let (mock_drain, _mock_drain_rx) = MockDrain::new();
+        let core = AsyncCore::custom(mock_drain).logger_values_cache(2).build();
+        let calls: Vec<_> =
+            (0..3).map(|_| Arc::new(AtomicUsize::new(0))).collect();
+        let values: Vec<_> = calls.iter().map(counted_values).collect();
+        let log = |i: usize| {
+            core.log(
+                &record!(Level::Info, "", &format_args!("msg"), b!()),
+                &values[i],
+            )
+            .unwrap()
+        };
+        let counts = || -> Vec<usize> {
+            calls.iter().map(|c| c.load(Ordering::Relaxed)).collect()
+        };
+
+        log(0);
+        log(1);
+        log(0);
+        assert_eq!(counts(), [1, 1, 0]);
+        // Full: evicts 1, used before 0
+        log(2);
+        assert_eq!(counts(), [1, 1, 1]);
+        assert_eq!(
+            core.logger_values_cache
+                .as_ref()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .entries
+                .len(),
+            2
+        );
+        log(0);
+        log(2);
+        assert_eq!(counts(), [1, 1, 1]);
+        log(1);
+        assert_eq!(counts(), [1, 2, 1]);
+    }
+
+    #[test]
+    fn max_kv_pairs_truncates_records() {
+        /// Record key-values with a given number of fields
+        struct ManyKV(usize);
+
+        impl KV for ManyKV {
+            fn serialize(
+                &self,
+                _record: &Record,
+                serializer: &mut dyn Serializer,
+            ) -> slog::Result {
+                for i in 0..self.0 {
+                    serializer.emit_usize(static_key("field"), i)?;
+                }
+                Ok(())
+            }
+        }
+
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCore::custom(mock_drain).max_kv_pairs(3).build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        for fields in &[100_000, 2] {
+            core.log(
+                &Record::new(
+                    &RecordStatic {
+                        location: &slog::RecordLocation {
+                            file: "",
+                            line: 0,
+                            column: 0,
+                            function: "",
+                            module: "",
+                        },
+                        level: Level::Info,
+                        tag: "",
+                    },
+                    &format_args!("msg"),
+                    BorrowedKV(&ManyKV(*fields)),
+                ),
+                &o!().into(),
+            )
+            .unwrap();
+        }
+        drop(core);
+
+        let entries: Vec<_> = mock_drain_rx.iter().collect();
+        assert_eq!(entries.len(), 2);
+        for kv in &[
+            "(\"field\", \"0\")",
+            "(\"field\", \"2\")",
+            "(\"kv_truncated\", \"99997\")",
+        ] {
+            assert!(entries[0].contains(kv), "{}", entries[0]);
+        }
+        assert!(!entries[0].contains("(\"field\", \"3\")"));
+        assert!(!entries[1].contains("kv_truncated"));
+    }
+
+    #[test]
+    fn max_value_len_cuts_oversized_values() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCore::custom(mock_drain).max_value_len(4).build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        let blob = "QUJDRA==".repeat(1000);
+        core.log(
+            &record!(
+                Level::Info,
+                "",
+                &format_args!("msg"),
+                b!("blob" => blob, "id" => 12_345_678, "short" => "ab",
+                   "exact" => "abcd", "fmt" => %"éééé", "moved" => ?"long")
+            ),
+            &o!("host" => "long host").into(),
+        )
+        .unwrap();
+        drop(core);
+
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec![
+                "INFO msg: [(\"host\", \"long host\"), \
+                 (\"moved\", \"\\\"lon...[truncated]\"), \
+                 (\"fmt\", \"éé...[truncated]\"), \
+                 (\"exact\", \"abcd\"), (\"short\", \"ab\"), \
+                 (\"id\", \"12345678\"), \
+                 (\"blob\", \"QUJD...[truncated]\")]"
+            ]
+        );
+    }
+
+    #[test]
+    fn logging_after_guard_dropped() {
+        let (drain, guard) = Async::new(slog::Discard).build_with_guard();
+        drop(guard);
+        match drain.log(
+            &record!(Level::Info, "", &format_args!("late"), b!()),
+            &o!().into(),
+        ) {
+            Err(ref err) if err.is_worker_gone() => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let (fallback, fallback_rx) = MockDrain::new();
+        let (drain, guard) = Async::new(slog::Discard)
+            .worker_gone_fallback(fallback)
+            .build_with_guard();
+        drop(guard);
+        drain
+            .log(
+                &record!(
+                    Level::Info,
+                    "",
+                    &format_args!("late"),
+                    b!("k" => "v")
+                ),
+                &o!().into(),
+            )
+            .unwrap();
+        assert_eq!(
+            fallback_rx.try_recv().unwrap(),
+            "INFO late: [(\"k\", \"v\")]"
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn into_stream_yields_records() {
+        let (drain, control, records) = Async::into_stream();
+        control.log_level(Level::Info).unwrap();
+        for msg in &["first", "second"] {
+            drain
+                .log(
+                    &record!(
+                        Level::Info,
+                        "",
+                        &format_args!("{}", msg),
+                        b!("k" => "v")
+                    ),
+                    &o!().into(),
+                )
+                .unwrap();
+        }
+        drop(drain);
+
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let mut records = records.into_inner();
+        while let Some(r) = records.blocking_recv() {
+            r.log_to(&mock_drain).unwrap();
+        }
+        drop(mock_drain);
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec![
+                "INFO first: [(\"k\", \"v\")]",
+                "INFO second: [(\"k\", \"v\")]",
+            ]
+        );
+    }
+
+    #[test]
+    fn self_report_writes_stats_periodically() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCore::custom(mock_drain)
+            .self_report(Duration::from_millis(50))
+            .build();
+        thread::sleep(Duration::from_millis(275));
+        drop(core);
+
+        let reports: Vec<_> = mock_drain_rx
+            .iter()
+            .filter(|entry| entry.starts_with("INFO slog-async: stats"))
+            .collect();
+        assert!(
+            reports.len() >= 3 && reports.len() <= 5,
+            "{} reports",
+            reports.len()
+        );
+        for kv in &[
+            "(\"capacity\", \"128\")",
+            "(\"dropped\", \"0\")",
+            "(\"processed\", \"0\")",
+        ] {
+            assert!(reports[0].contains(kv), "{}", reports[0]);
+        }
+    }
+
+    #[test]
+    fn reserved_capacity_keeps_room_for_important_records() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let core = AsyncCore::custom(gated_drain)
+            .chan_size(4)
+            .reserved_capacity(2, Level::Warning)
+            .build();
+        core.ctrl_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        let log = |level| {
+            let mut r = async_record("msg");
+            r.level = level;
+            core.send(r)
+        };
+
+        log(Level::Debug).unwrap();
+        entered.recv().unwrap();
+        log(Level::Debug).unwrap();
+        log(Level::Debug).unwrap();
+        assert!(matches!(log(Level::Debug), Err(AsyncError::Full)));
+        log(Level::Error).unwrap();
+        log(Level::Warning).unwrap();
+        assert!(matches!(log(Level::Error), Err(AsyncError::Full)));
+        drop(release);
+    }
+
+    #[test]
+    fn production_drain_logs() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (drain, guard) = Async::production(mock_drain);
+        let logger = slog::Logger::root(drain.fuse(), o!());
+        debug!(logger, "started"; "k" => "v");
+        drop(guard);
+        assert_eq!(
+            mock_drain_rx.recv().unwrap(),
+            "DEBG started: [(\"k\", \"v\")]"
+        );
+    }
+
+    #[test]
+    fn transform_renames_keys() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(mock_drain)
+            .transform(|mut r| {
+                r.rename_key("user", "user_id");
+                r.push_kv("transformed", true);
+                Some(r)
+            })
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let logger = slog::Logger::root(async_drain.fuse(), o!());
+        info!(logger, "login"; "user" => 42, "ip" => "::1");
+        drop(logger);
+
+        assert_eq!(
+            mock_drain_rx.recv().unwrap(),
+            "INFO login: [(\"ip\", \"::1\"), (\"user_id\", \"42\"), \
+             (\"transformed\", \"true\")]"
+        );
+    }
+
+    #[test]
+    fn transform_filters_records() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(mock_drain)
+            .transform(|mut r| {
+                if r.msg().starts_with("health check") {
+                    return None;
+                }
+                if r.level() == Level::Warning {
+                    r.set_level(Level::Error);
+                }
+                Some(r)
+            })
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let logger = slog::Logger::root(async_drain.fuse(), o!());
+        info!(logger, "health check ok");
+        warn!(logger, "disk almost full");
+        drop(logger);
+
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec!["ERRO disk almost full: []"]
+        );
+    }
+
+    #[test]
+    fn fallback_is_counted_in_stats() {
+        /// Takes a while for each record
+        struct SlowDrain;
+
+        impl Drain for SlowDrain {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                _: &Record,
+                _: &OwnedKVList,
+            ) -> Result<(), slog::Never> {
+                thread::sleep(Duration::from_millis(5));
+                Ok(())
+            }
+        }
+
+        let (drain, guard) = Async::new(slog::Discard)
+            .worker_gone_fallback(SlowDrain)
+            .build_with_guard();
+        log_msg(&drain, "written by the worker").unwrap();
+        assert_eq!(drain.stats().fallback_count, 0);
+        assert_eq!(drain.stats().fallback_duration, Duration::from_secs(0));
+
+        // The worker thread terminates
+        drop(guard);
+        log_msg(&drain, "written synchronously").unwrap();
+        log_msg(&drain, "written synchronously").unwrap();
+        let stats = drain.stats();
+        assert!(!stats.worker_alive);
+        assert_eq!(stats.fallback_count, 2);
+        assert!(stats.fallback_duration >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn flush_reports_progress() {
+        /// Takes a while for each record
+        struct SlowDrain;
+
+        impl Drain for SlowDrain {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                _: &Record,
+                _: &OwnedKVList,
+            ) -> Result<(), slog::Never> {
+                thread::sleep(Duration::from_millis(25));
+                Ok(())
+            }
+        }
+
+        let (async_drain, control) =
+            Async::new(SlowDrain).chan_size(32).build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        for _ in 0..20 {
+            log_msg(&async_drain, "backlog").unwrap();
+        }
+
+        let remaining = RefCell::new(vec![]);
+        async_drain
+            .flush_with_progress(|n| remaining.borrow_mut().push(n))
+            .unwrap();
+        let remaining = remaining.into_inner();
+        assert!(remaining.len() >= 2, "{:?}", remaining);
+        assert!(
+            remaining.windows(2).all(|w| w[0] >= w[1]),
+            "{:?}",
+            remaining
+        );
+        assert!(remaining[0] > remaining[remaining.len() - 1]);
+    }
+
+    #[test]
+    fn pid_router_routes_by_pid() {
+        let (default_drain, default_rx) = MockDrain::new();
+        let (first_drain, first_rx) = MockDrain::new();
+        let (second_drain, second_rx) = MockDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(default_drain)
+            .pid_router(PidRouter::new().route(1, first_drain))
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        control.enable(1).unwrap();
+        control.enable(2).unwrap();
+        let root = slog::Logger::root(async_drain.fuse(), o!());
+        let first = root.new(o!(PID_KEY => 1));
+        let second = root.new(o!(PID_KEY => 2));
+
+        info!(first, "first");
+        info!(second, "second, default");
+        info!(root, "no pid");
+        control.route(2, second_drain).unwrap();
+        info!(second, "second, routed");
+        control.unroute(1).unwrap();
+        info!(first, "first, default");
+        drop((root, first, second, control));
+
+        assert_eq!(
+            first_rx.iter().collect::<Vec<_>>(),
+            vec!["INFO first: [(\"pid\", \"1\")]"]
+        );
+        assert_eq!(
+            second_rx.iter().collect::<Vec<_>>(),
+            vec!["INFO second, routed: [(\"pid\", \"2\")]"]
+        );
+        assert_eq!(
+            default_rx.iter().collect::<Vec<_>>(),
+            vec![
+                "INFO second, default: [(\"pid\", \"2\")]",
+                "INFO no pid: []",
+                "INFO first, default: [(\"pid\", \"1\")]",
+            ]
+        );
+    }
+
+    #[test]
+    fn block_above_blocks_at_watermark() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(gated_drain)
+            .chan_size(8)
+            .overflow_strategy(OverflowStrategy::BlockAbove(2))
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let async_drain = Arc::new(async_drain);
+
+        // Park the worker inside the drain, then fill up to the watermark.
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+
+        let (sent_tx, sent_rx) = mpsc::channel();
+        let blocked = {
+            let async_drain = async_drain.clone();
+            thread::spawn(move || {
+                log_msg(&async_drain, "blocked").unwrap();
+                sent_tx.send(()).unwrap();
+            })
+        };
+        assert!(sent_rx.recv_timeout(Duration::from_millis(50)).is_err());
+        release.send(()).unwrap();
+        sent_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        blocked.join().unwrap();
+        drop(release);
+        assert_eq!(async_drain.drop_stats().total, 0);
+    }
+
+    #[test]
+    fn block_above_capacity_drops_and_reports() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(gated_drain)
+            .chan_size(1)
+            .overflow_strategy(OverflowStrategy::BlockAbove(100))
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+        log_msg(&async_drain, "dropped").unwrap();
+        assert_eq!(async_drain.dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            async_drain.overflow_strategy.load(),
+            OverflowStrategy::BlockAbove(100)
+        );
+        drop(release);
+    }
+
+    #[test]
+    fn set_enabled_replaces_enabled_pids() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) =
+            AsyncBuilder::new(mock_drain).build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        control.enable(1).unwrap();
+        control.enable(2).unwrap();
+        control
+            .set_enabled([2, 3].iter().cloned().collect())
+            .unwrap();
+
+        let root = slog::Logger::root(async_drain.fuse(), o!());
+        for pid in 1..4 {
+            info!(root.new(o!(PID_KEY => pid)), "msg");
+        }
+        drop((root, control));
+
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec![
+                "INFO msg: [(\"pid\", \"2\")]",
+                "INFO msg: [(\"pid\", \"3\")]",
+            ]
+        );
+    }
+
+    #[test]
+    fn producer_thread_count_counts_logging_threads() {
+        let (async_drain, control) =
+            AsyncBuilder::new(slog::Discard).build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let async_drain = Arc::new(async_drain);
+        assert_eq!(async_drain.producer_thread_count(), 0);
+
+        // Keep all the producers alive at once, so none reuses another's slot
+        let barrier = Arc::new(sync::Barrier::new(3));
+        let producers: Vec<_> = (0..3)
+            .map(|_| {
+                let async_drain = async_drain.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    log_msg(&*async_drain, "msg").unwrap();
+                    barrier.wait();
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert_eq!(async_drain.producer_thread_count(), 3);
+    }
+
+    #[test]
+    fn level_filters_by_severity() {
+        let levels = [
+            Level::Critical,
+            Level::Error,
+            Level::Warning,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ];
+        // `as_usize` is 1 for `Critical` up to 6 for `Trace`
+        for &filter in &levels {
+            for &level in &levels {
+                assert_eq!(
+                    LevelFilter::AtLeast(filter).accepts(level),
+                    level.as_usize() <= filter.as_usize(),
+                    "{:?} at least {:?}",
+                    level,
+                    filter
+                );
+                assert_eq!(
+                    LevelFilter::AtMost(filter).accepts(level),
+                    level.as_usize() >= filter.as_usize(),
+                    "{:?} at most {:?}",
+                    level,
+                    filter
+                );
+            }
+        }
+        assert!(LevelFilter::AtLeast(Level::Info).accepts(Level::Error));
+        assert!(!LevelFilter::AtLeast(Level::Info).accepts(Level::Debug));
+        assert!(LevelFilter::AtMost(Level::Debug).accepts(Level::Trace));
+        assert!(!LevelFilter::AtMost(Level::Debug).accepts(Level::Info));
+    }
+
+    #[test]
+    fn level_at_most_emits_verbose_records() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) =
+            AsyncBuilder::new(mock_drain).build_with_channel();
+        control.level_at_most(Level::Debug).unwrap();
+
+        let root = slog::Logger::root(async_drain.fuse(), o!());
+        error!(root, "error");
+        info!(root, "info");
+        debug!(root, "debug");
+        // `trace!` is compiled out of debug builds by default
+        root.log(&record!(Level::Trace, "", &format_args!("trace"), b!()));
+        drop((root, control));
+
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec!["DEBG debug: []", "TRCE trace: []"]
+        );
+    }
+
+    #[test]
+    fn lifo_writes_queued_records_newest_first() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let async_drain = Async::new(mock_drain)
+            .lifo(true)
+            // Records queue up until the worker starts processing
+            .on_thread_start(|| thread::sleep(Duration::from_millis(50)))
+            .build();
+        async_drain
+            .core
+            .ctrl_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        for i in 0..5 {
+            log_msg(&async_drain, &i.to_string()).unwrap();
+        }
+        async_drain.flush().unwrap();
+        log_msg(&async_drain, "after flush").unwrap();
+        drop(async_drain);
+
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec![
+                "INFO 4: []",
+                "INFO 3: []",
+                "INFO 2: []",
+                "INFO 1: []",
+                "INFO 0: []",
+                "INFO after flush: []",
+            ]
+        );
+    }
+
+    #[test]
+    fn cloned_record_keeps_key_values() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let r = AsyncRecord::from(
+            &record!(
+                Level::Warning,
+                "",
+                &format_args!("msg"),
+                b!("n" => 5, "s" => "text")
             ),
-            &self.logger_values,
-        )
+            &o!("k" => "v").into(),
+        );
+        r.clone().log_to(&mock_drain).unwrap();
+        r.log_to(&mock_drain).unwrap();
+        drop(mock_drain);
+
+        let logged: Vec<_> = mock_drain_rx.iter().collect();
+        assert_eq!(logged.len(), 2);
+        assert_eq!(logged[0], logged[1]);
     }
-}
 
-enum AsyncMsg {
-    Record(AsyncRecord),
-    // Disables a PID.
-    DisablePID(usize),
-    // Enables a PID.
-    EnablePID(usize),
-    // Sets the emitted log level
-    LogLevel(slog::Level),
-    // Ends the task
-    Finish,
-}
+    #[test]
+    fn fork_sides_have_independent_backpressure() {
+        let (fast_drain, fast_rx) = MockDrain::new();
+        let (slow_drain, slow_rx) = MockDrain::new();
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (fast, fast_control) = AsyncBuilder::new(fast_drain)
+            .overflow_strategy(OverflowStrategy::Block)
+            .build_with_channel();
+        let (slow, slow_control) =
+            AsyncBuilder::new(TeeDrain(gated_drain, slow_drain))
+                .chan_size(1)
+                .overflow_strategy(OverflowStrategy::Drop)
+                .build_with_channel();
+        fast_control.log_level(Level::Trace).unwrap();
+        slow_control.log_level(Level::Trace).unwrap();
+        let fork = Async::fork(fast, slow);
+        while !fork.second().core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
 
-impl Drop for AsyncCore {
-    fn drop(&mut self) {
-        let _err: Result<(), Box<dyn std::error::Error>> = {
-            || {
-                if let Some(join) = self.join.lock()?.take() {
-                    let _ = self.get_sender()?.send(AsyncMsg::Finish);
-                    if join.thread().id() != thread::current().id() {
-                        // A custom Drain::log implementation could dynamically
-                        // swap out the logger which eventually invokes
-                        // AsyncCore::drop in the worker thread.
-                        // If we drop the AsyncCore inside the logger thread,
-                        // this join() either panic or dead-lock.
-                        // TODO: Figure out whether skipping join() instead of
-                        // panicking is desirable.
-                        join.join().map_err(|_| {
-                            io::Error::new(
-                                io::ErrorKind::BrokenPipe,
-                                "Logging thread worker join error",
-                            )
-                        })?;
+        // Park the slow side, then fill its channel
+        log_msg(&fork, "0").unwrap();
+        entered.recv().unwrap();
+        for i in 1..10 {
+            log_msg(&fork, &i.to_string()).unwrap();
+        }
+        fork.first().flush().unwrap();
+        assert_eq!(
+            fast_rx.try_iter().collect::<Vec<_>>(),
+            (0..10)
+                .map(|i| format!("INFO {}: []", i))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(fork.second().drop_stats().total, 8);
+
+        drop(release);
+        drop(fork);
+        assert_eq!(
+            slow_rx.iter().collect::<Vec<_>>(),
+            vec!["INFO 0: []", "INFO 1: []"]
+        );
+    }
+
+    #[test]
+    fn inter_record_delay_paces_bursts() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(mock_drain)
+            .inter_record_delay(Duration::from_millis(20))
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+
+        let start = Instant::now();
+        for i in 0..5 {
+            log_msg(&async_drain, &i.to_string()).unwrap();
+        }
+        async_drain.flush().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(80));
+        drop(async_drain);
+        assert_eq!(mock_drain_rx.iter().count(), 5);
+    }
+
+    #[test]
+    fn take_dropped_count_partitions_drops() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(gated_drain)
+            .chan_size(1)
+            .overflow_strategy(OverflowStrategy::DropAndReport)
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+        // The first drop gets reported, the report then waits for the parked
+        // worker and holds back further reports
+        for _ in 0..3 {
+            log_msg(&async_drain, "dropped").unwrap();
+        }
+        assert_eq!(async_drain.dropped_count(), 2);
+        assert_eq!(async_drain.dropped_count(), 2);
+        assert_eq!(async_drain.take_dropped_count(), 2);
+        assert_eq!(async_drain.dropped_count(), 0);
+        assert_eq!(async_drain.take_dropped_count(), 0);
+        log_msg(&async_drain, "dropped").unwrap();
+        assert_eq!(async_drain.take_dropped_count(), 1);
+        assert_eq!(async_drain.drop_stats().total, 4);
+        drop(release);
+    }
+
+    #[test]
+    fn shared_drain_serializes_writers() {
+        /// Drain that isn't thread-safe, counting records
+        struct CountingDrain(Cell<usize>);
+
+        impl Drain for CountingDrain {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                _: &Record,
+                _: &OwnedKVList,
+            ) -> Result<(), slog::Never> {
+                let count = self.0.get();
+                thread::yield_now();
+                self.0.set(count + 1);
+                Ok(())
+            }
+        }
+
+        let sink = Arc::new(Mutex::new(CountingDrain(Cell::new(0))));
+        let drains: Vec<_> = (0..2)
+            .map(|_| {
+                let (drain, control) =
+                    AsyncBuilder::new(shared_drain(sink.clone()))
+                        .overflow_strategy(OverflowStrategy::Block)
+                        .build_with_channel();
+                control.log_level(Level::Trace).unwrap();
+                drain
+            })
+            .collect();
+        for _ in 0..100 {
+            for drain in &drains {
+                log_msg(drain, "msg").unwrap();
+            }
+        }
+        drop(drains);
+        assert_eq!(sink.lock().unwrap().0.get(), 200);
+    }
+
+    #[test]
+    fn sample_rate_keeps_a_fraction_of_records() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(mock_drain)
+            .overflow_strategy(OverflowStrategy::Block)
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+
+        let root = slog::Logger::root(async_drain.fuse(), o!());
+        for _ in 0..10000 {
+            info!(root, "sampled"; SAMPLE_RATE_KEY => 0.1);
+        }
+        info!(root, "never"; SAMPLE_RATE_KEY => 0.0);
+        info!(root, "always"; SAMPLE_RATE_KEY => 1.0);
+        info!(root, "not a rate"; SAMPLE_RATE_KEY => "often");
+        drop((root, control));
+
+        let logged: Vec<_> = mock_drain_rx.iter().collect();
+        let sampled = logged.iter().filter(|r| r.contains("sampled")).count();
+        assert!(sampled > 800 && sampled < 1200, "{}", sampled);
+        assert!(!logged.iter().any(|r| r.contains("never")));
+        assert!(logged.iter().any(|r| r.contains("always")));
+        assert!(logged.iter().any(|r| r.contains("not a rate")));
+    }
+
+    #[test]
+    fn sampling_seed_makes_sampling_reproducible() {
+        let sample = |seed| {
+            let (mock_drain, mock_drain_rx) = MockDrain::new();
+            let (async_drain, control) = AsyncBuilder::new(mock_drain)
+                .overflow_strategy(OverflowStrategy::Block)
+                .sampling_seed(seed)
+                .build_with_channel();
+            control.log_level(Level::Trace).unwrap();
+            let root = slog::Logger::root(async_drain.fuse(), o!());
+            for i in 0..200 {
+                info!(root, "sampled"; "i" => i, SAMPLE_RATE_KEY => 0.5);
+            }
+            drop((root, control));
+            mock_drain_rx.iter().collect::<Vec<_>>()
+        };
+
+        let kept = sample(42);
+        assert!(kept.len() > 50 && kept.len() < 150, "{}", kept.len());
+        assert_eq!(sample(42), kept);
+        assert_ne!(sample(43), kept);
+        assert_eq!(sample(0), sample(0));
+    }
+
+    #[test]
+    fn control_sender_reaches_worker() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let async_drain = Async::new(mock_drain).build();
+        let control = async_drain.control_sender();
+        control
+            .send(ControlMsg::level_at_least(Level::Info))
+            .unwrap();
+        control.send(ControlMsg::disable_pid(1)).unwrap();
+
+        let root = slog::Logger::root(async_drain.fuse(), o!());
+        info!(root, "kept");
+        debug!(root, "filtered");
+        info!(root.new(o!(PID_KEY => 1)), "disabled");
+        drop(root);
+
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec!["INFO kept: []"]
+        );
+        assert!(control
+            .send(ControlMsg::enable_pid(1))
+            .unwrap_err()
+            .is_worker_gone());
+    }
+
+    #[test]
+    fn shared_sender_without_thread_local_senders() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(mock_drain)
+            .thread_local_senders(false)
+            .overflow_strategy(OverflowStrategy::Block)
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let async_drain = Arc::new(async_drain);
+
+        let producers: Vec<_> = (0..4)
+            .map(|_| {
+                let async_drain = async_drain.clone();
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        log_msg(&*async_drain, "msg").unwrap();
                     }
-                }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert_eq!(async_drain.producer_thread_count(), 0);
+        drop((async_drain, control));
+        assert_eq!(mock_drain_rx.iter().count(), 200);
+    }
+
+    #[test]
+    fn strict_order_reorders_arrivals() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCoreBuilder::new(mock_drain).strict_order(2).build();
+        core.ctrl_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        let send = |seq: u64| {
+            let mut r = async_record(&seq.to_string());
+            r.seq = Some(seq);
+            core.ref_sender.send(AsyncMsg::Record(r)).unwrap();
+        };
+        for &seq in &[2, 0, 1, 5, 4] {
+            send(seq);
+        }
+        // 3 went missing, 4 and 5 wait until the buffer overflows
+        core.ref_sender
+            .send(AsyncMsg::Record(async_record("unnumbered")))
+            .unwrap();
+        send(6);
+        send(3);
+        core.flush().unwrap();
+        drop(core);
+
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec![
+                "INFO 0: []",
+                "INFO 1: []",
+                "INFO 2: []",
+                "INFO unnumbered: []",
+                "INFO 4: []",
+                "INFO 5: []",
+                "INFO 6: []",
+                "INFO 3: []",
+            ]
+        );
+    }
+
+    #[test]
+    fn strict_order_releases_sync_checkpoints() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCoreBuilder::new(mock_drain)
+            .strict_order(16)
+            .sync_checkpoint_level(Level::Critical)
+            .build();
+        core.ctrl_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        let mut r = async_record("1");
+        r.seq = Some(1);
+        core.ref_sender.send(AsyncMsg::Record(r)).unwrap();
+        // Take the place of record 0, dropped on overflow
+        core.sequence.as_ref().unwrap().store(2, Ordering::Relaxed);
+
+        let (done_tx, done_rx) = mpsc::channel();
+        let core = Arc::new(core);
+        let logger = {
+            let core = core.clone();
+            thread::spawn(move || {
+                let res = core.log(
+                    &record!(
+                        Level::Critical,
+                        "",
+                        &format_args!("checkpoint"),
+                        b!()
+                    ),
+                    &o!().into(),
+                );
+                done_tx.send(res.is_ok()).unwrap();
+            })
+        };
+        done_rx
+            .recv_timeout(Duration::from_secs(5))
+            .map(|ok| assert!(ok))
+            .expect("checkpoint held back behind a dropped record");
+        logger.join().unwrap();
+        assert_eq!(
+            mock_drain_rx.try_iter().collect::<Vec<_>>(),
+            vec!["INFO 1: []", "CRIT checkpoint: []"]
+        );
+    }
+
+    #[test]
+    fn sync_checkpoint_waits_until_written() {
+        /// Counts records, taking a while for each
+        struct SlowDrain(Arc<AtomicUsize>);
+
+        impl Drain for SlowDrain {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                _: &Record,
+                _: &OwnedKVList,
+            ) -> Result<(), slog::Never> {
+                thread::sleep(Duration::from_millis(50));
+                self.0.fetch_add(1, Ordering::SeqCst);
                 Ok(())
             }
-        }();
+        }
+
+        let written = Arc::new(AtomicUsize::new(0));
+        let (async_drain, control) =
+            AsyncBuilder::new(SlowDrain(written.clone()))
+                .sync_checkpoint_level(Level::Critical)
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let root = slog::Logger::root(async_drain.fuse(), o!());
+
+        error!(root, "queued");
+        assert_eq!(written.load(Ordering::SeqCst), 0);
+        crit!(root, "checkpoint");
+        assert_eq!(written.load(Ordering::SeqCst), 2);
     }
-}
-// }}}
 
-/// Behavior used when the channel is full.
-///
-/// # Note
-///
-/// More variants may be added in the future, without considering it a breaking change.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
-pub enum OverflowStrategy {
-    /// The message gets dropped and a message with number of dropped is produced once there's
-    /// space.
-    ///
-    /// This is the default.
-    ///
-    /// Note that the message with number of dropped messages takes one slot in the channel as
-    /// well.
-    DropAndReport,
-    /// The message gets dropped silently.
-    Drop,
-    /// The caller is blocked until there's enough space.
-    Block,
-    #[doc(hidden)]
-    DoNotMatchAgainstThisAndReadTheDocs,
-}
+    #[test]
+    fn serialization_panics_are_caught() {
+        struct Boom;
 
-/// `Async` builder
-pub struct AsyncBuilder<D>
-where
-    D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
-{
-    core: AsyncCoreBuilder<D>,
-    // Increment a counter whenever a message is dropped due to not fitting inside the channel.
-    inc_dropped: bool,
-}
+        impl fmt::Display for Boom {
+            fn fmt(&self, _: &mut fmt::Formatter) -> fmt::Result {
+                panic!("boom")
+            }
+        }
 
-impl<D> AsyncBuilder<D>
-where
-    D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
-{
-    fn new(drain: D) -> AsyncBuilder<D> {
-        AsyncBuilder {
-            core: AsyncCoreBuilder::new(drain),
-            inc_dropped: true,
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) = AsyncBuilder::new(mock_drain)
+            .catch_serialization_panics(true)
+            .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+
+        let root = slog::Logger::root(async_drain.fuse(), o!("k" => "v"));
+        warn!(root, "{}", Boom);
+        info!(root, "fine");
+        drop(root);
+
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec![
+                "WARN log serialization panicked: [(\"k\", \"v\")]",
+                "INFO fine: [(\"k\", \"v\")]",
+            ]
+        );
+    }
+
+    #[test]
+    fn stats_render_as_prometheus() {
+        let stats = AsyncStatsSnapshot {
+            depth: 3,
+            capacity: 4,
+            processed: 10,
+            dropped: 2,
+            load_factor: 0.75,
+            worker_alive: true,
+            fallback_count: 0,
+            fallback_duration: Duration::from_millis(1500),
+        };
+        let out = stats.to_prometheus("app_log");
+
+        assert!(out.starts_with(
+            "# HELP app_log_queue_depth Messages waiting in the channel\n\
+             # TYPE app_log_queue_depth gauge\n\
+             app_log_queue_depth 3\n"
+        ));
+        let samples: Vec<_> =
+            out.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(
+            samples,
+            vec![
+                "app_log_queue_depth 3",
+                "app_log_queue_capacity 4",
+                "app_log_queue_utilization 0.75",
+                "app_log_processed_total 10",
+                "app_log_dropped_unreported 2",
+                "app_log_worker_alive 1",
+                "app_log_fallback_total 0",
+                "app_log_fallback_seconds_total 1.5",
+            ]
+        );
+        assert!(out.contains("# TYPE app_log_processed_total counter\n"));
+        assert!(out.ends_with('\n'));
+    }
+
+    #[test]
+    fn overflow_handler_decides_per_record() {
+        /// Test-helper drain usable from several threads
+        struct SyncMockDrain(Mutex<MockDrain>);
+
+        impl Drain for SyncMockDrain {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                record: &Record,
+                logger_values: &OwnedKVList,
+            ) -> Result<(), slog::Never> {
+                self.0.lock().unwrap().log(record, logger_values)
+            }
+        }
+
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (redirect_drain, redirect_rx) = MockDrain::new();
+        let redirect_drain =
+            Arc::new(SyncMockDrain(Mutex::new(redirect_drain)));
+        let (async_drain, control) =
+            AsyncBuilder::new(TeeDrain(gated_drain, mock_drain))
+                .chan_size(1)
+                .overflow_strategy(OverflowStrategy::Drop)
+                .overflow_handler(move |r| match r.level() {
+                    Level::Critical => OverflowDecision::Block,
+                    Level::Error => {
+                        OverflowDecision::Redirect(redirect_drain.clone())
+                    }
+                    _ => OverflowDecision::Drop,
+                })
+                .measure_send_latency(true)
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let async_drain = Arc::new(async_drain);
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+        let log = |level: Level, msg: &str| {
+            let mut r = async_record(msg);
+            r.level = level;
+            async_drain.send_serialized(r).unwrap();
+        };
+
+        // Park the worker, then fill the channel
+        log(Level::Info, "parked");
+        entered.recv().unwrap();
+        log(Level::Info, "queued");
+        log(Level::Info, "dropped");
+        log(Level::Error, "redirected");
+        assert_eq!(redirect_rx.try_recv().unwrap(), "ERRO redirected: []");
+
+        let (sent_tx, sent_rx) = mpsc::channel();
+        let blocked = {
+            let async_drain = async_drain.clone();
+            thread::spawn(move || {
+                let mut r = async_record("blocked");
+                r.level = Level::Critical;
+                async_drain.send_serialized(r).unwrap();
+                sent_tx.send(()).unwrap();
+            })
+        };
+        assert!(sent_rx.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(release);
+        sent_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        blocked.join().unwrap();
+        // The blocked send is measured as well
+        let latency = async_drain.send_latency_percentiles().unwrap();
+        assert!(latency.max >= Duration::from_millis(50), "{:?}", latency);
+        drop((async_drain, control));
+
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec!["INFO parked: []", "INFO queued: []", "CRIT blocked: []"]
+        );
+    }
+
+    #[test]
+    fn overflow_with_takes_records_finding_channel_full() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (spill_tx, spill_rx) = mpsc::channel();
+        let spill_tx = Mutex::new(spill_tx);
+        let (async_drain, control) =
+            AsyncBuilder::new(TeeDrain(gated_drain, mock_drain))
+                .chan_size(1)
+                .overflow_with(move |r| {
+                    spill_tx.lock().unwrap().send(r.msg().to_string()).unwrap()
+                })
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+
+        log_msg(&async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&async_drain, "queued").unwrap();
+        log_msg(&async_drain, "spilled 1").unwrap();
+        log_msg(&async_drain, "spilled 2").unwrap();
+        assert_eq!(async_drain.dropped_count(), 0);
+
+        drop(release);
+        drop((async_drain, control));
+        assert_eq!(
+            spill_rx.iter().collect::<Vec<_>>(),
+            vec!["spilled 1", "spilled 2"]
+        );
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec!["INFO parked: []", "INFO queued: []"]
+        );
+    }
+
+    #[test]
+    fn sync_escape_writes_under_sustained_overflow() {
+        let (gated_drain, entered, release) = GatedDrain::new();
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, control) =
+            AsyncBuilder::new(TeeDrain(gated_drain, mock_drain))
+                .chan_size(1)
+                .overflow_strategy(OverflowStrategy::Drop)
+                .sync_escape_after(Duration::from_millis(50))
+                .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let async_drain = Arc::new(async_drain);
+        while !async_drain.core.ref_sender.is_empty() {
+            thread::yield_now();
+        }
+
+        // Park the worker, then fill the channel
+        log_msg(&*async_drain, "parked").unwrap();
+        entered.recv().unwrap();
+        log_msg(&*async_drain, "queued").unwrap();
+        log_msg(&*async_drain, "dropped").unwrap();
+        thread::sleep(Duration::from_millis(60));
+
+        let (sent_tx, sent_rx) = mpsc::channel();
+        let escaping = {
+            let async_drain = async_drain.clone();
+            thread::spawn(move || {
+                log_msg(&*async_drain, "escaped").unwrap();
+                sent_tx.send(()).unwrap();
+            })
+        };
+        assert!(sent_rx.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(release);
+        sent_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        escaping.join().unwrap();
+        assert_eq!(
+            mock_drain_rx.try_iter().collect::<Vec<_>>(),
+            vec!["INFO parked: []", "INFO queued: []", "INFO escaped: []"]
+        );
+    }
+
+    #[test]
+    fn manual_worker_overflows_before_run() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (async_drain, worker) = AsyncBuilder::new(mock_drain)
+            .chan_size(2)
+            .overflow_strategy(OverflowStrategy::Drop)
+            .build_manual();
+        async_drain
+            .core
+            .ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        // Used to block on the full channel, waiting for a worker nobody
+        // runs yet
+        let (done_tx, done_rx) = mpsc::channel();
+        let async_drain = Arc::new(async_drain);
+        let producer = {
+            let async_drain = async_drain.clone();
+            thread::spawn(move || {
+                for i in 0..4 {
+                    log_msg(&*async_drain, &format!("early {}", i)).unwrap();
+                }
+                done_tx.send(()).unwrap();
+            })
+        };
+        done_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("logging before run() deadlocked");
+        producer.join().unwrap();
+        assert_eq!(async_drain.drop_stats().total, 3);
+
+        let runner = thread::spawn(move || worker.run());
+        async_drain.flush().unwrap();
+        let entries: Vec<_> = mock_drain_rx.try_iter().collect();
+        assert_eq!(entries, vec!["INFO early 0: []"]);
+        drop(async_drain);
+        runner.join().unwrap();
+    }
+
+    #[test]
+    fn manual_worker_runs_on_caller_thread() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (core, worker) = AsyncCore::custom(mock_drain)
+            .on_thread_start(move || {
+                let name = thread::current().name().map(String::from);
+                started_tx.send(name).unwrap();
+            })
+            .build_manual();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        let core = Arc::new(core);
+
+        let producers: Vec<_> = (0..2)
+            .map(|i| {
+                let core = core.clone();
+                thread::spawn(move || {
+                    log_msg(&*core, &format!("producer {}", i)).unwrap();
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
         }
+        // Nothing runs the worker loop yet
+        assert!(started_rx.try_recv().is_err());
+        assert!(mock_drain_rx.try_recv().is_err());
+
+        let runner = thread::Builder::new()
+            .name("own-logger".into())
+            .spawn(move || worker.run())
+            .unwrap();
+        assert_eq!(started_rx.recv().unwrap().as_deref(), Some("own-logger"));
+        AsyncCore::flush(&core).unwrap();
+        let mut entries: Vec<_> = mock_drain_rx.try_iter().collect();
+        entries.sort();
+        assert_eq!(entries, vec!["INFO producer 0: []", "INFO producer 1: []"]);
+
+        drop(core);
+        runner.join().unwrap();
     }
 
-    /// Set channel size used to send logging records to worker thread. When
-    /// buffer is full `AsyncCore` will start returning `AsyncError::Full`.
-    pub fn chan_size(self, s: usize) -> Self {
-        AsyncBuilder {
-            core: self.core.chan_size(s),
-            ..self
+    #[test]
+    fn send_latency_is_measured() {
+        let disabled = AsyncCore::custom(slog::Discard).build();
+        assert_eq!(disabled.send_latency_percentiles(), None);
+
+        let core = Arc::new(
+            AsyncCore::custom(slog::Discard)
+                .blocking(true)
+                .measure_send_latency(true)
+                .build(),
+        );
+        let producers: Vec<_> = (0..4)
+            .map(|_| {
+                let core = core.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        core.send(async_record("msg")).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
         }
+
+        let latency = core.send_latency_percentiles().unwrap();
+        assert_eq!(latency.samples, 400);
+        assert!(latency.p50 > Duration::from_nanos(0), "{:?}", latency);
+        assert!(latency.p50 <= latency.p90, "{:?}", latency);
+        assert!(latency.p90 <= latency.p99, "{:?}", latency);
+        assert!(latency.p99 <= latency.max, "{:?}", latency);
     }
 
-    /// Sets what will happen if the channel is full.
-    pub fn overflow_strategy(
-        self,
-        overflow_strategy: OverflowStrategy,
-    ) -> Self {
-        let (block, inc) = match overflow_strategy {
-            OverflowStrategy::Block => (true, false),
-            OverflowStrategy::Drop => (false, false),
-            OverflowStrategy::DropAndReport => (false, true),
-            OverflowStrategy::DoNotMatchAgainstThisAndReadTheDocs => {
-                panic!("Invalid variant")
-            }
-        };
-        AsyncBuilder {
-            core: self.core.blocking(block),
-            inc_dropped: inc,
+    #[test]
+    fn latency_histogram_percentiles() {
+        let histogram = LatencyHistogram::default();
+        for nanos in 1..=100 {
+            histogram.record(Duration::from_nanos(nanos * 10));
         }
+        assert_eq!(
+            histogram.percentiles(),
+            SendLatencyPercentiles {
+                samples: 100,
+                p50: Duration::from_nanos(512),
+                p90: Duration::from_nanos(1024),
+                p99: Duration::from_nanos(1024),
+                max: Duration::from_nanos(1024),
+            }
+        );
     }
 
-    /// Configure a name to be used for the background thread.
-    ///
-    /// The name must not contain '\0'.
-    ///
-    /// # Panics
-    ///
-    /// If a name with '\0' is passed.
-    pub fn thread_name(self, name: String) -> Self {
-        AsyncBuilder {
-            core: self.core.thread_name(name),
-            ..self
+    #[test]
+    fn checkpoint_writes_recent_records() {
+        let (drain, _rx) = MockDrain::new();
+        let (async_drain, control) =
+            Async::new(drain).keep_recent(2).build_with_channel();
+        let async_drain = Arc::new(async_drain);
+        control.log_level(Level::Trace).unwrap();
+        let log =
+            slog::Logger::root(async_drain.clone().fuse(), o!("host" => "db"));
+        for i in 1..4 {
+            info!(log, "saved"; "i" => i);
         }
+
+        let mut out = Vec::new();
+        assert_eq!(async_drain.checkpoint(&mut out).unwrap(), 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "INFO saved host=db i=2\nINFO saved host=db i=3\n"
+        );
+
+        // Nothing is kept unless asked for
+        let (drain, _rx) = MockDrain::new();
+        let (async_drain, control) = Async::new(drain).build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        log_msg(&async_drain, "not kept").unwrap();
+        let mut out = Vec::new();
+        assert_eq!(async_drain.checkpoint(&mut out).unwrap(), 0);
+        assert!(out.is_empty());
     }
 
-    /// Complete building `Async`
-    pub fn build(self) -> Async {
-        Async {
-            core: self.core.build_no_guard(),
-            dropped: AtomicUsize::new(0),
-            inc_dropped: self.inc_dropped,
+    #[test]
+    fn hash_chain_detects_removed_record() {
+        /// Verifies the chain, leaving out the second record as if removed
+        struct VerifyingDrain {
+            chain: Mutex<HashChain>,
+            seen: AtomicUsize,
+            results: mpsc::Sender<bool>,
         }
-    }
 
-    /// Complete building `Async`
-    pub fn build_no_guard(self) -> Async {
-        Async {
-            core: self.core.build_no_guard(),
-            dropped: AtomicUsize::new(0),
-            inc_dropped: self.inc_dropped,
+        impl Drain for VerifyingDrain {
+            type Ok = ();
+            type Err = slog::Never;
+
+            fn log(
+                &self,
+                record: &Record,
+                values: &OwnedKVList,
+            ) -> Result<(), slog::Never> {
+                if self.seen.fetch_add(1, Ordering::SeqCst) != 1 {
+                    let valid =
+                        self.chain.lock().unwrap().verify(record, values);
+                    self.results.send(valid).unwrap();
+                }
+                Ok(())
+            }
+        }
+
+        let (results_tx, results_rx) = mpsc::channel();
+        let drain = VerifyingDrain {
+            chain: Mutex::new(HashChain::new("chain")),
+            seen: AtomicUsize::new(0),
+            results: results_tx,
+        };
+        let (async_drain, control) =
+            Async::new(drain).hash_chain("chain").build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let log = slog::Logger::root(async_drain.fuse(), o!("host" => "db"));
+        for i in 0..5 {
+            info!(log, "audit"; "i" => i);
         }
+        drop(log);
+
+        assert_eq!(
+            results_rx.iter().collect::<Vec<_>>(),
+            vec![true, false, true, true]
+        );
+
+        // A modified record breaks the chain
+        let values = o!().into();
+        let hash = HashChain::new("chain").link(
+            &record!(Level::Info, "", &format_args!("one"), b!()),
+            &values,
+        );
+        assert!(HashChain::new("chain").verify(
+            &record!(
+                Level::Info,
+                "",
+                &format_args!("one"),
+                b!("chain" => hash.clone())
+            ),
+            &values
+        ));
+        assert!(!HashChain::new("chain").verify(
+            &record!(
+                Level::Info,
+                "",
+                &format_args!("two"),
+                b!("chain" => hash)
+            ),
+            &values
+        ));
     }
 
-    /// Complete building `Async` with PID channel
-    pub fn build_with_channel(self) -> (Async, PIDLogControl) {
-        let async_struct = Async {
-            core: self.core.build_no_guard(),
-            dropped: AtomicUsize::new(0),
-            inc_dropped: self.inc_dropped,
+    #[test]
+    fn level_control_changes_verbosity_immediately() {
+        let max_level = Arc::new(AtomicUsize::new(Level::Info.as_usize()));
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let async_drain = Async::new(mock_drain)
+            .level_control(max_level.clone())
+            .build();
+        let log = |level, msg| {
+            let mut r = async_record(msg);
+            r.level = level;
+            async_drain.core.send(r).unwrap();
+            async_drain.flush().unwrap();
         };
-        let log_control =
-            PIDLogControl::new(async_struct.core.ref_sender.clone());
-        (async_struct, log_control)
+
+        log(Level::Info, "info");
+        log(Level::Debug, "hidden");
+        max_level.store(Level::Debug.as_usize(), Ordering::Relaxed);
+        log(Level::Debug, "debug");
+        log(Level::Trace, "hidden");
+        max_level.store(0, Ordering::Relaxed);
+        log(Level::Critical, "hidden");
+        drop(async_drain);
+
+        assert_eq!(
+            mock_drain_rx.iter().collect::<Vec<_>>(),
+            vec!["INFO info: []", "DEBG debug: []"]
+        );
     }
 
-    /// Complete building `Async` with `AsyncGuard`
-    ///
-    /// See `AsyncGuard` for more information.
-    pub fn build_with_guard(self) -> (Async, AsyncGuard) {
-        let (core, guard) = self.core.build_with_guard();
-        (
-            Async {
-                core,
-                dropped: AtomicUsize::new(0),
-                inc_dropped: self.inc_dropped,
-            },
-            guard,
-        )
+    /// Test-helper chunked drain sending every encoded chunk
+    struct MockChunkedDrain(mpsc::Sender<Vec<u8>>);
+
+    impl ChunkedDrain for MockChunkedDrain {
+        fn serialize(&self, record: &AsyncRecord) -> Vec<u8> {
+            record.msg.as_bytes().to_vec()
+        }
+
+        fn log_chunk(&self, chunk: &OutputChunk) {
+            self.0.send(chunk.encode()).unwrap();
+        }
     }
-}
 
-/// Async drain
-///
-/// `Async` will send all the logging records to a wrapped drain running in
-/// another thread.
-///
-/// `Async` never returns `AsyncError::Full`.
-///
-/// `Record`s are passed to the worker thread through a channel with a bounded
-/// size (see `AsyncBuilder::chan_size`). On channel overflow `Async` will
-/// start dropping `Record`s and log a message informing about it after
-/// sending more `Record`s is possible again. The exact details of handling
-/// overflow is implementation defined, might change and should not be relied
-/// on, other than message won't be dropped as long as channel does not
-/// overflow.
-///
-/// Any messages reported by `Async` will contain `slog-async` logging `Record`
-/// tag to allow easy custom handling.
-///
-/// Note: On drop `Async` waits for it's worker-thread to finish (after handling
-/// all previous `Record`s sent to it). If you can't tolerate the delay, make
-/// sure you drop it eg. in another thread.
-pub struct Async {
-    core: AsyncCore,
-    dropped: AtomicUsize,
-    // Increment the dropped counter if dropped?
-    inc_dropped: bool,
-}
+    #[test]
+    fn chunked_records_are_split_and_reassembled() {
+        let (tx, rx) = mpsc::channel();
+        let core = AsyncCore::custom_chunked(MockChunkedDrain(tx))
+            .max_output_chunk(OutputChunk::HEADER_LEN + 4)
+            .build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
 
-impl Async {
-    /// New `AsyncCore` with default parameters
-    pub fn default<
-        D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static,
-    >(
-        drain: D,
-    ) -> Self {
-        AsyncBuilder::new(drain).build()
+        let msgs = ["0123456789", "ab", "", "abcdefgh"];
+        for msg in &msgs {
+            log_msg(&core, msg).unwrap();
+        }
+        drop(core);
+
+        let frames: Vec<Vec<u8>> = rx.iter().collect();
+        assert_eq!(frames.len(), 3 + 1 + 1 + 2);
+        let mut records: Vec<(u64, Vec<u8>)> = vec![];
+        for frame in &frames {
+            assert!(frame.len() <= OutputChunk::HEADER_LEN + 4);
+            let chunk = OutputChunk::decode(frame).unwrap();
+            if chunk.index == 0 {
+                records.push((chunk.record_id, vec![]));
+            }
+            let record = records.last_mut().unwrap();
+            assert_eq!(record.0, chunk.record_id);
+            record.1.extend_from_slice(chunk.data);
+            if chunk.index + 1 == chunk.count {
+                // Every chunk arrived, in order
+                assert_eq!(
+                    chunk.count as usize,
+                    frames
+                        .iter()
+                        .filter(|f| OutputChunk::decode(f).unwrap().record_id
+                            == chunk.record_id)
+                        .count()
+                );
+            }
+        }
+        let ids: Vec<u64> = records.iter().map(|r| r.0).collect();
+        assert_eq!(ids, vec![0, 1, 2, 3]);
+        let reassembled: Vec<&[u8]> =
+            records.iter().map(|r| r.1.as_slice()).collect();
+        let expected: Vec<&[u8]> = msgs.iter().map(|m| m.as_bytes()).collect();
+        assert_eq!(reassembled, expected);
+        assert_eq!(OutputChunk::decode(&[0; 15]), None);
     }
 
-    /// Build `Async` drain with custom parameters
-    ///
-    /// The wrapped drain must handle all results (`Drain<Ok=(),Error=Never>`)
-    /// since there's no way to return it back. See `slog::DrainExt::fuse()` and
-    /// `slog::DrainExt::ignore_res()` for typical error handling strategies.
-    pub fn new<D: slog::Drain<Err = slog::Never, Ok = ()> + Send + 'static>(
-        drain: D,
-    ) -> AsyncBuilder<D> {
-        AsyncBuilder::new(drain)
+    #[test]
+    fn pool_threads_share_bound_senders() {
+        let (mock_drain, mock_drain_rx) = MockDrain::new();
+        let core = AsyncCore::custom(mock_drain).pool_senders(2).build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        // Threads come and go, reusing the indices of the pool
+        for i in 0..6 {
+            thread::scope(|s| {
+                s.spawn(|| {
+                    core.bind_sender(i);
+                    let sender = core.get_sender().unwrap();
+                    assert!(std::ptr::eq(sender, &core.pool_senders[i % 2]));
+                    log_msg(&core, &format!("pooled {}", i)).unwrap();
+                });
+            });
+        }
+        // Unbound threads still cache a sender of their own
+        thread::scope(|s| {
+            s.spawn(|| log_msg(&core, "unbound").unwrap());
+        });
+        assert_eq!(core.producer_thread_count(), 1);
+
+        drop(core);
+        let written: Vec<String> = mock_drain_rx.iter().collect();
+        let mut expected: Vec<String> =
+            (0..6).map(|i| format!("INFO pooled {}: []", i)).collect();
+        expected.push("INFO unbound: []".to_string());
+        assert_eq!(written, expected);
     }
 
-    fn push_dropped(&self, logger_values: &OwnedKVList) -> AsyncResult<()> {
-        let dropped = self.dropped.swap(0, Ordering::Relaxed);
-        if dropped > 0 {
-            match self.core.log(
-                &record!(
-                    slog::Level::Error,
-                    "slog-async",
-                    &format_args!(
-                        "slog-async: logger dropped messages \
-                         due to channel \
-                         overflow"
-                    ),
-                    b!("count" => dropped)
-                ),
-                logger_values,
-            ) {
-                Ok(()) => {}
-                Err(AsyncError::Full) => {
-                    self.dropped.fetch_add(dropped + 1, Ordering::Relaxed);
-                    return Ok(());
-                }
-                Err(e) => return Err(e),
+    /// Test-helper drain panicking on its first write
+    struct FailFirstDrain(AtomicBool);
+
+    impl Drain for FailFirstDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, _: &Record, _: &OwnedKVList) -> Result<(), slog::Never> {
+            if !self.0.swap(true, Ordering::Relaxed) {
+                panic!("cannot open log file");
             }
+            Ok(())
         }
-        Ok(())
     }
-}
 
-impl Drain for Async {
-    type Ok = ();
-    type Err = AsyncError;
+    #[test]
+    fn overlong_thread_names_are_cut_or_rejected() {
+        let (tx, rx) = mpsc::channel();
+        let core = AsyncCore::custom(slog::Discard)
+            .thread_name("slog-async-worker-0".into())
+            .on_thread_start(move || {
+                let name = thread::current().name().map(String::from);
+                tx.send(name).unwrap();
+            })
+            .build();
+        assert_eq!(rx.recv().unwrap(), Some("slog-async-work".to_string()));
+        drop(core);
 
-    // TODO: Review `Ordering::Relaxed`
-    fn log(
-        &self,
-        record: &Record,
-        logger_values: &OwnedKVList,
-    ) -> AsyncResult<()> {
-        self.push_dropped(logger_values)?;
+        // Cut at a character boundary
+        assert_eq!(truncated_len("slog-async-\u{e9}\u{e9}\u{e9}"), 15);
+        assert_eq!(truncated_len("slog-async-w\u{e9}\u{e9}"), 14);
+        assert_eq!(truncated_len("short"), 5);
 
-        match self.core.log(record, logger_values) {
-            Ok(()) => {}
-            Err(AsyncError::Full) if self.inc_dropped => {
-                self.dropped.fetch_add(1, Ordering::Relaxed);
+        let res = AsyncCore::custom(slog::Discard)
+            .thread_name("slog-async-worker-0".into())
+            .try_build();
+        match res {
+            Err(AsyncError::Fatal(err)) => {
+                assert!(err.to_string().contains("longer than 15 bytes"))
             }
-            Err(AsyncError::Full) => {}
-            Err(e) => return Err(e),
+            _ => panic!("expected the name to be rejected"),
         }
-
-        Ok(())
+        assert!(AsyncCore::custom(slog::Discard)
+            .thread_name("slog-async".into())
+            .try_build()
+            .is_ok());
     }
-}
 
-impl Drop for Async {
-    fn drop(&mut self) {
-        let _ = self.push_dropped(&o!().into());
+    #[test]
+    #[should_panic(expected = "Empty thread name passed")]
+    fn empty_thread_name_is_rejected() {
+        let _ = AsyncCore::custom(slog::Discard).thread_name(String::new());
     }
-}
 
-// }}}
+    #[test]
+    fn probe_on_build_fails_the_build() {
+        let res = AsyncBuilder::new(FailFirstDrain(AtomicBool::new(false)))
+            .probe_on_build(true)
+            .try_build();
+        match res {
+            Err(AsyncError::Fatal(err)) => {
+                assert!(err.to_string().contains("cannot open log file"))
+            }
+            _ => panic!("expected the probe to fail"),
+        }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::sync::mpsc;
+        // Without probing, the failure only shows up in the worker thread
+        assert!(AsyncBuilder::new(FailFirstDrain(AtomicBool::new(false)))
+            .try_build()
+            .is_ok());
+    }
 
     #[test]
-    fn integration_test() {
+    fn probe_on_build_writes_probe_record() {
         let (mock_drain, mock_drain_rx) = MockDrain::new();
-        let async_drain = AsyncBuilder::new(mock_drain).build();
-        let slog =
-            slog::Logger::root(async_drain.fuse(), o!("field1" => "value1"));
-
-        info!(slog, "Message 1"; "field2" => "value2");
-        warn!(slog, "Message 2"; "field3" => "value3");
-        assert_eq!(
-            mock_drain_rx.recv().unwrap(),
-            r#"INFO Message 1: [("field1", "value1"), ("field2", "value2")]"#
-        );
+        let core = AsyncCore::custom(mock_drain)
+            .probe_on_build(true)
+            .try_build()
+            .unwrap();
         assert_eq!(
-            mock_drain_rx.recv().unwrap(),
-            r#"WARN Message 2: [("field1", "value1"), ("field3", "value3")]"#
+            mock_drain_rx.try_recv().unwrap(),
+            "TRCE slog-async: probe: []"
         );
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+        log_msg(&core, "logged").unwrap();
+        drop(core);
+        assert_eq!(mock_drain_rx.recv().unwrap(), "INFO logged: []");
+    }
+
+    #[test]
+    fn flush_timeout_gives_up_on_stalled_drain() {
+        let (drain, entered, release) = GatedDrain::new();
+        let core = AsyncCore::custom(drain).build();
+        core.ref_sender
+            .send(AsyncMsg::LogLevel(Level::Trace.into()))
+            .unwrap();
+
+        log_msg(&core, "stalled").unwrap();
+        entered.recv().unwrap();
+        assert!(!core.flush_timeout(Duration::from_millis(50)).unwrap());
+
+        drop(release);
+        assert!(core.flush_timeout(Duration::from_secs(10)).unwrap());
+    }
+
+    /// Serializes a message without any key-values into an `AsyncRecord`
+    fn async_record(msg: &str) -> AsyncRecord {
+        AsyncRecord::from(
+            &record!(Level::Info, "", &format_args!("{}", msg), b!()),
+            &o!().into(),
+        )
+    }
+
+    /// Logs a message without any key-values directly to `drain`
+    fn log_msg<D: Drain>(drain: &D, msg: &str) -> Result<D::Ok, D::Err> {
+        drain.log(
+            &record!(Level::Info, "", &format_args!("{}", msg), b!()),
+            &o!().into(),
+        )
+    }
+
+    /// Test-helper drain that parks the worker thread until released
+    struct GatedDrain {
+        entered: mpsc::Sender<()>,
+        release: mpsc::Receiver<()>,
+    }
+
+    impl GatedDrain {
+        fn new() -> (Self, mpsc::Receiver<()>, mpsc::Sender<()>) {
+            let (entered_tx, entered_rx) = mpsc::channel();
+            let (release_tx, release_rx) = mpsc::channel();
+            let drain = GatedDrain {
+                entered: entered_tx,
+                release: release_rx,
+            };
+            (drain, entered_rx, release_tx)
+        }
+    }
+
+    impl slog::Drain for GatedDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(
+            &self,
+            _record: &Record,
+            _logger_kv: &OwnedKVList,
+        ) -> Result<Self::Ok, Self::Err> {
+            let _ = self.entered.send(());
+            // Returns immediately once the test dropped its `Sender`
+            let _ = self.release.recv();
+            Ok(())
+        }
+    }
+
+    /// Test-helper drain logging to two drains in turn
+    struct TeeDrain<A, B>(A, B);
+
+    impl<A, B> slog::Drain for TeeDrain<A, B>
+    where
+        A: slog::Drain<Ok = (), Err = slog::Never>,
+        B: slog::Drain<Ok = (), Err = slog::Never>,
+    {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(
+            &self,
+            record: &Record,
+            logger_kv: &OwnedKVList,
+        ) -> Result<Self::Ok, Self::Err> {
+            self.0.log(record, logger_kv)?;
+            self.1.log(record, logger_kv)
+        }
     }
 
     /// Test-helper drain
@@ -0,0 +1,187 @@
+//! logfmt drain
+//!
+//! Writes each record as a line of `key=value` pairs, starting with the level
+//! and the message, followed by logger and record key-values:
+//!
+//! ```text
+//! level=info msg="request done" path=/index.html status=200
+//! ```
+//!
+//! Values are quoted when empty or holding spaces, `=` or quotes, and quotes,
+//! backslashes and control characters are escaped, so each record stays on a
+//! single line. Keys are written as is.
+
+use slog::{Drain, Key, Level, OwnedKVList, Record, Serializer, KV};
+use std::fmt::{self, Write as FmtWrite};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+/// Drain writing records in logfmt to a `Write`r
+///
+/// Each record is written with a single `write_all` call. Meant to be wrapped
+/// by `Async`, so the formatting and IO happen on the worker thread.
+pub struct LogfmtDrain<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write> LogfmtDrain<W> {
+    /// Write records to `writer`.
+    pub fn new(writer: W) -> Self {
+        LogfmtDrain {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    fn format(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Result<String, slog::Error> {
+        let mut line = format!("level={} msg=", level_name(record.level()));
+        push_value(&mut line, &record.msg().to_string());
+        let mut ser = LogfmtSerializer(&mut line);
+        logger_values.serialize(record, &mut ser)?;
+        record.kv().serialize(record, &mut ser)?;
+        line.push('\n');
+        Ok(line)
+    }
+}
+
+impl<W: Write> Drain for LogfmtDrain<W> {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()> {
+        let line = self.format(record, logger_values)?;
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(e) => e.into_inner(),
+        };
+        writer.write_all(line.as_bytes())
+    }
+}
+
+/// Lowercase level name, as usual in logfmt.
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Critical => "critical",
+        Level::Error => "error",
+        Level::Warning => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+/// Append `value` to `line`, quoted and escaped if needed.
+fn push_value(line: &mut String, value: &str) {
+    let quote = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c == ' ' || c == '=' || c == '"' || c.is_control());
+    if !quote {
+        line.push_str(value);
+        return;
+    }
+    line.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(line, "\\u{{{:x}}}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+/// Appends ` key=value` pairs to a line.
+struct LogfmtSerializer<'a>(&'a mut String);
+
+impl<'a> Serializer for LogfmtSerializer<'a> {
+    fn emit_arguments(
+        &mut self,
+        key: Key,
+        val: &fmt::Arguments,
+    ) -> slog::Result {
+        write!(self.0, " {}=", key)?;
+        push_value(self.0, &val.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn quoted(value: &str) -> String {
+        let mut line = String::new();
+        push_value(&mut line, value);
+        line
+    }
+
+    #[test]
+    fn values_are_quoted_and_escaped() {
+        assert_eq!(quoted("plain"), "plain");
+        assert_eq!(quoted(""), "\"\"");
+        assert_eq!(quoted("two words"), "\"two words\"");
+        assert_eq!(quoted("a=b"), "\"a=b\"");
+        assert_eq!(quoted("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quoted("line\nbreak"), "\"line\\nbreak\"");
+        assert_eq!(quoted("tab\there"), "\"tab\\there\"");
+        assert_eq!(quoted("back\\slash"), "back\\slash");
+        assert_eq!(quoted("\"back\\slash\""), "\"\\\"back\\\\slash\\\"\"");
+        assert_eq!(quoted("bell\u{7}"), "\"bell\\u{7}\"");
+    }
+
+    #[test]
+    fn records_are_written_as_lines() {
+        let drain = LogfmtDrain::new(Vec::new());
+        drain
+            .log(
+                &record!(
+                    Level::Warning,
+                    "",
+                    &format_args!("disk almost full"),
+                    b!("free" => "", "path" => "/var/log")
+                ),
+                &o!("host" => "db 1").into(),
+            )
+            .unwrap();
+        drain
+            .log(
+                &record!(Level::Info, "", &format_args!("done"), b!()),
+                &o!().into(),
+            )
+            .unwrap();
+
+        let written =
+            String::from_utf8(drain.writer.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", written);
+        let pairs = lines[0]
+            .strip_prefix("level=warn msg=\"disk almost full\"")
+            .unwrap_or_else(|| panic!("{}", written));
+        // slog doesn't guarantee the order of the key-values
+        let expected = [" host=\"db 1\"", " free=\"\"", " path=/var/log"];
+        for pair in &expected {
+            assert!(pairs.contains(pair), "{} in {}", pair, written);
+        }
+        assert_eq!(
+            pairs.len(),
+            expected.iter().map(|pair| pair.len()).sum::<usize>(),
+            "{}",
+            written
+        );
+        assert_eq!(lines[1], "level=info msg=done");
+    }
+}
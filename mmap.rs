@@ -0,0 +1,242 @@
+//! Memory-mapped ring file drain
+//!
+//! Keeps the most recent records in a file mapped into memory, for post-crash
+//! analysis: pages written to the mapping belong to the kernel, so they reach
+//! the file even when the process crashes right after. They are not synced
+//! to disk though, so an OS crash or power loss can still lose them.
+//!
+//! The file holds a fixed number of fixed-size slots, overwritten in a
+//! circle. Each record is formatted as the short level name and the message,
+//! followed by logger and record key-values as ` key=value` pairs, and
+//! truncated to fit its slot. Read the records back with `replay`.
+//!
+//! ```no_run
+//! #[macro_use]
+//! extern crate slog;
+//! extern crate slog_async;
+//!
+//! use slog::Drain;
+//! use slog_async::mmap::{replay, MmapRingDrain};
+//!
+//! fn main() {
+//!     // After a crash, read what the previous run logged last
+//!     if let Ok(records) = replay("app.ring") {
+//!         for record in records {
+//!             println!("{}", record);
+//!         }
+//!     }
+//!
+//!     let drain = MmapRingDrain::create("app.ring", 1024, 256).unwrap();
+//!     let drain = slog_async::Async::new(drain).build();
+//!     let _log = slog::Logger::root(drain.fuse(), o!());
+//! }
+//! ```
+
+use format_entry;
+use memmap2::MmapMut;
+use slog::{Drain, OwnedKVList, Record};
+use std::convert::TryFrom;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+const MAGIC: &[u8; 8] = b"SLOGRNG1";
+// Magic, record size, number of slots and number of records written
+const HEADER_SIZE: usize = 8 + 4 + 4 + 8;
+// Length prefix of each slot
+const LEN_SIZE: usize = 4;
+
+/// Drain writing records into a circular memory-mapped file
+///
+/// Meant to be wrapped by `Async`, so the copying into the mapping happens on
+/// the worker thread.
+pub struct MmapRingDrain {
+    map: Mutex<MmapMut>,
+    slots: usize,
+    record_size: usize,
+}
+
+impl MmapRingDrain {
+    /// Create or truncate the file at `path`, holding the last `slots`
+    /// records of at most `record_size` bytes each.
+    ///
+    /// `record_size` includes a 4 bytes length prefix: records are truncated
+    /// to `record_size - 4` bytes.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        slots: usize,
+        record_size: usize,
+    ) -> io::Result<Self> {
+        if slots == 0 || record_size <= LEN_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ring file needs a slot and room for a record",
+            ));
+        }
+        let header = |n: usize| {
+            u32::try_from(n).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "ring too large")
+            })
+        };
+        let (slots_u32, record_size_u32) =
+            (header(slots)?, header(record_size)?);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len((HEADER_SIZE + slots * record_size) as u64)?;
+        // The file is only modified through this mapping
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        map[..8].copy_from_slice(MAGIC);
+        map[8..12].copy_from_slice(&record_size_u32.to_le_bytes());
+        map[12..16].copy_from_slice(&slots_u32.to_le_bytes());
+        map[16..24].copy_from_slice(&0u64.to_le_bytes());
+
+        Ok(MmapRingDrain {
+            map: Mutex::new(map),
+            slots,
+            record_size,
+        })
+    }
+}
+
+impl Drain for MmapRingDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Result<(), slog::Never> {
+        let entry = format_entry(record, logger_values);
+        let entry = truncate(&entry, self.record_size - LEN_SIZE);
+
+        let mut map = match self.map.lock() {
+            Ok(map) => map,
+            Err(e) => e.into_inner(),
+        };
+        let written = read_u64(&map[16..24]);
+        let start = HEADER_SIZE
+            + (written % self.slots as u64) as usize * self.record_size;
+        let slot = &mut map[start..start + self.record_size];
+        slot[..LEN_SIZE].copy_from_slice(&(entry.len() as u32).to_le_bytes());
+        slot[LEN_SIZE..LEN_SIZE + entry.len()]
+            .copy_from_slice(entry.as_bytes());
+        map[16..24].copy_from_slice(&(written + 1).to_le_bytes());
+        Ok(())
+    }
+}
+
+/// Read the records of a ring file written by `MmapRingDrain`, oldest first.
+pub fn replay<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
+    let data = fs::read(path)?;
+    let invalid =
+        || io::Error::new(io::ErrorKind::InvalidData, "not a ring file");
+    if data.len() < HEADER_SIZE || &data[..8] != MAGIC {
+        return Err(invalid());
+    }
+    let record_size = read_u32(&data[8..12]) as usize;
+    let slots = read_u32(&data[12..16]) as usize;
+    let written = read_u64(&data[16..24]);
+    if record_size <= LEN_SIZE
+        || data.len() != HEADER_SIZE + slots * record_size
+    {
+        return Err(invalid());
+    }
+
+    let count = written.min(slots as u64);
+    let oldest = written - count;
+    (oldest..written)
+        .map(|n| {
+            let start = HEADER_SIZE + (n % slots as u64) as usize * record_size;
+            let slot = &data[start..start + record_size];
+            let len = read_u32(&slot[..LEN_SIZE]) as usize;
+            if len > record_size - LEN_SIZE {
+                return Err(invalid());
+            }
+            Ok(String::from_utf8_lossy(&slot[LEN_SIZE..LEN_SIZE + len])
+                .into_owned())
+        })
+        .collect()
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(bytes);
+    u32::from_le_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
+
+/// Longest prefix of `s` of at most `max` bytes, cut at a char boundary.
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::Level;
+    use std::process;
+
+    #[test]
+    fn ring_file_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("slog-async-mmap-{}.ring", process::id()));
+        let drain = MmapRingDrain::create(&path, 3, 20).unwrap();
+        for i in 0..5 {
+            drain
+                .log(
+                    &record!(
+                        Level::Info,
+                        "",
+                        &format_args!("msg {}", i),
+                        b!("k" => "v")
+                    ),
+                    &o!().into(),
+                )
+                .unwrap();
+        }
+        drain
+            .log(
+                &record!(
+                    Level::Warning,
+                    "",
+                    &format_args!("too long to fit"),
+                    b!("k" => "v")
+                ),
+                &o!().into(),
+            )
+            .unwrap();
+        drop(drain);
+
+        let records = replay(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(
+            records.unwrap(),
+            vec!["INFO msg 3 k=v", "INFO msg 4 k=v", "WARN too long to"]
+        );
+    }
+
+    #[test]
+    fn truncate_at_char_boundary() {
+        assert_eq!(truncate("aé", 2), "a");
+        assert_eq!(truncate("aé", 3), "aé");
+    }
+}
@@ -0,0 +1,425 @@
+//! OpenTelemetry log records
+//!
+//! Converts records into `LogRecord`s of the `opentelemetry` logs API and
+//! emits them with a `Logger`, eg. an `SdkLogger` of the `opentelemetry_sdk`
+//! crate, whose processors export them. Built with `Async::to_otel` the
+//! conversion runs on the worker thread, batched as with `Async::new_batch`.
+//!
+//! # Mapping
+//!
+//! Every record is mapped as follows:
+//!
+//! * the level to `severity_number` and `severity_text`, following the
+//!   OpenTelemetry log data model, see `severity`:
+//!
+//!   | Level      | `severity_number` | `severity_text` |
+//!   |------------|-------------------|-----------------|
+//!   | `Trace`    | 1 (`TRACE`)       | `TRACE`         |
+//!   | `Debug`    | 5 (`DEBUG`)       | `DEBUG`         |
+//!   | `Info`     | 9 (`INFO`)        | `INFO`          |
+//!   | `Warning`  | 13 (`WARN`)       | `WARN`          |
+//!   | `Error`    | 17 (`ERROR`)      | `ERROR`         |
+//!   | `Critical` | 21 (`FATAL`)      | `FATAL`         |
+//!
+//! * the message to `body`, as a string
+//! * the tag to `target`, left unset if there's no tag
+//! * logger and record key-values to `attributes`, in the order they're
+//!   serialized, record key-values taking precedence over logger ones with
+//!   the same key. Booleans, integers fitting an `i64` and floats keep their
+//!   type, everything else is formatted as a string.
+//! * `observed_timestamp` to when the worker thread converted the batch, the
+//!   same for all its records
+//!
+//! The instrumentation scope is that of the `Logger`.
+//!
+//! # Flushing
+//!
+//! A batch is emitted once `max_records` records are buffered or the oldest
+//! one waited for `max_delay`, whichever comes first, as well as on
+//! `Async::flush` and when the drain finishes. Exporting the emitted records
+//! is up to the processors of the `Logger`'s provider, as is shutting them
+//! down.
+
+use opentelemetry::logs::{AnyValue, LogRecord, Logger, Severity};
+use slog::{Key, Level, Serializer, KV};
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use {AsyncRecord, BatchDrain};
+
+/// Severity of `level`, see the module documentation.
+pub fn severity(level: Level) -> Severity {
+    match level {
+        Level::Trace => Severity::Trace,
+        Level::Debug => Severity::Debug,
+        Level::Info => Severity::Info,
+        Level::Warning => Severity::Warn,
+        Level::Error => Severity::Error,
+        Level::Critical => Severity::Fatal,
+    }
+}
+
+/// `BatchDrain` converting records for an OpenTelemetry `Logger`
+///
+/// See `Async::to_otel`.
+pub struct OtelBatchDrain<L> {
+    logger: L,
+}
+
+impl<L: Logger + Send + 'static> OtelBatchDrain<L> {
+    /// Emit records with `logger`.
+    pub fn new(logger: L) -> Self {
+        OtelBatchDrain { logger }
+    }
+
+    fn emit(&self, r: &AsyncRecord, observed_timestamp: SystemTime) {
+        let mut record = self.logger.create_log_record();
+        let severity = severity(r.level());
+        record.set_observed_timestamp(observed_timestamp);
+        record.set_severity_number(severity);
+        record.set_severity_text(severity.name());
+        record.set_body(AnyValue::from(r.msg().to_string()));
+        if !r.tag().is_empty() {
+            record.set_target(r.tag().to_string());
+        }
+        let mut attributes = Vec::new();
+        r.as_record_values(|r, logger_values| {
+            let mut ser = AttributeSerializer {
+                attributes: &mut attributes,
+            };
+            // `AttributeSerializer` doesn't fail. Record key-values come
+            // last, overwriting logger ones.
+            let _ = logger_values.serialize(r, &mut ser);
+            let _ = r.kv().serialize(r, &mut ser);
+        });
+        record.add_attributes(attributes);
+        self.logger.emit(record);
+    }
+}
+
+impl<L: Logger + Send + 'static> BatchDrain for OtelBatchDrain<L> {
+    fn log_batch(&self, records: &[AsyncRecord]) {
+        let now = SystemTime::now();
+        for r in records {
+            self.emit(r, now);
+        }
+    }
+}
+
+/// Default number of records converted at once with `Async::to_otel`
+pub const DEFAULT_MAX_RECORDS: usize = 512;
+
+/// Default delay before converting a batch with `Async::to_otel`
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Collects key-values as attributes.
+struct AttributeSerializer<'a> {
+    attributes: &'a mut Vec<(String, AnyValue)>,
+}
+
+impl<'a> AttributeSerializer<'a> {
+    fn push(&mut self, key: Key, value: AnyValue) -> slog::Result {
+        let key: &str = key.as_ref();
+        match self.attributes.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.attributes.push((key.to_string(), value)),
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Serializer for AttributeSerializer<'a> {
+    fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
+        self.push(key, AnyValue::Boolean(val))
+    }
+    fn emit_u8(&mut self, key: Key, val: u8) -> slog::Result {
+        self.push(key, AnyValue::Int(val.into()))
+    }
+    fn emit_i8(&mut self, key: Key, val: i8) -> slog::Result {
+        self.push(key, AnyValue::Int(val.into()))
+    }
+    fn emit_u16(&mut self, key: Key, val: u16) -> slog::Result {
+        self.push(key, AnyValue::Int(val.into()))
+    }
+    fn emit_i16(&mut self, key: Key, val: i16) -> slog::Result {
+        self.push(key, AnyValue::Int(val.into()))
+    }
+    fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
+        self.push(key, AnyValue::Int(val.into()))
+    }
+    fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
+        self.push(key, AnyValue::Int(val.into()))
+    }
+    fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
+        self.push(key, AnyValue::Int(val))
+    }
+    fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
+        match i64::try_from(val) {
+            Ok(val) => self.push(key, AnyValue::Int(val)),
+            Err(_) => self.push(key, AnyValue::from(val.to_string())),
+        }
+    }
+    fn emit_isize(&mut self, key: Key, val: isize) -> slog::Result {
+        self.emit_i64(key, val as i64)
+    }
+    fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
+        self.emit_u64(key, val as u64)
+    }
+    fn emit_f32(&mut self, key: Key, val: f32) -> slog::Result {
+        self.push(key, AnyValue::Double(val.into()))
+    }
+    fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
+        self.push(key, AnyValue::Double(val))
+    }
+    fn emit_arguments(
+        &mut self,
+        key: Key,
+        val: &fmt::Arguments,
+    ) -> slog::Result {
+        self.push(key, AnyValue::from(val.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opentelemetry::Key as OtelKey;
+    use slog::Drain;
+    use std::borrow::Cow;
+    use std::sync::mpsc;
+    use Async;
+
+    /// Log record keeping what was set
+    #[derive(Debug, Default)]
+    struct MockRecord {
+        observed_timestamp: Option<SystemTime>,
+        severity_number: Option<Severity>,
+        severity_text: Option<&'static str>,
+        body: Option<AnyValue>,
+        target: Option<Cow<'static, str>>,
+        attributes: Vec<(OtelKey, AnyValue)>,
+    }
+
+    impl MockRecord {
+        fn attribute(&self, key: &str) -> Option<&AnyValue> {
+            self.attributes
+                .iter()
+                .find(|(k, _)| k.as_str() == key)
+                .map(|(_, value)| value)
+        }
+    }
+
+    impl LogRecord for MockRecord {
+        fn set_event_name(&mut self, _name: &'static str) {}
+        fn set_target<T>(&mut self, target: T)
+        where
+            T: Into<Cow<'static, str>>,
+        {
+            self.target = Some(target.into());
+        }
+        fn set_timestamp(&mut self, _timestamp: SystemTime) {}
+        fn set_observed_timestamp(&mut self, timestamp: SystemTime) {
+            self.observed_timestamp = Some(timestamp);
+        }
+        fn set_severity_text(&mut self, text: &'static str) {
+            self.severity_text = Some(text);
+        }
+        fn set_severity_number(&mut self, number: Severity) {
+            self.severity_number = Some(number);
+        }
+        fn set_body(&mut self, body: AnyValue) {
+            self.body = Some(body);
+        }
+        fn add_attributes<I, K, V>(&mut self, attributes: I)
+        where
+            I: IntoIterator<Item = (K, V)>,
+            K: Into<OtelKey>,
+            V: Into<AnyValue>,
+        {
+            for (key, value) in attributes {
+                self.add_attribute(key, value);
+            }
+        }
+        fn add_attribute<K, V>(&mut self, key: K, value: V)
+        where
+            K: Into<OtelKey>,
+            V: Into<AnyValue>,
+        {
+            self.attributes.push((key.into(), value.into()));
+        }
+    }
+
+    /// Logger sending every emitted record
+    struct MockLogger(mpsc::Sender<MockRecord>);
+
+    impl Logger for MockLogger {
+        type LogRecord = MockRecord;
+
+        fn create_log_record(&self) -> MockRecord {
+            MockRecord::default()
+        }
+
+        fn emit(&self, record: MockRecord) {
+            self.0.send(record).unwrap();
+        }
+    }
+
+    #[test]
+    fn records_are_mapped_to_log_records() {
+        let (tx, rx) = mpsc::channel();
+        let (drain, control) = Async::new_batch(
+            OtelBatchDrain::new(MockLogger(tx)),
+            2,
+            Duration::from_secs(3600),
+        )
+        .build_with_channel();
+        control.log_level(Level::Trace).unwrap();
+        let log = slog::Logger::root(
+            drain.fuse(),
+            o!("user" => "root", "port" => 42u32),
+        );
+        warn!(log, #"http", "slow request";
+              "status" => 504, "ok" => false, "ratio" => 0.5,
+              "big" => u64::MAX);
+        crit!(log, "down"; "user" => "alice");
+        debug!(log, "third");
+
+        let (warn, crit) = (rx.recv().unwrap(), rx.recv().unwrap());
+        assert_eq!(warn.severity_number, Some(Severity::Warn));
+        assert_eq!(warn.severity_text, Some("WARN"));
+        assert_eq!(warn.body, Some(AnyValue::from("slow request")));
+        assert_eq!(warn.target.as_deref(), Some("http"));
+        assert_eq!(warn.attribute("status"), Some(&AnyValue::Int(504)));
+        assert_eq!(warn.attribute("ok"), Some(&AnyValue::Boolean(false)));
+        assert_eq!(warn.attribute("ratio"), Some(&AnyValue::Double(0.5)));
+        assert_eq!(
+            warn.attribute("big"),
+            Some(&AnyValue::from(u64::MAX.to_string()))
+        );
+        assert_eq!(warn.attribute("port"), Some(&AnyValue::Int(42)));
+        assert!(warn.observed_timestamp.is_some());
+        assert_eq!(warn.observed_timestamp, crit.observed_timestamp);
+
+        assert_eq!(crit.severity_number, Some(Severity::Fatal));
+        assert_eq!(crit.severity_text, Some("FATAL"));
+        assert_eq!(crit.target, None);
+        // The record value replaces the logger one in place
+        assert_eq!(
+            crit.attributes,
+            vec![
+                (OtelKey::from("port"), AnyValue::Int(42)),
+                (OtelKey::from("user"), AnyValue::from("alice")),
+            ]
+        );
+
+        // The rest gets emitted when finishing
+        drop(log);
+        let third = rx.recv().unwrap();
+        assert_eq!(third.severity_number, Some(Severity::Debug));
+        assert_eq!(third.body, Some(AnyValue::from("third")));
+    }
+    #[test]
+    fn levels_map_to_severities() {
+        let severities: Vec<_> = [
+            Level::Trace,
+            Level::Debug,
+            Level::Info,
+            Level::Warning,
+            Level::Error,
+            Level::Critical,
+        ]
+        .iter()
+        .map(|&level| {
+            let severity = severity(level);
+            (severity, severity as i32, severity.name())
+        })
+        .collect();
+        assert_eq!(
+            severities,
+            vec![
+                (Severity::Trace, 1, "TRACE"),
+                (Severity::Debug, 5, "DEBUG"),
+                (Severity::Info, 9, "INFO"),
+                (Severity::Warn, 13, "WARN"),
+                (Severity::Error, 17, "ERROR"),
+                (Severity::Fatal, 21, "FATAL"),
+            ]
+        );
+    }
+
+    #[test]
+    fn values_map_to_any_values() {
+        /// Attributes of the key-values of `record`, sorted by key
+        fn attributes(record: &slog::Record) -> Vec<(String, AnyValue)> {
+            let mut attributes = Vec::new();
+            record
+                .kv()
+                .serialize(
+                    record,
+                    &mut AttributeSerializer {
+                        attributes: &mut attributes,
+                    },
+                )
+                .unwrap();
+            attributes.sort_by(|(a, _), (b, _)| a.cmp(b));
+            attributes
+        }
+
+        let attributes = attributes(&record!(
+            Level::Info,
+            "",
+            &format_args!("msg"),
+            b!(
+                "bool" => true,
+                "u8" => 8u8,
+                "i8" => -8i8,
+                "u16" => 16u16,
+                "i16" => -16i16,
+                "u32" => 32u32,
+                "i32" => -32i32,
+                "u64" => 64u64,
+                "big_u64" => u64::MAX,
+                "i64" => i64::MIN,
+                "usize" => 7usize,
+                "isize" => -7isize,
+                "f32" => 0.5f32,
+                "f64" => -0.25f64,
+                "str" => "text",
+                "char" => 'c',
+                "unit" => (),
+                "none" => None::<u8>,
+                "fmt" => format_args!("{}-{}", 1, 2),
+            )
+        ));
+        let string = |s: &str| AnyValue::from(s.to_string());
+
+        let mut expected = vec![
+            ("bool", AnyValue::Boolean(true)),
+            ("u8", AnyValue::Int(8)),
+            ("i8", AnyValue::Int(-8)),
+            ("u16", AnyValue::Int(16)),
+            ("i16", AnyValue::Int(-16)),
+            ("u32", AnyValue::Int(32)),
+            ("i32", AnyValue::Int(-32)),
+            ("u64", AnyValue::Int(64)),
+            ("big_u64", string(&u64::MAX.to_string())),
+            ("i64", AnyValue::Int(i64::MIN)),
+            ("usize", AnyValue::Int(7)),
+            ("isize", AnyValue::Int(-7)),
+            ("f32", AnyValue::Double(0.5)),
+            ("f64", AnyValue::Double(-0.25)),
+            ("str", string("text")),
+            ("char", string("c")),
+            ("unit", string("()")),
+            // As formatted by `slog`
+            ("none", string("")),
+            ("fmt", string("1-2")),
+        ];
+        expected.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+        assert_eq!(attributes, expected);
+    }
+}
@@ -0,0 +1,219 @@
+//! Process-global registry of live `AsyncCore`s
+//!
+//! Every `AsyncCore`, and so every `Async`, registers itself when built and
+//! is removed once dropped. This lets test teardown flush all of them with
+//! `flush_all`, without keeping track of each drain or `AsyncGuard`, and
+//! `Async::install_exit_guard` to do so when the process exits.
+
+use super::{send_flush, wait_flushed, AsyncMsg};
+use crossbeam_channel::Sender;
+use std::os::raw::c_int;
+use std::panic;
+use std::sync::{Arc, Mutex, MutexGuard, Once, OnceLock, Weak};
+use std::thread;
+
+/// Channels of a registered `AsyncCore`, owned by it.
+pub(crate) struct Registration {
+    sender: Sender<AsyncMsg>,
+    ctrl_sender: Sender<AsyncMsg>,
+    worker_thread: Arc<OnceLock<thread::ThreadId>>,
+}
+
+static REGISTRY: Mutex<Vec<Weak<Registration>>> = Mutex::new(Vec::new());
+
+fn registry() -> MutexGuard<'static, Vec<Weak<Registration>>> {
+    // The registry is only ever pushed to or pruned, it can't be left
+    // inconsistent by a panic
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Add an `AsyncCore` to the registry, until the returned `Registration` is
+/// dropped.
+pub(crate) fn register(
+    sender: &Sender<AsyncMsg>,
+    ctrl_sender: &Sender<AsyncMsg>,
+    worker_thread: &Arc<OnceLock<thread::ThreadId>>,
+) -> Arc<Registration> {
+    let registration = Arc::new(Registration {
+        sender: sender.clone(),
+        ctrl_sender: ctrl_sender.clone(),
+        worker_thread: worker_thread.clone(),
+    });
+    let mut registry = registry();
+    registry.retain(|r| r.strong_count() > 0);
+    registry.push(Arc::downgrade(&registration));
+    registration
+}
+
+/// Wait until every live `AsyncCore` wrote all the records sent so far.
+///
+/// The worker threads are flushed concurrently, see `AsyncCore::flush`.
+/// Workers that already terminated are skipped, as is the worker of the
+/// calling thread.
+pub fn flush_all() {
+    let registrations: Vec<_> =
+        registry().iter().filter_map(Weak::upgrade).collect();
+    let current = thread::current().id();
+    let acks: Vec<_> = registrations
+        .iter()
+        .filter(|r| r.worker_thread.get() != Some(&current))
+        .filter_map(|r| send_flush(&r.ctrl_sender, &r.sender).ok())
+        .collect();
+    for ack_rx in &acks {
+        let _ = wait_flushed(ack_rx);
+    }
+}
+
+extern "C" {
+    // Part of the C standard library, linked by `std` on every platform
+    fn atexit(cb: extern "C" fn()) -> c_int;
+}
+
+extern "C" fn flush_all_at_exit() {
+    // Unwinding out of an `extern "C"` function aborts
+    let _ = panic::catch_unwind(flush_all);
+}
+
+/// See `Async::install_exit_guard`.
+pub(crate) fn install_exit_guard() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        // Only fails when out of memory for the handler table, nothing can
+        // be done about it then
+        let _ = unsafe { atexit(flush_all_at_exit) };
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::{Drain, Level, OwnedKVList, Record};
+    use std::env;
+    use std::io::{self, Write};
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use Async;
+
+    /// Counts records, taking a while for each
+    struct SlowDrain(Arc<AtomicUsize>);
+
+    impl Drain for SlowDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, _: &Record, _: &OwnedKVList) -> Result<(), slog::Never> {
+            thread::sleep(Duration::from_millis(1));
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flush_all_flushes_every_drain() {
+        let counters: Vec<_> =
+            (0..3).map(|_| Arc::new(AtomicUsize::new(0))).collect();
+        let drains: Vec<_> = counters
+            .iter()
+            .map(|counter| {
+                let (drain, control) = Async::new(SlowDrain(counter.clone()))
+                    .chan_size(32)
+                    .build_with_channel();
+                control.log_level(Level::Trace).unwrap();
+                Arc::new(drain)
+            })
+            .collect();
+
+        let producers: Vec<_> = drains
+            .iter()
+            .map(|drain| {
+                let drain = drain.clone();
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        drain
+                            .log(
+                                &record!(
+                                    Level::Info,
+                                    "",
+                                    &format_args!("msg"),
+                                    b!()
+                                ),
+                                &o!().into(),
+                            )
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        flush_all();
+        for counter in &counters {
+            assert_eq!(counter.load(Ordering::SeqCst), 20);
+        }
+    }
+
+    #[test]
+    fn dropped_drains_are_unregistered() {
+        let drain = Async::default(slog::Discard);
+        let registration = Arc::downgrade(&drain.core._registration);
+        assert!(registry().iter().any(|r| r.ptr_eq(&registration)));
+        drop(drain);
+        assert!(registration.upgrade().is_none());
+    }
+
+    /// Writes messages to stdout, slowly
+    struct StdoutDrain;
+
+    impl Drain for StdoutDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(
+            &self,
+            record: &Record,
+            _: &OwnedKVList,
+        ) -> Result<(), slog::Never> {
+            thread::sleep(Duration::from_millis(10));
+            let _ = writeln!(io::stdout(), "logged: {}", record.msg());
+            Ok(())
+        }
+    }
+
+    const EXIT_CHILD_ENV: &str = "SLOG_ASYNC_EXIT_GUARD_CHILD";
+
+    #[test]
+    fn exit_guard_flushes_on_exit() {
+        if env::var_os(EXIT_CHILD_ENV).is_some() {
+            let (drain, control) = Async::new(StdoutDrain).build_with_channel();
+            control.log_level(Level::Trace).unwrap();
+            Async::install_exit_guard();
+            for i in 0..5 {
+                drain
+                    .log(
+                        &record!(Level::Info, "", &format_args!("{}", i), b!()),
+                        &o!().into(),
+                    )
+                    .unwrap();
+            }
+            process::exit(0);
+        }
+
+        let output = process::Command::new(env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "registry::test::exit_guard_flushes_on_exit",
+                "--nocapture",
+            ])
+            .env(EXIT_CHILD_ENV, "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        for i in 0..5 {
+            assert!(stdout.contains(&format!("logged: {}\n", i)), "{}", stdout);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Syslog drain
+//!
+//! Formats records as RFC 5424 messages and sends them to a local syslog
+//! daemon socket or a remote UDP collector. Wrapped by `Async` the socket IO
+//! happens on the worker thread.
+
+use slog::{Drain, Level, OwnedKVList, Record, KV};
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::Path;
+use std::process;
+use PairSerializer;
+
+/// Syslog facility
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[allow(missing_docs)]
+pub enum Facility {
+    Kern = 0,
+    User = 1,
+    Mail = 2,
+    Daemon = 3,
+    Auth = 4,
+    Syslog = 5,
+    Lpr = 6,
+    News = 7,
+    Uucp = 8,
+    Cron = 9,
+    AuthPriv = 10,
+    Ftp = 11,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+/// Map a `Level` to a syslog severity.
+///
+/// syslog has no level below debug, so `Trace` is mapped to debug as well.
+pub fn severity(level: Level) -> u8 {
+    match level {
+        Level::Critical => 2,
+        Level::Error => 3,
+        Level::Warning => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+enum Socket {
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+/// Drain sending RFC 5424 formatted records to syslog
+///
+/// The record tag is used as the APP-NAME and key-values are appended to the
+/// message as `key=value` pairs. Timestamp and hostname are left for the
+/// syslog daemon to fill in.
+pub struct SyslogDrain {
+    socket: Socket,
+    facility: Facility,
+}
+
+impl SyslogDrain {
+    /// Send to a local syslog daemon listening on the datagram socket `path`,
+    /// typically `/dev/log`.
+    #[cfg(unix)]
+    pub fn unix<P: AsRef<Path>>(
+        path: P,
+        facility: Facility,
+    ) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(SyslogDrain {
+            socket: Socket::Unix(socket),
+            facility,
+        })
+    }
+
+    /// Send to a syslog collector over UDP.
+    pub fn udp<A: ToSocketAddrs>(
+        addr: A,
+        facility: Facility,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        Ok(SyslogDrain {
+            socket: Socket::Udp(socket),
+            facility,
+        })
+    }
+
+    fn format(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Result<String, slog::Error> {
+        let pri = self.facility as u8 * 8 + severity(record.level());
+        let app_name = if record.tag().is_empty() {
+            "-"
+        } else {
+            record.tag()
+        };
+        let mut msg = format!(
+            "<{}>1 - - {} {} - - {}",
+            pri,
+            app_name,
+            process::id(),
+            record.msg()
+        );
+        let mut ser = PairSerializer(&mut msg);
+        logger_values.serialize(record, &mut ser)?;
+        record.kv().serialize(record, &mut ser)?;
+        Ok(msg)
+    }
+}
+
+impl Drain for SyslogDrain {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()> {
+        let msg = self.format(record, logger_values)?;
+        match self.socket {
+            #[cfg(unix)]
+            Socket::Unix(ref socket) => socket.send(msg.as_bytes())?,
+            Socket::Udp(ref socket) => socket.send(msg.as_bytes())?,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn severity_mapping() {
+        assert_eq!(severity(Level::Critical), 2);
+        assert_eq!(severity(Level::Error), 3);
+        assert_eq!(severity(Level::Warning), 4);
+        assert_eq!(severity(Level::Info), 6);
+        assert_eq!(severity(Level::Debug), 7);
+        assert_eq!(severity(Level::Trace), 7);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_integration() {
+        let path = std::env::temp_dir()
+            .join(format!("slog-async-syslog-{}.sock", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let drain = SyslogDrain::unix(&path, Facility::Local0).unwrap();
+        drain
+            .log(
+                &record!(
+                    Level::Info,
+                    "myapp",
+                    &format_args!("hello"),
+                    b!("k" => "v")
+                ),
+                &o!("id" => 1).into(),
+            )
+            .unwrap();
+
+        let mut buf = [0; 1024];
+        let n = server.recv(&mut buf).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            std::str::from_utf8(&buf[..n]).unwrap(),
+            format!("<134>1 - - myapp {} - - hello id=1 k=v", process::id())
+        );
+    }
+}
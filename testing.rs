@@ -0,0 +1,79 @@
+//! Helpers for testing logging behavior
+//!
+//! `VecDrain` collects formatted records in memory, so tests of code using
+//! `slog` can assert on what was logged.
+//!
+//! ```
+//! #[macro_use]
+//! extern crate slog;
+//! extern crate slog_async;
+//!
+//! use slog::Drain;
+//! use slog_async::testing::VecDrain;
+//!
+//! fn main() {
+//!     let vec_drain = VecDrain::new();
+//!     let (drain, control) =
+//!         slog_async::Async::new(vec_drain.clone()).build_with_channel();
+//!     control.log_level(slog::Level::Info).unwrap();
+//!     let drain = std::sync::Arc::new(drain);
+//!     let log = slog::Logger::root(drain.clone().fuse(), o!("id" => 1));
+//!
+//!     info!(log, "hello"; "k" => "v");
+//!     debug!(log, "filtered");
+//!
+//!     // Wait for the worker thread to write the records
+//!     drain.flush().unwrap();
+//!     assert_eq!(vec_drain.entries(), vec!["INFO hello id=1 k=v"]);
+//! }
+//! ```
+
+use format_entry;
+use slog::{Drain, OwnedKVList, Record};
+use std::sync::{Arc, Mutex};
+
+/// Drain collecting formatted records into a shared `Vec<String>`
+///
+/// Clones share the same storage: keep one to inspect the entries after
+/// handing the other to a logger. Each record is formatted as the short level
+/// name and the message, followed by logger and record key-values as
+/// ` key=value` pairs.
+///
+/// When wrapped by `Async` records are written by the worker thread, so call
+/// `Async::flush` before looking at the entries.
+#[derive(Clone, Default)]
+pub struct VecDrain {
+    entries: Arc<Mutex<Vec<String>>>,
+}
+
+impl VecDrain {
+    /// Create an empty `VecDrain`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy of all the entries logged so far
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Remove all the entries logged so far
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+impl Drain for VecDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Result<(), slog::Never> {
+        let entry = format_entry(record, logger_values);
+        self.entries.lock().unwrap().push(entry);
+        Ok(())
+    }
+}